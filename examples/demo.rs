@@ -99,7 +99,7 @@ fn hids_test<P: Into<Option<f64>>>(hids: &mut Hids, pause: P) -> Result<()> {
     hids.key_press();
     sleep(millis);
     println!("blow");
-    hids.blow(1.0);
+    hids.blow(1.0)?;
     sleep(millis);
     println!("whistle");
     hids.whistle();
@@ -241,7 +241,7 @@ fn sensors_test(sensors: &mut Sensors) -> Result<()> {
             .model()
     );
     for _ in [0..5].iter() {
-        let distance = sensors.sonar_distance().unwrap_or(-1.0);
+        let distance = sensors.sonar_distance().map_or(-1.0, |r| r.filtered);
         let ir = sensors.ir_proximity();
         let ldr = sensors.ldr_tracking();
         let line = sensors.line_tracking();
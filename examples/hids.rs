@@ -61,7 +61,7 @@ fn test<P: Into<Option<f64>>>(hids: &mut Hids, pause: P) -> CarResult {
     hids.key_press();
     sleep(millis);
     println!("blow");
-    hids.blow(1.0);
+    hids.blow(1.0)?;
     sleep(millis);
     println!("3 whistles");
     hids.whistle();
@@ -78,7 +78,7 @@ fn main() -> Result<()> {
 
 fn test(sensors: &mut Sensors) {
     // Raw sensor data.
-    let distance = sensors.sonar_distance().unwrap_or(-1.0);
+    let distance = sensors.sonar_distance().map_or(-1.0, |r| r.filtered);
     let ir = sensors.ir_proximity();
     let ldr = sensors.ldr_tracking();
     let tracking = sensors.line_tracking();
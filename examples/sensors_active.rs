@@ -58,7 +58,9 @@ fn main() -> Result<()> {
     );
     sleep(Duration::from_secs(2));
     let mut sensors = Sensors::new().context("Failed to get instance")?;
-    sensors.sonar_active(true);
+    sensors
+        .sonar_active(true)
+        .context("Failed to start active sonar")?;
     // Give it a little time to queue up sonar data.
     sleep(Duration::from_secs_f64(0.05));
     // Stuff needed to nicely handle Ctrl-C from user.
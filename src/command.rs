@@ -35,44 +35,166 @@
 // SOFTWARE.
 //! Contains higher level command and control components.
 
-use crate::{Hids, Motors, Result, Rr4cError, Rr4cResult, Servos};
+use crate::{
+    EmergencyStop, ExitReason, IrFollower, LightSeeker, LightShow, LineFollower, Mode,
+    ObstacleAvoider, Result, Robot, Rr4cError, Rr4cResult, Status, Supervisor,
+};
+use std::cmp::Ordering::{Equal, Greater, Less};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// A robot mode & command decoder.
 ///
 /// Expected to be used as part of a Tcp (Web), Bluetooth, or other server and
 /// client type control schema.
 /// Could also be used in a CLI or file based scripting system.
+///
+/// Autonomous modes are run in the background by a [Supervisor](Supervisor)
+/// so frames keep being decoded while they run.
+/// Selecting a new mode stops the running one and brakes before switching.
+/// Custom modes can be added with [`register_mode()`].
+///
+/// Holding the `KEY` button down for 2 seconds, a RR4W `STOP` piece, a Yahboom
+/// `$4WD,STOP#` frame, or [`emergency_stop()`] all engage the emergency stop
+/// latch.
+/// It stays engaged until [`reset_emergency_stop()`] is called or a `STOP0`
+/// piece or frame is received.
+///
+/// [`register_mode()`]: Decoder::register_mode()
+/// [`emergency_stop()`]: Decoder::emergency_stop()
+/// [`reset_emergency_stop()`]: Decoder::reset_emergency_stop()
 #[derive(Debug)]
 pub struct Decoder {
-    /// Holds instance of `Hids` structure.
-    hids: Hids,
+    /// Emergency stop latch shared with the robot.
+    emergency_stop: EmergencyStop,
     /// Used to track current LED color.
     led_color: u8,
-    /// Holds the current command mode.
-    mode: CarModes,
-    /// Holds an instance of `Motors` structure.
-    motors: Motors,
+    /// Registered modes starting with the built-in ones.
+    modes: Vec<ModeEntry>,
     /// Holds current default motor speed.
     motor_speed: i8,
-    /// Holds a instance of `Servos` structure.
-    servos: Servos,
+    /// Robot subsystems shared with any running mode.
+    robot: Arc<Mutex<Robot>>,
+    /// Runs autonomous modes in the background.
+    supervisor: Supervisor,
 }
 
 impl Decoder {
     /// Constructor
     pub fn new() -> Rr4cResult<Self> {
-        let mut servos = Servos::new()?;
-        servos.servos_init()?;
-        Ok(Self {
-            hids: Hids::new()?,
+        let robot = Robot::new()?;
+        let mut decoder = Self {
+            emergency_stop: robot.motors.emergency_stop().clone(),
             led_color: 0,
-            mode: CarModes::Remote,
-            motors: Motors::new()?,
+            modes: Vec::new(),
             motor_speed: 25,
-            servos,
-        })
+            robot: Arc::new(Mutex::new(robot)),
+            supervisor: Supervisor::new(),
+        };
+        // Weak so the watcher owned by the robot doesn't keep it alive.
+        let robot = Arc::downgrade(&decoder.robot);
+        decoder.robot().hids.on_key_hold(None, move || {
+            if let Some(robot) = robot.upgrade() {
+                let _ = robot
+                    .lock()
+                    .expect("Someone broke the lock")
+                    .emergency_stop();
+            }
+        });
+        decoder.register_mode("Tracking", "21", 2, |speed| {
+            Box::new(LineFollower::new_with_kitchen_sink(speed, None, None, None))
+        })?;
+        decoder.register_mode("UltrasonicAvoid", "31", 3, |speed| {
+            Box::new(ObstacleAvoider::new_with_kitchen_sink(speed, None, None))
+        })?;
+        decoder.register_mode("LedColors", "41", 4, |_| Box::new(LightShow::new()))?;
+        decoder.register_mode("LightSeeking", "51", 5, |speed| {
+            Box::new(LightSeeker::new_with_kitchen_sink(speed, None, None))
+        })?;
+        decoder.register_mode("InfraredFollow", "61", 6, |speed| {
+            Box::new(IrFollower::new_with_kitchen_sink(speed, None, None))
+        })?;
+        Ok(decoder)
+    }
+    /// Engages the emergency stop latch.
+    ///
+    /// Any running mode is stopped, the motors are braked, and a fault is
+    /// shown.
+    /// Motion commands and selecting modes fail with
+    /// [`Rr4cError::EmergencyStop`] until [`reset_emergency_stop()`] is called.
+    ///
+    /// [`reset_emergency_stop()`]: Decoder::reset_emergency_stop()
+    pub fn emergency_stop(&mut self) -> Result {
+        // Engage first so a mode stepping right now can't move again.
+        self.emergency_stop.engage();
+        self.supervisor.stop();
+        let mut robot = self.robot();
+        robot.hids.clear_status(Status::Autonomous);
+        robot.emergency_stop()
+    }
+    /// Used to check if the emergency stop latch is engaged.
+    pub fn is_emergency_stopped(&self) -> bool {
+        self.emergency_stop.is_engaged()
+    }
+    /// Registers a custom [Mode](Mode).
+    ///
+    /// Registered modes are run in the background the same way as the
+    /// built-in ones with the same alerts when selected.
+    ///
+    /// ## Arguments
+    /// * `name` - Name used to select the mode with [`select_mode()`] and when
+    ///   reporting.
+    /// * `code` - Optional two digit code used to select the mode with a
+    ///   Yahboom `$4WD,MODExx#` frame.
+    ///   Codes ending in `0` and `11` are reserved for going back to remote
+    ///   control.
+    /// * `alerts` - Number of times the LEDs flash and the buzzer beeps when
+    ///   the mode is selected.
+    ///   Defaults to 1.
+    ///   Internally limited between 1 and 8.
+    /// * `factory` - Makes a new instance of the mode each time it is
+    ///   selected.
+    ///   Called with the current default motor speed.
+    ///
+    /// [`select_mode()`]: Decoder::select_mode()
+    pub fn register_mode<'a, C, A, F>(
+        &mut self,
+        name: &str,
+        code: C,
+        alerts: A,
+        factory: F,
+    ) -> Result
+    where
+        C: Into<Option<&'a str>>,
+        A: Into<Option<u8>>,
+        F: FnMut(i8) -> Box<dyn Mode> + Send + 'static,
+    {
+        let code = code.into();
+        if let Some(code) = code {
+            let reserved = code.len() != 2 || code.ends_with('0') || code == "11";
+            if reserved || self.modes.iter().any(|m| m.code.as_deref() == Some(code)) {
+                return Err(Rr4cError::ModeInUse(code.to_string()));
+            }
+        }
+        if self.modes.iter().any(|m| m.name == name) {
+            return Err(Rr4cError::ModeInUse(name.to_string()));
+        }
+        self.modes.push(ModeEntry {
+            alerts: alerts.into().unwrap_or(1).clamp(1, 8),
+            code: code.map(str::to_string),
+            factory: Box::new(factory),
+            name: name.to_string(),
+        });
+        Ok(())
+    }
+    /// Resets the emergency stop latch so the robot can move again.
+    ///
+    /// The motors stay braked until the next motion command.
+    pub fn reset_emergency_stop(&mut self) {
+        self.emergency_stop.reset();
+        self.robot().hids.clear_status(Status::Fault);
     }
     /// Top level command decoder.
     ///
@@ -86,58 +208,97 @@ impl Decoder {
             .and_then(|v| v.strip_suffix("#"))
         {
             for piece in line.split_terminator(',') {
-                if piece.len() <= 3 {
-                    return Err(Rr4cError::UnknownCommand(piece.to_string()));
-                }
-                match &piece[..=3] {
-                    "CAM" => {
+                match Self::rr_command(piece)? {
+                    RrCommand::Cam => {
                         if piece.len() > 3 {
                             self.cam_decode(piece)?;
                         } else {
-                            self.servos.set_camera_pan(None)?;
-                            self.servos.set_camera_tilt(None)?;
+                            self.robot().servos.set_camera_pan(None)?;
+                            self.robot().servos.set_camera_tilt(None)?;
                         }
                         continue;
                     }
-                    "FAN" => {
+                    RrCommand::Fan => {
                         self.fan_decode(piece)?;
                         continue;
                     }
-                    "FRT" => {
+                    RrCommand::Frt => {
                         if piece.len() > 3 {
                             self.frt_decode(piece)?;
                         } else {
-                            self.servos.set_front(None)?;
+                            self.robot().servos.set_front(None)?;
                         }
                         continue;
                     }
-                    "LED" => {
+                    RrCommand::Led => {
                         if piece.len() > 3 {
                             self.led_decode(piece)?;
                         } else {
-                            self.hids.lights(0, 0, 0)?;
+                            self.robot().hids.lights(0, 0, 0)?;
                         }
                         continue;
                     }
-                    "MTR" => {
+                    RrCommand::Mtr => {
                         if piece.len() > 3 {
                             self.mtr_decode(piece)?;
                         } else {
-                            self.motors.movement(self.motor_speed, self.motor_speed)?;
+                            self.robot()
+                                .motors
+                                .movement(self.motor_speed, self.motor_speed)?;
                         }
                         continue;
                     }
-                    y => {
-                        return Err(Rr4cError::UnknownCommand(y.to_string()));
+                    RrCommand::Stop => {
+                        self.stop_decode(piece)?;
+                        continue;
                     }
                 }
             }
             Ok(())
         } else {
-            self.mode = CarModes::Remote;
             Err(Rr4cError::BadCommand(line.to_string()))
         }
     }
+    /// Selects a registered mode by name and starts running it in the
+    /// background.
+    ///
+    /// Any running mode is stopped first.
+    ///
+    /// ## Arguments
+    /// * `name` - Name the mode was registered with.
+    pub fn select_mode(&mut self, name: &str) -> Result {
+        match self.modes.iter().position(|m| m.name == name) {
+            Some(index) => self.run_mode(index),
+            None => Err(Rr4cError::UnknownModeCommand(name.to_string())),
+        }
+    }
+    /// Sets the LED light show used by the `LedColors` mode.
+    ///
+    /// ## Arguments
+    /// * `show` - [LightShow](LightShow) with the palettes, timing, and sonar
+    ///   reaction to use.
+    pub fn set_light_show(&mut self, show: LightShow) {
+        if let Some(entry) = self.modes.iter_mut().find(|m| m.name == "LedColors") {
+            entry.factory = Box::new(move |_| Box::new(show.clone()));
+        }
+    }
+    /// Stops any running mode, brakes, and goes back to remote control.
+    ///
+    /// Why the mode exited is available from [`supervisor()`].
+    ///
+    /// [`supervisor()`]: Decoder::supervisor()
+    pub fn stop_mode(&mut self) -> Result {
+        self.supervisor.stop();
+        let mut robot = self.robot();
+        robot.hids.clear_status(Status::Autonomous);
+        robot.motors.brake()
+    }
+    /// Access the [Supervisor](Supervisor) running autonomous modes.
+    ///
+    /// Used to report the current mode and why the latest one exited.
+    pub fn supervisor(&self) -> &Supervisor {
+        &self.supervisor
+    }
     /// Yahboom command decoder.
     ///
     /// ## Arguments
@@ -146,7 +307,6 @@ impl Decoder {
     pub fn yb_decode<'a, L: Into<&'a str>>(&mut self, line: L) -> Result {
         let line = line.into();
         if !line.starts_with('$') || !line.ends_with('#') {
-            self.mode = CarModes::Remote;
             return Err(Rr4cError::BadCommand(line.to_string()));
         }
         if let Some(line) = line.strip_prefix("$4WD,").and_then(|v| v.strip_suffix("#")) {
@@ -155,7 +315,7 @@ impl Decoder {
                 let pos: u8 = remains
                     .parse()
                     .map_err(|_| Rr4cError::BadCommandValue(line.to_string()))?;
-                self.servos.set_front(pos)?;
+                self.robot().servos.set_front(pos)?;
             // LEDs
             } else if let Some(remains) = line.strip_prefix("CLR") {
                 let mut red: u8;
@@ -178,57 +338,37 @@ impl Decoder {
                             .map_err(|_| Rr4cError::BadCommandValue(line.to_string()))?;
                         // Scale to %
                         blue = 100 * blue / 255;
-                        return self.hids.lights(red, green, blue);
+                        return self.robot().hids.lights(red, green, blue);
                     }
                 }
                 return Err(Rr4cError::BadCommand(line.to_string()));
+            // Emergency stop
+            } else if line.starts_with("STOP") {
+                return self.stop_decode(line);
             } else if let Some(remains) = line.strip_prefix("MODE") {
                 return match remains {
                     "00" | "10" | "20" | "30" | "40" | "50" | "60" => {
-                        self.motors.brake()?;
-                        self.mode = CarModes::Remote;
-                        self.hids.lights(100, 0, 0)?;
-                        self.hids.beep(1.0);
-                        self.hids.lights(0, 0, 0)
+                        self.stop_mode()?;
+                        self.robot().hids.notify(Status::Idle);
+                        Ok(())
                     }
                     "11" => {
-                        self.mode = CarModes::Remote;
-                        self.alert_mode(None)?;
+                        self.stop_mode()?;
+                        self.alert_mode(1)?;
                         Ok(())
                     }
-                    "21" => {
-                        self.mode = CarModes::Tracking;
-                        self.alert_mode(None)?;
-                        self.tracking_mode()
-                    }
-                    "31" => {
-                        self.mode = CarModes::UltrasonicAvoid;
-                        self.alert_mode(None)?;
-                        self.ultrasonic_avoid()
-                    }
-                    "41" => {
-                        self.mode = CarModes::LedColors;
-                        self.alert_mode(None)?;
-                        self.led_colors()
-                    }
-                    "51" => {
-                        self.mode = CarModes::LightSeeking;
-                        self.alert_mode(None)?;
-                        self.light_seeking()
-                    }
-                    "61" => {
-                        self.mode = CarModes::InfraredFollow;
-                        self.alert_mode(None)?;
-                        self.infrared_follow()
-                    }
-                    r => {
-                        self.motors.brake()?;
-                        self.mode = CarModes::Remote;
-                        self.hids.lights(100, 0, 0)?;
-                        self.hids.beep(1.0);
-                        self.hids.lights(0, 0, 0)?;
-                        Err(Rr4cError::UnknownModeCommand(r.to_string()))
-                    }
+                    code => match self
+                        .modes
+                        .iter()
+                        .position(|m| m.code.as_deref() == Some(code))
+                    {
+                        Some(index) => self.run_mode(index),
+                        None => {
+                            self.stop_mode()?;
+                            self.robot().hids.notify(Status::Fault);
+                            Err(Rr4cError::UnknownModeCommand(code.to_string()))
+                        }
+                    },
                 };
             } else {
                 return Err(Rr4cError::UnknownCommand(line.to_string()));
@@ -247,83 +387,95 @@ impl Decoder {
             match bytes[2] {
                 // Not spin
                 b'0' => match bytes[1] {
-                    b'0' => self.motors.brake()?,
-                    b'1' => self.motors.movement(self.motor_speed, self.motor_speed)?,
-                    b'2' => self.motors.movement(-self.motor_speed, -self.motor_speed)?,
-                    b'3' => self.motors.movement(0, self.motor_speed)?,
-                    b'4' => self.motors.movement(self.motor_speed, 0)?,
-                    b'5' => self.motors.movement(0, -self.motor_speed)?, // Non Yahboom extension
-                    b'6' => self.motors.movement(-self.motor_speed, 0)?, // Non Yahboom extension
+                    b'0' => self.robot().motors.brake()?,
+                    b'1' => self
+                        .robot()
+                        .motors
+                        .movement(self.motor_speed, self.motor_speed)?,
+                    b'2' => self
+                        .robot()
+                        .motors
+                        .movement(-self.motor_speed, -self.motor_speed)?,
+                    b'3' => self.robot().motors.movement(0, self.motor_speed)?,
+                    b'4' => self.robot().motors.movement(self.motor_speed, 0)?,
+                    b'5' => self.robot().motors.movement(0, -self.motor_speed)?, // Non Yahboom extension
+                    b'6' => self.robot().motors.movement(-self.motor_speed, 0)?, // Non Yahboom extension
                     y => return Err(Rr4cError::UnknownMotorCommand(y)),
                 },
-                b'1' => self.motors.movement(-self.motor_speed, self.motor_speed)?,
-                b'2' => self.motors.movement(self.motor_speed, -self.motor_speed)?,
+                b'1' => self
+                    .robot()
+                    .motors
+                    .movement(-self.motor_speed, self.motor_speed)?,
+                b'2' => self
+                    .robot()
+                    .motors
+                    .movement(self.motor_speed, -self.motor_speed)?,
                 y => return Err(Rr4cError::UnknownSpinCommand(y)),
             };
             if bytes[4] == b'1' {
-                self.hids.whistle();
+                self.robot().hids.whistle();
             };
             // Servos
             match bytes[8] {
                 b'0' => {}
-                b'1' => self.servos.front_left()?,
-                b'2' => self.servos.front_right()?,
-                b'3' => self.servos.camera_tilt_up()?,
-                b'4' => self.servos.camera_tilt_down()?,
-                b'5' => self.servos.set_camera_tilt(90)?,
-                b'6' => self.servos.camera_pan_left()?,
-                b'7' => self.servos.camera_pan_right()?,
-                b'8' => self.servos.set_camera_pan(90)?,
-                b'9' => self.servos.set_front(90)?, // Non Yahboom extension
+                b'1' => self.robot().servos.front_left()?,
+                b'2' => self.robot().servos.front_right()?,
+                b'3' => self.robot().servos.camera_tilt_up()?,
+                b'4' => self.robot().servos.camera_tilt_down()?,
+                b'5' => self.robot().servos.set_camera_tilt(90)?,
+                b'6' => self.robot().servos.camera_pan_left()?,
+                b'7' => self.robot().servos.camera_pan_right()?,
+                b'8' => self.robot().servos.set_camera_pan(90)?,
+                b'9' => self.robot().servos.set_front(90)?, // Non Yahboom extension
                 y => return Err(Rr4cError::UnknownServoCommand(y)),
             }
             // Yahboom hacky front servo reset.
             if bytes[16] == b'1' {
-                self.servos.set_front(90)?;
+                self.robot().servos.set_front(90)?;
             }
             // LEDs
             match bytes[12] {
                 b'0' => {
                     self.led_color = 0;
-                    self.hids.set_color(self.led_color)?;
+                    self.robot().hids.set_color(self.led_color)?;
                 }
                 b'1' => {
                     self.led_color += 1;
-                    self.hids.set_color(self.led_color)?;
+                    self.robot().hids.set_color(self.led_color)?;
                 }
                 b'2' => {
                     self.led_color = 2;
-                    self.hids.set_color(self.led_color)?;
+                    self.robot().hids.set_color(self.led_color)?;
                 }
                 b'3' => {
                     self.led_color = 3;
-                    self.hids.set_color(self.led_color)?;
+                    self.robot().hids.set_color(self.led_color)?;
                 }
                 b'4' => {
                     self.led_color = 4;
-                    self.hids.set_color(self.led_color)?;
+                    self.robot().hids.set_color(self.led_color)?;
                 }
                 b'5' => {
                     self.led_color = 5;
-                    self.hids.set_color(self.led_color)?;
+                    self.robot().hids.set_color(self.led_color)?;
                 }
                 b'6' => {
                     self.led_color = 6;
-                    self.hids.set_color(self.led_color)?;
+                    self.robot().hids.set_color(self.led_color)?;
                 }
                 b'7' => {
                     self.led_color = 7;
-                    self.hids.set_color(self.led_color)?;
+                    self.robot().hids.set_color(self.led_color)?;
                 }
                 b'8' => {
                     self.led_color = 0;
-                    self.hids.set_color(self.led_color)?;
+                    self.robot().hids.set_color(self.led_color)?;
                 }
                 y => return Err(Rr4cError::UnknownLedCommand(y)),
             }
             // Fan (outfire)
             if bytes[14] == b'1' {
-                self.hids.toggle_fan()?;
+                self.robot().hids.toggle_fan()?;
             }
         } else {
             return Err(Rr4cError::BadCommand(line.to_string()));
@@ -333,37 +485,19 @@ impl Decoder {
     /// Visual/audio human mode change alerter.
     ///
     /// ## Arguments
-    /// * `mode` - Optional `CarModes` to use for alert.
-    /// Defaults to the internally tracked mode value.
-    fn alert_mode(&mut self, mode: Option<CarModes>) -> Result {
-        let mode = mode.unwrap_or(self.mode);
+    /// * `count` - Number of times to flash the LEDs and beep.
+    ///   `Remote` mode uses 1 with the built-in modes counting up from 2.
+    fn alert_mode(&mut self, count: u8) -> Result {
         let length = 0.2;
         let delay = Duration::from_secs_f64(length);
-        let count = match mode {
-            CarModes::Remote => 1u8,
-            CarModes::Tracking => 2,
-            CarModes::UltrasonicAvoid => 3,
-            CarModes::LedColors => 4,
-            CarModes::LightSeeking => 5,
-            CarModes::InfraredFollow => 6,
-        };
         for i in 0..count {
-            self.hids.set_color(i)?;
-            self.hids.beep(length);
-            self.hids.lights(0, 0, 0)?;
+            self.robot().hids.set_color(i)?;
+            self.robot().hids.beep(length);
+            self.robot().hids.lights(0, 0, 0)?;
             sleep(delay);
         }
         Ok(())
     }
-    fn infrared_follow(&self) -> Result {
-        todo!()
-    }
-    fn led_colors(&self) -> Result {
-        todo!()
-    }
-    fn light_seeking(&self) -> Result {
-        todo!()
-    }
     /// Camera command decoder.
     ///
     /// ## Arguments
@@ -372,16 +506,16 @@ impl Decoder {
     fn cam_decode(&mut self, piece: &str) -> Result {
         match &piece[3..4] {
             "I" => {
-                self.servos.set_camera_pan(None)?;
-                self.servos.set_camera_tilt(None)
+                self.robot().servos.set_camera_pan(None)?;
+                self.robot().servos.set_camera_tilt(None)
             }
             "P" => {
                 if piece.len() == 5 {
                     if &piece[5..6] == "L" {
-                        return self.servos.camera_pan_left();
+                        return self.robot().servos.camera_pan_left();
                     }
                     if &piece[5..6] == "R" {
-                        return self.servos.camera_pan_right();
+                        return self.robot().servos.camera_pan_right();
                     }
                 }
                 let angle: Option<u8>;
@@ -394,15 +528,15 @@ impl Decoder {
                 } else {
                     angle = None;
                 }
-                self.servos.set_camera_pan(angle)
+                self.robot().servos.set_camera_pan(angle)
             }
             "T" => {
                 if piece.len() == 5 {
                     if &piece[5..6] == "D" {
-                        return self.servos.camera_tilt_down();
+                        return self.robot().servos.camera_tilt_down();
                     }
                     if &piece[5..6] == "U" {
-                        return self.servos.camera_tilt_up();
+                        return self.robot().servos.camera_tilt_up();
                     }
                 }
                 let angle: Option<u8>;
@@ -415,7 +549,7 @@ impl Decoder {
                 } else {
                     angle = None;
                 }
-                self.servos.set_camera_tilt(angle)
+                self.robot().servos.set_camera_tilt(angle)
             }
             _ => {
                 let mut angles = Vec::new();
@@ -427,12 +561,12 @@ impl Decoder {
                 }
                 match angles.len() {
                     1 => {
-                        self.servos.set_camera_pan(angles[0])?;
-                        self.servos.set_camera_tilt(angles[0])
+                        self.robot().servos.set_camera_pan(angles[0])?;
+                        self.robot().servos.set_camera_tilt(angles[0])
                     }
                     2 => {
-                        self.servos.set_camera_pan(angles[0])?;
-                        self.servos.set_camera_tilt(angles[1])
+                        self.robot().servos.set_camera_pan(angles[0])?;
+                        self.robot().servos.set_camera_tilt(angles[1])
                     }
                     _ => Err(Rr4cError::BadCommandValue(piece.to_string())),
                 }
@@ -444,18 +578,37 @@ impl Decoder {
     /// ## Arguments
     /// * `piece` - Segment of command frame to be decoded.
     fn fan_decode(&mut self, piece: &str) -> Result {
-        match &piece[3..4] {
+        match Self::fan_command(piece)? {
+            FanCommand::Blow => self.robot().hids.blow(10.0),
+            FanCommand::Off => self.robot().hids.set_fan_speed(0),
+            FanCommand::Speed(speed) => self.robot().hids.set_fan_speed(speed),
+            FanCommand::Toggle => self.robot().hids.toggle_fan(),
+        }
+    }
+    /// Parses a fan command piece.
+    ///
+    /// ## Arguments
+    /// * `piece` - Segment of command frame to be decoded.
+    pub(crate) fn fan_command(piece: &str) -> Rr4cResult<FanCommand> {
+        match piece.get(3..4) {
             // Toggle Fan On/Off
-            "T" => self.hids.toggle_fan(),
+            Some("T") => Ok(FanCommand::Toggle),
             // Turn Fan Off
-            "0" => {
-                self.hids.blow(0.01);
-                Ok(())
-            }
+            Some("0") => Ok(FanCommand::Off),
             // Turn Fan On for 10 secs.
-            "1" => {
-                self.hids.blow(10.0);
-                Ok(())
+            Some("1") => Ok(FanCommand::Blow),
+            // Set Fan speed
+            Some("S") => {
+                let speed: Option<u8> = if piece.len() > 4 {
+                    Some(
+                        piece[4..]
+                            .parse()
+                            .map_err(|_| Rr4cError::BadCommandValue(piece.to_string()))?,
+                    )
+                } else {
+                    None
+                };
+                Ok(FanCommand::Speed(speed))
             }
             _ => Err(Rr4cError::BadCommandValue(piece.to_string())),
         }
@@ -466,16 +619,16 @@ impl Decoder {
     /// * `piece` - Segment of command frame to be decoded.
     fn frt_decode(&mut self, piece: &str) -> Result {
         match &piece[3..4] {
-            "I" => self.servos.set_front(None),
-            "L" => self.servos.front_left(),
-            "R" => self.servos.front_right(),
+            "I" => self.robot().servos.set_front(None),
+            "L" => self.robot().servos.front_left(),
+            "R" => self.robot().servos.front_right(),
             _ => {
                 let angle: Option<u8> = Some(
                     piece[4..]
                         .parse()
                         .map_err(|_| Rr4cError::BadCommandValue(piece.to_string()))?,
                 );
-                self.servos.set_front(angle)
+                self.robot().servos.set_front(angle)
             }
         }
     }
@@ -497,7 +650,7 @@ impl Decoder {
                 } else {
                     brightness = None;
                 }
-                self.hids.set_blue(brightness)
+                self.robot().hids.set_blue(brightness)
             }
             "C" => {
                 let index: u8;
@@ -508,7 +661,7 @@ impl Decoder {
                 } else {
                     return Err(Rr4cError::BadCommandValue(piece.to_string()));
                 }
-                self.hids.set_color(index)
+                self.robot().hids.set_color(index)
             }
             "G" => {
                 let brightness: Option<u8>;
@@ -521,7 +674,7 @@ impl Decoder {
                 } else {
                     brightness = None;
                 }
-                self.hids.set_green(brightness)
+                self.robot().hids.set_green(brightness)
             }
             "R" => {
                 let brightness: Option<u8>;
@@ -534,7 +687,7 @@ impl Decoder {
                 } else {
                     brightness = None;
                 }
-                self.hids.set_red(brightness)
+                self.robot().hids.set_red(brightness)
             }
             _ => {
                 let mut colors = Vec::new();
@@ -547,9 +700,9 @@ impl Decoder {
                 match colors.len() {
                     1 => {
                         // Use the same brightness for all colors to have white.
-                        self.hids.lights(colors[0], colors[0], colors[0])
+                        self.robot().hids.lights(colors[0], colors[0], colors[0])
                     }
-                    3 => self.hids.lights(colors[0], colors[1], colors[2]),
+                    3 => self.robot().hids.lights(colors[0], colors[1], colors[2]),
                     _ => Err(Rr4cError::BadCommandValue(piece.to_string())),
                 }
             }
@@ -565,44 +718,40 @@ impl Decoder {
             // Motor Accelerate
             "A" => {
                 self.motor_speed = (self.motor_speed + Self::SPEED_INCREMENT).min(100);
-                let (mut left, mut right) = self.motors.speeds();
-                match left.signum() {
-                    1 => left = (left + Self::SPEED_INCREMENT).min(100),
-                    0 => left = Self::SPEED_INCREMENT,
-                    -1 => left = (left - Self::SPEED_INCREMENT).max(-100),
-                    _ => unreachable!(),
-                }
-                match right.signum() {
-                    1 => right = (right + Self::SPEED_INCREMENT).min(100),
-                    -1 => right = (right - Self::SPEED_INCREMENT).max(-100),
-                    0 => right = Self::SPEED_INCREMENT,
-                    _ => unreachable!(),
-                }
-                self.motors.movement(left, right)
+                let (mut left, mut right) = self.robot().motors.speeds();
+                match left.cmp(&0) {
+                    Greater => left = (left + Self::SPEED_INCREMENT).min(100),
+                    Equal => left = Self::SPEED_INCREMENT,
+                    Less => left = (left - Self::SPEED_INCREMENT).max(-100),
+                }
+                match right.cmp(&0) {
+                    Greater => right = (right + Self::SPEED_INCREMENT).min(100),
+                    Less => right = (right - Self::SPEED_INCREMENT).max(-100),
+                    Equal => right = Self::SPEED_INCREMENT,
+                }
+                self.robot().motors.movement(left, right)
             }
             // Motor Decelerate
             "D" => {
                 self.motor_speed =
                     (self.motor_speed - Self::SPEED_INCREMENT).max(Self::SPEED_INCREMENT);
-                let (mut left, mut right) = self.motors.speeds();
-                match left.signum() {
-                    -1 => left = (left + Self::SPEED_INCREMENT).min(-Self::SPEED_INCREMENT),
-                    0 => left = 0,
-                    1 => left = (left - Self::SPEED_INCREMENT).max(Self::SPEED_INCREMENT),
-                    _ => unreachable!(),
-                }
-                match right.signum() {
-                    -1 => right = (right + Self::SPEED_INCREMENT).min(-Self::SPEED_INCREMENT),
-                    1 => right = (right - Self::SPEED_INCREMENT).max(Self::SPEED_INCREMENT),
-                    0 => right = 0,
-                    _ => unreachable!(),
-                }
-                self.motors.movement(left, right)
+                let (mut left, mut right) = self.robot().motors.speeds();
+                match left.cmp(&0) {
+                    Less => left = (left + Self::SPEED_INCREMENT).min(-Self::SPEED_INCREMENT),
+                    Equal => left = 0,
+                    Greater => left = (left - Self::SPEED_INCREMENT).max(Self::SPEED_INCREMENT),
+                }
+                match right.cmp(&0) {
+                    Less => right = (right + Self::SPEED_INCREMENT).min(-Self::SPEED_INCREMENT),
+                    Greater => right = (right - Self::SPEED_INCREMENT).max(Self::SPEED_INCREMENT),
+                    Equal => right = 0,
+                }
+                self.robot().motors.movement(left, right)
             }
             // Motor Enable/Disable
             "E" => {
                 if piece == "MTRE0" || piece == "MTRE1" {
-                    self.motors.enable(piece == "MTRE1");
+                    self.robot().motors.enable(piece == "MTRE1");
                     Ok(())
                 } else {
                     Err(Rr4cError::BadCommandValue(piece.to_string()))
@@ -618,7 +767,7 @@ impl Decoder {
                 } else {
                     speed = self.motor_speed;
                 }
-                self.motors.movement(speed, 0)
+                self.robot().motors.movement(speed, 0)
             }
             // Motor Right
             "R" => {
@@ -630,7 +779,7 @@ impl Decoder {
                 } else {
                     speed = self.motor_speed;
                 }
-                self.motors.movement(0, speed)
+                self.robot().motors.movement(0, speed)
             }
             // Motor Spin Left/Right
             "S" => {
@@ -646,9 +795,9 @@ impl Decoder {
                     speed = self.motor_speed;
                 }
                 if &piece[4..5] == "L" {
-                    self.motors.movement(-speed, speed)
+                    self.robot().motors.movement(-speed, speed)
                 } else if &piece[4..5] == "R" {
-                    self.motors.movement(speed, -speed)
+                    self.robot().motors.movement(speed, -speed)
                 } else {
                     Err(Rr4cError::BadCommand(piece.to_string()))
                 }
@@ -665,18 +814,18 @@ impl Decoder {
                 match speeds.len() {
                     1 => {
                         if speeds[0] == 1 || speeds[0] == 0 {
-                            self.motors.enable(speeds[0] == 1);
+                            self.robot().motors.enable(speeds[0] == 1);
                             Ok(())
                         } else {
                             Err(Rr4cError::BadCommandValue(piece.to_string()))
                         }
                     }
-                    2 => self.motors.movement(speeds[0], speeds[1]),
+                    2 => self.robot().motors.movement(speeds[0], speeds[1]),
                     3 => {
                         let (left, right, enable) = (speeds[0], speeds[1], speeds[2]);
-                        self.motors.movement(left, right)?;
+                        self.robot().motors.movement(left, right)?;
                         if enable == 1 || enable == 0 {
-                            self.motors.enable(enable == 1);
+                            self.robot().motors.enable(enable == 1);
                             Ok(())
                         } else {
                             Err(Rr4cError::BadCommandValue(piece.to_string()))
@@ -687,28 +836,192 @@ impl Decoder {
             }
         }
     }
-    fn tracking_mode(&mut self) -> Result {
-        todo!()
+    /// Locks the shared [Robot](Robot).
+    fn robot(&self) -> MutexGuard<'_, Robot> {
+        self.robot.lock().expect("Someone broke the lock")
+    }
+    /// Finds which command a RR4W frame piece is for.
+    ///
+    /// Commands are the first three characters of the piece except for
+    /// `STOP`.
+    /// All but `FAN` can also be sent by themselves to use their defaults.
+    ///
+    /// ## Arguments
+    /// * `piece` - Segment of command frame to be decoded.
+    pub(crate) fn rr_command(piece: &str) -> Rr4cResult<RrCommand> {
+        if piece.starts_with("STOP") {
+            return Ok(RrCommand::Stop);
+        }
+        match piece.get(..3) {
+            Some("CAM") => Ok(RrCommand::Cam),
+            Some("FAN") if piece.len() > 3 => Ok(RrCommand::Fan),
+            Some("FRT") => Ok(RrCommand::Frt),
+            Some("LED") => Ok(RrCommand::Led),
+            Some("MTR") => Ok(RrCommand::Mtr),
+            _ => Err(Rr4cError::UnknownCommand(piece.to_string())),
+        }
+    }
+    /// Starts running a registered mode in the background.
+    ///
+    /// Any running mode is stopped first then the mode is alerted before it
+    /// starts.
+    ///
+    /// ## Arguments
+    /// * `index` - Index of the mode in the registry.
+    fn run_mode(&mut self, index: usize) -> Result {
+        if self.emergency_stop.is_engaged() {
+            return Err(Rr4cError::EmergencyStop);
+        }
+        self.supervisor.stop();
+        let entry = &mut self.modes[index];
+        let mode = (entry.factory)(self.motor_speed);
+        let name = entry.name.clone();
+        let alerts = entry.alerts;
+        self.alert_mode(alerts)?;
+        self.robot().hids.set_status(Status::Autonomous);
+        let mode = Latched(self.emergency_stop.clone(), mode);
+        let mode = Supervised {
+            mode: Box::new(mode),
+            sonar_active: false,
+        };
+        self.supervisor.start(&name, self.robot.clone(), mode);
+        Ok(())
+    }
+    /// Finds if an emergency stop piece or frame engages or resets the latch.
+    ///
+    /// Returns `true` for `STOP` or `STOP1` which engage the latch and `false`
+    /// for `STOP0` which resets it.
+    ///
+    /// ## Arguments
+    /// * `piece` - Command piece starting with `STOP`.
+    pub(crate) fn stop_command(piece: &str) -> Rr4cResult<bool> {
+        match piece.get(4..) {
+            Some("" | "1") => Ok(true),
+            Some("0") => Ok(false),
+            _ => Err(Rr4cError::BadCommandValue(piece.to_string())),
+        }
     }
-    fn ultrasonic_avoid(&self) -> Result {
-        todo!()
+    /// Decodes an emergency stop piece or frame.
+    ///
+    /// `STOP` or `STOP1` engages the latch and `STOP0` resets it.
+    ///
+    /// ## Arguments
+    /// * `piece` - Command piece starting with `STOP`.
+    fn stop_decode(&mut self, piece: &str) -> Result {
+        if Self::stop_command(piece)? {
+            self.emergency_stop()
+        } else {
+            self.reset_emergency_stop();
+            Ok(())
+        }
     }
     /// Increment value used when change motor speed in a command.
     const SPEED_INCREMENT: i8 = 10;
 }
 
-/// Used to track current robot control mode.
-#[derive(Debug, Copy, Clone)]
-pub(crate) enum CarModes {
-    /// Car is by default in `Remote` mode if another mode has not been selected.
-    Remote,
-    /// Automated line tracking mode.
-    Tracking,
-    /// Combined ultrasonic and proximity obstacle avoidance mode.
-    UltrasonicAvoid,
-    LedColors,
-    /// Follows a visible light source.
-    LightSeeking,
-    /// todo
-    InfraredFollow,
+/// Fan commands found in a RR4W `FAN` piece.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FanCommand {
+    /// Turn the fan on for 10 seconds.
+    Blow,
+    /// Turn the fan off.
+    Off,
+    /// Set the fan speed or use the last one when `None`.
+    Speed(Option<u8>),
+    /// Toggle the fan on/off.
+    Toggle,
+}
+
+/// Commands found at the start of a RR4W frame piece.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RrCommand {
+    /// Camera pan and tilt servos.
+    Cam,
+    /// Fan.
+    Fan,
+    /// Front servo.
+    Frt,
+    /// LEDs.
+    Led,
+    /// Motors.
+    Mtr,
+    /// Emergency stop latch.
+    Stop,
+}
+
+/// Factory used to make a new instance of a registered mode.
+type ModeFactory = Box<dyn FnMut(i8) -> Box<dyn Mode> + Send>;
+
+/// A mode registered with a [Decoder](Decoder).
+struct ModeEntry {
+    /// Number of times to flash the LEDs and beep when selected.
+    alerts: u8,
+    /// Optional Yahboom `MODExx` code.
+    code: Option<String>,
+    /// Makes a new instance of the mode.
+    factory: ModeFactory,
+    /// Name of the mode.
+    name: String,
+}
+
+impl fmt::Debug for ModeEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModeEntry")
+            .field("alerts", &self.alerts)
+            .field("code", &self.code)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Wraps a mode to abort it as soon as an [EmergencyStop](EmergencyStop)
+/// latch is engaged.
+pub(crate) struct Latched<M>(pub(crate) EmergencyStop, pub(crate) M);
+
+impl<T, M: Mode<T>> Mode<T> for Latched<M> {
+    fn start(&mut self, target: &mut T) -> Result {
+        self.1.start(target)
+    }
+    fn step(&mut self, target: &mut T, now: Instant) -> Rr4cResult<bool> {
+        self.0.check()?;
+        self.1.step(target, now)
+    }
+    fn stop(&mut self, target: &mut T, reason: &ExitReason) -> Result {
+        self.1.stop(target, reason)
+    }
+}
+
+/// Wraps a mode run by a [Decoder](Decoder) to add the clean up shared by
+/// all of them.
+///
+/// Active sonar is turned on while the mode runs so its steps never wait on
+/// an echo while holding the robot.
+/// Once the mode exits the motors are braked, active sonar is put back how it
+/// was, the autonomous status is cleared, and a fault is shown if it failed.
+struct Supervised {
+    /// The wrapped mode.
+    mode: Box<dyn Mode>,
+    /// Whether active sonar was on before the mode started.
+    sonar_active: bool,
+}
+
+impl Mode for Supervised {
+    fn start(&mut self, robot: &mut Robot) -> Result {
+        self.sonar_active = robot.sensors.is_sonar_active();
+        robot.sensors.sonar_active(true)?;
+        self.mode.start(robot)
+    }
+    fn step(&mut self, robot: &mut Robot, now: Instant) -> Rr4cResult<bool> {
+        self.mode.step(robot, now)
+    }
+    fn stop(&mut self, robot: &mut Robot, reason: &ExitReason) -> Result {
+        let braked = robot.motors.brake();
+        let stopped = self.mode.stop(robot, reason);
+        let sonar = robot.sensors.sonar_active(self.sonar_active);
+        robot.hids.clear_status(Status::Autonomous);
+        if let ExitReason::Failed(_) = reason {
+            robot.hids.notify(Status::Fault);
+        }
+        braked.and(stopped).and(sonar)
+    }
 }
@@ -44,10 +44,16 @@ pub enum Rr4cError {
     BadCommand(String),
     #[error("Was given bad command value in command: '{0}'")]
     BadCommandValue(String),
+    #[error("Was given invalid RTTTL: '{0}'")]
+    BadRtttl(String),
+    #[error("Emergency stop is engaged")]
+    EmergencyStop,
     #[error("Gpio access failed")]
     Gpio(#[from] rppal::gpio::Error),
     #[error("Was given an invalid or incomplete command: '{0}'")]
     IncompleteCommand(String),
+    #[error("Mode name or code is already in use: '{0}'")]
+    ModeInUse(String),
     #[error("Given unknown command: '{0}'")]
     UnknownCommand(String),
     #[error("Given unknown led command: '{0}'")]
@@ -60,10 +66,27 @@ pub enum Rr4cError {
     UnknownMotorSpeedCommand(u8),
     #[error("Given unknown servo command '{0}'")]
     UnknownServoCommand(u8),
+    #[error("Sonar measurement failed")]
+    Sonar(#[from] SonarError),
+    #[error("Sonar is being pinged by a scheduler")]
+    SonarScheduled,
     #[error("Given unknown spin command '{0}'")]
     UnknownSpinCommand(u8),
 }
 
+/// Reasons an ultrasonic distance measurement can fail.
+#[derive(Clone, Copy, Debug, Error, PartialEq)]
+pub enum SonarError {
+    #[error("No echo was heard")]
+    NoEcho,
+    #[error("Echo distance of {0}cm is out of range")]
+    OutOfRange(f32),
+    #[error("Newest reading is stale by {0:?}")]
+    Stale(std::time::Duration),
+    #[error("Echo did not finish before timeout")]
+    Timeout,
+}
+
 /// Result type used when return value is needed from methods in library.
 pub type Rr4cResult<T> = std::result::Result<T, Rr4cError>;
 
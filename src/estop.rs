@@ -0,0 +1,107 @@
+// Copyright © 2021-present, Michael Cummings
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// MIT License
+//
+// Copyright © 2021-present, Michael Cummings <mgcummings@yahoo.com>.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! Contains the emergency stop latch shared by everything that can move the
+//! robot.
+
+use crate::{Result, Rr4cError};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// A shared emergency stop latch.
+///
+/// Clones all share the same latch so it can be handed to anything that needs
+/// to trigger it or check it.
+/// Once engaged it stays engaged until [`reset()`] is called.
+/// [Motors](crate::Motors) refuse to move while it is engaged and it is shown
+/// in the [Sensors](crate::Sensors) postbacks.
+///
+/// __NOTE:__ Engaging the latch by itself does not stop motors which are
+/// already moving.
+/// Use [`Decoder::emergency_stop()`] which also brakes and stops any running
+/// mode.
+///
+/// ## Examples
+///
+/// ```edition2018
+/// use rust_rpi_4wd_car::EmergencyStop;
+///
+/// let estop = EmergencyStop::new();
+/// let shared = estop.clone();
+/// shared.engage();
+/// assert!(estop.is_engaged());
+/// estop.reset();
+/// assert!(!shared.is_engaged());
+/// ```
+///
+/// [`reset()`]: EmergencyStop::reset()
+/// [`Decoder::emergency_stop()`]: crate::Decoder::emergency_stop()
+#[derive(Clone, Debug, Default)]
+pub struct EmergencyStop {
+    /// Shared latch state.
+    engaged: Arc<AtomicBool>,
+}
+
+impl EmergencyStop {
+    /// Constructor
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Used before moving to refuse while the latch is engaged.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`Rr4cError::EmergencyStop`] while the latch is engaged.
+    pub fn check(&self) -> Result {
+        if self.is_engaged() {
+            return Err(Rr4cError::EmergencyStop);
+        }
+        Ok(())
+    }
+    /// Engages the latch.
+    pub fn engage(&self) {
+        self.engaged.store(true, Ordering::Release);
+    }
+    /// Used to check if the latch is engaged.
+    pub fn is_engaged(&self) -> bool {
+        self.engaged.load(Ordering::Acquire)
+    }
+    /// Releases the latch so the robot can move again.
+    pub fn reset(&self) {
+        self.engaged.store(false, Ordering::Release);
+    }
+}
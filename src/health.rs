@@ -0,0 +1,336 @@
+// Copyright © 2021-present, Michael Cummings
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// MIT License
+//
+// Copyright © 2021-present, Michael Cummings <mgcummings@yahoo.com>.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! Contains the sensor health diagnostics used to catch sensors that are
+//! stuck, dead or unplugged.
+
+use crate::{DigitalSensor, Reading, SonarError};
+use std::{fmt, time::Duration};
+
+/// Health of a single digital sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DigitalHealth {
+    /// Which sensor.
+    pub sensor: DigitalSensor,
+    /// Latest unfiltered state of the sensor.
+    pub state: bool,
+    /// Time since the unfiltered state last changed or since the sensors were
+    /// created when it never has.
+    pub since_edge: Duration,
+    /// Count of unfiltered changes of state.
+    pub raw_transitions: u64,
+}
+
+/// Problem found by a [HealthMonitor](HealthMonitor).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HealthWarning {
+    /// A digital sensor is changing state far faster than is believable.
+    Chattering {
+        /// Which sensor.
+        sensor: DigitalSensor,
+        /// Changes of state per second.
+        rate: f32,
+    },
+    /// Sonar measurements keep failing in a row.
+    SonarDead {
+        /// Number of failures in a row.
+        failures: u32,
+        /// Latest failure.
+        last_error: Option<SonarError>,
+    },
+    /// Sonar measurements fail more often than they succeed.
+    SonarUnreliable {
+        /// Share of measurements that failed from 0.0 to 1.0.
+        failure_rate: f32,
+    },
+    /// A line tracking sensor hasn't changed while the others next to it have
+    /// or an infrared (IR) proximity sensor has been active for too long.
+    Stuck {
+        /// Which sensor.
+        sensor: DigitalSensor,
+        /// State it is stuck in.
+        state: bool,
+        /// How long it has been stuck.
+        since_edge: Duration,
+    },
+}
+
+impl fmt::Display for HealthWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthWarning::Chattering { sensor, rate } => {
+                write!(f, "{:?} is chattering at {:.0} changes/s", sensor, rate)
+            }
+            HealthWarning::SonarDead {
+                failures,
+                last_error,
+            } => match last_error {
+                Some(error) => write!(f, "Sonar failed {} times in a row: {}", failures, error),
+                None => write!(f, "Sonar failed {} times in a row", failures),
+            },
+            HealthWarning::SonarUnreliable { failure_rate } => {
+                write!(f, "Sonar fails {:.0}% of the time", failure_rate * 100.0)
+            }
+            HealthWarning::Stuck {
+                sensor,
+                state,
+                since_edge,
+            } => write!(
+                f,
+                "{:?} looks stuck {} for {:.1}s",
+                sensor,
+                if *state { "on" } else { "off" },
+                since_edge.as_secs_f32()
+            ),
+        }
+    }
+}
+
+/// Sensor statistics with any warnings from a [HealthMonitor](HealthMonitor).
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthReport {
+    /// Monotonic time since the [Sensors](crate::Sensors) were created.
+    pub timestamp: Duration,
+    /// Built-in sonar statistics.
+    pub sonar: SonarStats,
+    /// Health of each digital sensor in [DigitalSensor](DigitalSensor) order.
+    pub digital: [DigitalHealth; 6],
+    /// Problems found.
+    pub warnings: Vec<HealthWarning>,
+}
+
+impl HealthReport {
+    /// Used to check if no problems were found.
+    pub fn is_healthy(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+/// Checks sensor statistics for sensors that look stuck or dead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HealthMonitor {
+    /// Changes of state per second above which a digital sensor is chattering.
+    chatter_rate: f32,
+    /// Previous check time and raw transitions used to measure chattering.
+    previous: Option<(Duration, [u64; 6])>,
+    /// Failures in a row after which the sonar is dead.
+    sonar_failures: u32,
+    /// How long a line tracking sensor can go unchanged while the others next
+    /// to it change, or an IR sensor can stay active, before it is stuck.
+    stuck_after: Duration,
+}
+
+impl HealthMonitor {
+    /// Constructor which uses default values for all optional arguments.
+    pub fn new() -> Self {
+        Self::new_with_thresholds(None, None, None)
+    }
+    /// Constructor with all optional arguments.
+    ///
+    /// ## Arguments
+    ///
+    /// * `stuck_after` - Seconds a line tracking sensor can go unchanged while
+    ///   the others next to it change, or an infrared (IR) proximity sensor can
+    ///   stay active, before it is stuck.
+    ///   Defaults to 30 seconds.
+    /// * `sonar_failures` - Failures in a row after which the sonar is dead.
+    ///   Defaults to 10 failures.
+    /// * `chatter_rate` - Changes of state per second above which a digital
+    ///   sensor is chattering.
+    ///   Defaults to 200 changes per second.
+    pub fn new_with_thresholds<S, F, C>(stuck_after: S, sonar_failures: F, chatter_rate: C) -> Self
+    where
+        S: Into<Option<f64>>,
+        F: Into<Option<u32>>,
+        C: Into<Option<f32>>,
+    {
+        let stuck_after = stuck_after.into().unwrap_or(Self::STUCK_AFTER).max(0.0);
+        Self {
+            chatter_rate: chatter_rate.into().unwrap_or(Self::CHATTER_RATE).max(0.0),
+            previous: None,
+            sonar_failures: sonar_failures.into().unwrap_or(Self::SONAR_FAILURES).max(1),
+            stuck_after: Duration::from_secs_f64(stuck_after),
+        }
+    }
+    /// Checks sensor statistics and produces a [HealthReport](HealthReport).
+    ///
+    /// ## Arguments
+    ///
+    /// * `timestamp` - Monotonic time of the statistics.
+    /// * `sonar` - Sonar statistics.
+    /// * `digital` - Health of each digital sensor in
+    ///   [DigitalSensor](DigitalSensor) order.
+    pub fn check(
+        &mut self,
+        timestamp: Duration,
+        sonar: SonarStats,
+        digital: [DigitalHealth; 6],
+    ) -> HealthReport {
+        let mut warnings = Vec::new();
+        if sonar.consecutive_failures >= self.sonar_failures {
+            warnings.push(HealthWarning::SonarDead {
+                failures: sonar.consecutive_failures,
+                last_error: sonar.last_error,
+            });
+        } else if sonar.attempts() >= Self::MIN_SONAR_ATTEMPTS && sonar.failure_rate() > 0.5 {
+            warnings.push(HealthWarning::SonarUnreliable {
+                failure_rate: sonar.failure_rate(),
+            });
+        }
+        // The IR sensors see different sides so are checked on their own. Only
+        // staying active is suspicious as open space leaves them inactive.
+        let stuck_ir = Self::IR_SENSORS
+            .iter()
+            .filter(|i| digital[**i].state && digital[**i].since_edge >= self.stuck_after);
+        let changing = Self::LINE_SENSORS
+            .iter()
+            .any(|i| digital[*i].since_edge < self.stuck_after);
+        // Nothing changing at all is just a robot sitting still.
+        let stuck_line = Self::LINE_SENSORS
+            .iter()
+            .filter(|i| changing && digital[**i].since_edge >= self.stuck_after);
+        for i in stuck_ir.chain(stuck_line) {
+            let health = digital[*i];
+            warnings.push(HealthWarning::Stuck {
+                sensor: health.sensor,
+                state: health.state,
+                since_edge: health.since_edge,
+            });
+        }
+        if let Some((previous, transitions)) = self.previous {
+            let secs = timestamp.saturating_sub(previous).as_secs_f32();
+            if secs > 0.0 {
+                for (health, before) in digital.iter().zip(transitions.iter()) {
+                    let rate = health.raw_transitions.saturating_sub(*before) as f32 / secs;
+                    if rate > self.chatter_rate {
+                        warnings.push(HealthWarning::Chattering {
+                            sensor: health.sensor,
+                            rate,
+                        });
+                    }
+                }
+            }
+        }
+        self.previous = Some((timestamp, digital.map(|h| h.raw_transitions)));
+        HealthReport {
+            timestamp,
+            sonar,
+            digital,
+            warnings,
+        }
+    }
+    /// Default changes of state per second above which a sensor is chattering.
+    const CHATTER_RATE: f32 = 200.0;
+    /// Indexes of the infrared (IR) proximity sensors.
+    const IR_SENSORS: [usize; 2] = [0, 1];
+    /// Indexes of the line tracking sensors that are expected to change
+    /// together.
+    const LINE_SENSORS: [usize; 4] = [2, 3, 4, 5];
+    /// Fewest sonar measurements before the failure rate is trusted.
+    const MIN_SONAR_ATTEMPTS: u64 = 20;
+    /// Default failures in a row after which the sonar is dead.
+    const SONAR_FAILURES: u32 = 10;
+    /// Default seconds before a digital sensor is stuck.
+    const STUCK_AFTER: f64 = 30.0;
+}
+
+impl Default for HealthMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts of [Sonar](crate::Sonar) measurement results.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SonarStats {
+    /// Measurements that succeeded.
+    pub successes: u64,
+    /// Measurements where no echo was heard.
+    pub no_echoes: u64,
+    /// Measurements where the echo was too near or far.
+    pub out_of_range: u64,
+    /// Measurements where the newest reading was stale.
+    pub stale: u64,
+    /// Measurements where the echo did not finish in time.
+    pub timeouts: u64,
+    /// Failures since the last success.
+    pub consecutive_failures: u32,
+    /// Latest failure.
+    pub last_error: Option<SonarError>,
+    /// Timestamp of the latest successful reading.
+    pub last_success: Option<Duration>,
+}
+
+impl SonarStats {
+    /// Total number of measurements.
+    pub fn attempts(&self) -> u64 {
+        self.successes + self.failures()
+    }
+    /// Total number of failed measurements.
+    pub fn failures(&self) -> u64 {
+        self.no_echoes + self.out_of_range + self.stale + self.timeouts
+    }
+    /// Share of measurements that failed from 0.0 to 1.0.
+    pub fn failure_rate(&self) -> f32 {
+        match self.attempts() {
+            0 => 0.0,
+            attempts => self.failures() as f32 / attempts as f32,
+        }
+    }
+    /// Counts a measurement result.
+    ///
+    /// ## Arguments
+    ///
+    /// * `result` - Result of a measurement.
+    pub(crate) fn record(&mut self, result: &Result<Reading, SonarError>) {
+        let error = match result {
+            Ok(reading) => {
+                self.successes += 1;
+                self.consecutive_failures = 0;
+                self.last_success = Some(reading.timestamp);
+                return;
+            }
+            Err(error) => *error,
+        };
+        match error {
+            SonarError::NoEcho => self.no_echoes += 1,
+            SonarError::OutOfRange(_) => self.out_of_range += 1,
+            SonarError::Stale(_) => self.stale += 1,
+            SonarError::Timeout => self.timeouts += 1,
+        }
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.last_error = Some(error);
+    }
+}
@@ -35,10 +35,16 @@
 // SOFTWARE.
 //! Contains all the human interactive components.
 
-use crate::{Result, Rr4cError, Rr4cResult};
+use crate::{status::StatusSet, Buzzer, Melody, Result, Rr4cError, Rr4cResult, Status};
 use rppal::gpio::{Gpio, IoPin, Level, Mode, OutputPin, PullUpDown};
-use std::thread::sleep;
-use std::time::Duration;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
 
 /// Proven easier access to audio, visual, and other forms of human interaction
 /// with the robot.
@@ -46,24 +52,47 @@ use std::time::Duration;
 pub struct Hids {
     /// Instance of [IoPin] connected to both the buzzer and the `key` button.
     ///
+    /// Shared with any melody being played in the background.
+    ///
     /// [IoPin]: rppal::gpio::IoPin
-    buzz_key: IoPin,
+    buzz_key: AmIoPin,
+    /// Kind of buzzer used when playing melodies.
+    buzzer: Buzzer,
+    /// Any melody currently being played in the background.
+    background: Option<Background>,
     /// Instance of [OutputPin] connected to the fan motor.
     ///
     /// [OutputPin]: rppal::gpio::OutputPin
     fan: OutputPin,
+    /// Optional full power spin-up kick used when starting the fan.
+    fan_kick: Option<Duration>,
+    /// Last non-zero fan speed used by [`toggle_fan()`] and [`blow()`].
+    ///
+    /// [`toggle_fan()`]: Hids::toggle_fan()
+    /// [`blow()`]: Hids::blow()
+    fan_level: u8,
+    /// Optional time used to ramp down the fan when slowing it.
+    fan_ramp_down: Option<Duration>,
+    /// Current fan speed as 0-100(%).
+    fan_speed: u8,
+    /// Any background thread watching for the `KEY` button being held down.
+    key_watcher: Option<KeyWatcher>,
+    /// Status currently being shown on the LEDs and buzzer.
+    shown: Option<Status>,
+    /// Set of active statuses.
+    statuses: StatusSet,
     /// Instance of [OutputPin] connected to red LEDs.
     ///
     /// [OutputPin]: rppal::gpio::OutputPin
-    led_r: OutputPin,
+    led_r: AmOutputPin,
     /// Instance of [OutputPin] connected to green LEDs.
     ///
     /// [OutputPin]: rppal::gpio::OutputPin
-    led_g: OutputPin,
+    led_g: AmOutputPin,
     /// Instance of [OutputPin] connected to blue LEDs.
     ///
     /// [OutputPin]: rppal::gpio::OutputPin
-    led_b: OutputPin,
+    led_b: AmOutputPin,
 }
 
 impl Hids {
@@ -82,15 +111,26 @@ impl Hids {
         led_g.set_low();
         led_b.set_low();
         Ok(Self {
-            buzz_key,
+            buzz_key: Arc::new(Mutex::new(buzz_key)),
+            buzzer: Buzzer::Active,
+            background: None,
             fan,
-            led_r,
-            led_g,
-            led_b,
+            fan_kick: None,
+            fan_level: 100,
+            fan_ramp_down: None,
+            fan_speed: 0,
+            key_watcher: None,
+            shown: None,
+            statuses: StatusSet::default(),
+            led_r: Arc::new(Mutex::new(led_r)),
+            led_g: Arc::new(Mutex::new(led_g)),
+            led_b: Arc::new(Mutex::new(led_b)),
         })
     }
     /// Used to sound buzzer.
     ///
+    /// Any status being shown goes back to its repeating part afterwards.
+    ///
     /// ## Arguments
     ///
     /// * `secs` - Optional number of seconds to sound buzzer.
@@ -100,44 +140,102 @@ impl Hids {
         let secs = secs.into().unwrap_or(0.1).abs().min(10.0).max(0.1);
         let dur = Duration::from_secs_f64(secs);
         let off = Duration::from_secs_f64(0.01);
-        self.buzz_key.set_mode(Mode::Output);
+        self.stop_playing();
+        let mut buzz_key = self.buzz_key.lock().expect("Someone broke the lock");
+        buzz_key.set_mode(Mode::Output);
         // Ensure not already on.
-        self.buzz_key.set_high();
+        buzz_key.set_high();
         sleep(off);
         // On
-        self.buzz_key.set_low();
+        buzz_key.set_low();
         sleep(dur);
         // Off
-        self.buzz_key.set_high();
+        buzz_key.set_high();
         sleep(off);
-        self.buzz_key.set_mode(Mode::Input);
+        buzz_key.set_mode(Mode::Input);
+        drop(buzz_key);
+        self.resume_status();
     }
     /// Turn on the fan motor to blow out flame.
     ///
+    /// The fan runs at the last non-zero speed given to [`set_fan_speed()`]
+    /// which is full speed by default.
+    ///
     /// ## Arguments
     ///
     /// * `secs` - Optional number of seconds to run fan.
-    /// Defaults to 2 seconds.
-    /// Internal limits to maximum of 60 seconds.
-    pub fn blow<S: Into<Option<f64>>>(&mut self, secs: S) {
+    ///   Defaults to 2 seconds.
+    ///   Internal limits to maximum of 60 seconds.
+    ///
+    /// [`set_fan_speed()`]: Hids::set_fan_speed()
+    pub fn blow<S: Into<Option<f64>>>(&mut self, secs: S) -> Result {
         let secs = secs.into().unwrap_or(2.0).abs().min(60.0);
         let wait = Duration::from_secs_f64(secs);
-        self.fan.set_low();
+        self.set_fan_speed(self.fan_level)?;
         sleep(wait);
-        self.fan.set_high();
+        self.set_fan_speed(0)?;
         sleep(Duration::from_millis(100));
+        Ok(())
+    }
+    /// Clears a status so it is no longer shown.
+    ///
+    /// The next highest priority active status is shown instead if there is
+    /// one.
+    ///
+    /// ## Arguments
+    ///
+    /// * `status` - The [Status](Status) to clear.
+    pub fn clear_status(&mut self, status: Status) {
+        self.statuses.clear(status);
+        self.show_status();
+    }
+    /// Access the current fan speed as 0-100(%).
+    pub fn fan_speed(&self) -> u8 {
+        self.fan_speed
+    }
+    /// Used to check if the `KEY` button on the robot is currently held down
+    /// without waiting for a press.
+    ///
+    /// The button has to read as down for a few milliseconds in a row to
+    /// filter out noise.
+    /// Always `false` while a melody is using the buzzer as the buzzer and
+    /// button share the same pin.
+    pub fn is_key_down(&self) -> bool {
+        let buzz_key = self.buzz_key.lock().expect("Someone broke the lock");
+        if buzz_key.mode() != Mode::Input {
+            return false;
+        }
+        let dur = Duration::from_millis(1);
+        (0..3).all(|_| {
+            let down = buzz_key.is_low();
+            sleep(dur);
+            down
+        })
+    }
+    /// Used to check if a melody or Morse code is still being played in the
+    /// background.
+    pub fn is_playing(&self) -> bool {
+        match &self.background {
+            Some(background) => !background.done.load(Ordering::Acquire),
+            None => false,
+        }
     }
     /// Waits for the `KEY` button on the robot to be pressed.
     ///
     /// Filters out noise/debounce the button press.
+    /// Anything being played is stopped first as the buzzer and button share
+    /// the same pin.
+    /// Any status being shown goes back to its repeating part afterwards.
     pub fn key_press(&mut self) {
         let mut state = Level::High;
         let mut history = 0b01010101u8;
-        self.buzz_key.set_mode(Mode::Input);
-        self.buzz_key.set_pullupdown(PullUpDown::PullUp);
+        self.stop_playing();
+        let mut buzz_key = self.buzz_key.lock().expect("Someone broke the lock");
+        buzz_key.set_mode(Mode::Input);
+        buzz_key.set_pullupdown(PullUpDown::PullUp);
         let dur = Duration::from_millis(3);
         while history != 255 {
-            let level = self.buzz_key.read();
+            let level = buzz_key.read();
             let changed = match level {
                 Level::Low => state == Level::High,
                 Level::High => state == Level::Low,
@@ -155,6 +253,8 @@ impl Hids {
             };
             sleep(dur);
         }
+        drop(buzz_key);
+        self.resume_status();
     }
     /// Used to set all three color LEDs at the same time.
     ///
@@ -180,6 +280,226 @@ impl Hids {
         self.set_green(green)?;
         self.set_blue(blue)
     }
+    /// Sends text as Morse code in a background thread.
+    ///
+    /// Anything already being played is stopped first.
+    /// Useful for reporting things like an IP address or an error code when
+    /// nothing else is attached to the robot.
+    /// See [`Melody::from_morse()`] for the characters that can be sent.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - Text to be sent.
+    /// * `wpm` - Optional speed in words per minute.
+    ///   Defaults to 15 words per minute.
+    /// * `output` - Where the Morse code should be sent.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018
+    /// # #[cfg(target_arch = "arm")]
+    /// # {
+    /// # extern crate rust_rpi_4wd_car;
+    /// use rust_rpi_4wd_car::{Hids, MorseOutput, Result};
+    ///
+    /// fn main() -> Result {
+    ///     let mut hids = Hids::new()?;
+    ///     hids.morse("SOS", 12, MorseOutput::Both);
+    ///     hids.wait_playing();
+    ///     Ok(())
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// [`Melody::from_morse()`]: Melody::from_morse()
+    pub fn morse<'a, T, W>(&mut self, text: T, wpm: W, output: MorseOutput)
+    where
+        T: Into<&'a str>,
+        W: Into<Option<u8>>,
+    {
+        let melody = Melody::from_morse(text, wpm);
+        let sound = output != MorseOutput::Lights;
+        let color = if output == MorseOutput::Buzzer {
+            None
+        } else {
+            Some((100, 100, 100))
+        };
+        let repeat = Self::status_repeat(self.shown);
+        self.spawn_player(melody, sound, color, repeat);
+    }
+    /// Plays the intro of a status pattern once without making it active.
+    ///
+    /// Blocks until the intro has finished then goes back to showing the
+    /// highest priority active status.
+    ///
+    /// ## Arguments
+    ///
+    /// * `status` - The [Status](Status) to signal.
+    pub fn notify(&mut self, status: Status) {
+        let pattern = status.pattern();
+        self.spawn_player(pattern.intro, pattern.sound, Some(pattern.color), None);
+        self.wait_playing();
+        self.resume_status();
+    }
+    /// Calls `callback` from a background thread each time the `KEY` button is
+    /// held down long enough.
+    ///
+    /// The callback is only called once per press no matter how long the
+    /// button is held.
+    /// Presses are missed while a melody is using the buzzer or
+    /// [`key_press()`] is waiting as the buzzer and button share the same pin.
+    /// A button already held keeps counting while something else briefly
+    /// holds the pin.
+    /// Any earlier watcher is stopped and replaced.
+    ///
+    /// ## Arguments
+    ///
+    /// * `secs` - Optional number of seconds the button has to be held.
+    ///   Defaults to 2 seconds.
+    ///   Internally limited between 0.5 and 10 secs.
+    /// * `callback` - Called each time a long press is seen.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018, no_run
+    /// # #[cfg(target_arch = "arm")]
+    /// # {
+    /// use rust_rpi_4wd_car::{Hids, Result};
+    ///
+    /// fn main() -> Result {
+    ///     let mut hids = Hids::new()?;
+    ///     hids.on_key_hold(None, || println!("KEY was held"));
+    ///     hids.key_press();
+    ///     Ok(())
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// [`key_press()`]: Hids::key_press()
+    pub fn on_key_hold<S, F>(&mut self, secs: S, mut callback: F)
+    where
+        S: Into<Option<f64>>,
+        F: FnMut() + Send + 'static,
+    {
+        // Stop any earlier watcher first.
+        self.key_watcher = None;
+        let hold = Duration::from_secs_f64(secs.into().unwrap_or(2.0).abs().clamp(0.5, 10.0));
+        let buzz_key = self.buzz_key.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || {
+            let mut key_hold = KeyHold::new(hold);
+            while !thread_stop.load(Ordering::Acquire) {
+                // Never wait on a melody or key_press() holding the pin.
+                let down = buzz_key
+                    .try_lock()
+                    .ok()
+                    .map(|pin| pin.mode() == Mode::Input && pin.is_low());
+                if key_hold.update(down, Instant::now()) {
+                    callback();
+                }
+                thread::park_timeout(Self::KEY_POLL);
+            }
+        });
+        self.key_watcher = Some(KeyWatcher {
+            handle: Some(handle),
+            stop,
+        });
+    }
+    /// Plays a melody on the buzzer in a background thread.
+    ///
+    /// Anything already being played is stopped first.
+    /// Any status being shown goes back to its repeating part once the melody
+    /// has finished.
+    ///
+    /// ## Arguments
+    ///
+    /// * `melody` - The [Melody](Melody) to play.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018
+    /// # #[cfg(target_arch = "arm")]
+    /// # {
+    /// # extern crate rust_rpi_4wd_car;
+    /// use rust_rpi_4wd_car::{Hids, Melody, Result};
+    ///
+    /// fn main() -> Result {
+    ///     let mut hids = Hids::new()?;
+    ///     hids.play(Melody::startup());
+    ///     hids.wait_playing();
+    ///     Ok(())
+    /// }
+    /// # }
+    /// ```
+    ///
+    pub fn play(&mut self, melody: Melody) {
+        let repeat = Self::status_repeat(self.shown);
+        self.spawn_player(melody, true, None, repeat);
+    }
+    /// Parses and plays a ring tone text transfer language (RTTTL) string in a
+    /// background thread.
+    ///
+    /// See [`Melody::from_rtttl()`] and [`play()`] for more info.
+    ///
+    /// ## Arguments
+    ///
+    /// * `rtttl` - RTTTL string like `"beep:d=4,o=5,b=120:c,p,8e,8g."`.
+    ///
+    /// [`Melody::from_rtttl()`]: Melody::from_rtttl()
+    /// [`play()`]: Hids::play()
+    pub fn play_rtttl<'a, R: Into<&'a str>>(&mut self, rtttl: R) -> Result {
+        let melody = Melody::from_rtttl(rtttl)?;
+        self.play(melody);
+        Ok(())
+    }
+    /// Sets the kind of buzzer melodies are played on.
+    ///
+    /// The stock buzzer is [`Buzzer::Active`] which can only play the rhythm
+    /// of a melody.
+    ///
+    /// ## Arguments
+    ///
+    /// * `buzzer` - Kind of buzzer connected to the buzzer pin.
+    ///
+    /// [`Buzzer::Active`]: Buzzer::Active
+    pub fn set_buzzer(&mut self, buzzer: Buzzer) {
+        self.buzzer = buzzer;
+    }
+    /// Sets a status as active.
+    ///
+    /// Its [Pattern](crate::Pattern) is shown when it is the highest priority
+    /// active status and replaces anything already being played.
+    /// While a pattern with a repeating part is shown it controls the LEDs.
+    ///
+    /// ## Arguments
+    ///
+    /// * `status` - The [Status](Status) to set.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018
+    /// # #[cfg(target_arch = "arm")]
+    /// # {
+    /// # extern crate rust_rpi_4wd_car;
+    /// use rust_rpi_4wd_car::{Hids, Result, Status};
+    ///
+    /// fn main() -> Result {
+    ///     let mut hids = Hids::new()?;
+    ///     hids.set_status(Status::Booting);
+    ///     hids.set_status(Status::Fault);
+    ///     // Still shows the fault.
+    ///     hids.clear_status(Status::Booting);
+    ///     assert_eq!(hids.status(), Some(Status::Fault));
+    ///     Ok(())
+    /// }
+    /// # }
+    /// ```
+    ///
+    pub fn set_status(&mut self, status: Status) {
+        self.statuses.set(status);
+        self.show_status();
+    }
     /// Sets to brightness of the blue LEDs.
     ///
     /// ## Arguments
@@ -211,14 +531,72 @@ impl Hids {
     ///
     pub fn set_blue<C: Into<Option<u8>>>(&mut self, brightness: C) -> Result {
         let brightness = brightness.into().unwrap_or(50).min(100);
-        if brightness != 0 {
-            let dc = brightness as f64 * 0.01f64;
-            self.led_b
-                .set_pwm_frequency(Self::FREQUENCY, dc)
-                .map_err(Rr4cError::Gpio)
-        } else {
-            self.led_b.clear_pwm().map_err(Rr4cError::Gpio)
+        Self::led_output(&self.led_b, brightness)
+    }
+    /// Sets an optional full power spin-up kick for the fan.
+    ///
+    /// Small DC fans will often stall when started at a low PWM speed so a
+    /// short burst at full power is used to get them turning first.
+    ///
+    /// ## Arguments
+    ///
+    /// * `secs` - Optional number of seconds to run the fan at full power
+    ///   whenever it is started from a stop.
+    ///   A `None` value disables the kick.
+    ///   Internally limited to a maximum of 2 seconds.
+    pub fn set_fan_kick<S: Into<Option<f64>>>(&mut self, secs: S) {
+        self.fan_kick = secs
+            .into()
+            .map(|secs| Duration::from_secs_f64(secs.abs().min(2.0)));
+    }
+    /// Sets an optional ramp down time used when slowing the fan.
+    ///
+    /// ## Arguments
+    ///
+    /// * `secs` - Optional number of seconds to take when changing to any
+    ///   slower fan speed.
+    ///   A `None` value changes speed immediately.
+    ///   Internally limited to a maximum of 10 seconds.
+    pub fn set_fan_ramp_down<S: Into<Option<f64>>>(&mut self, secs: S) {
+        self.fan_ramp_down = secs
+            .into()
+            .map(|secs| Duration::from_secs_f64(secs.abs().min(10.0)));
+    }
+    /// Sets the speed of the fan motor.
+    ///
+    /// Uses any kick or ramp down that has been set with [`set_fan_kick()`] or
+    /// [`set_fan_ramp_down()`].
+    ///
+    /// ## Arguments
+    ///
+    /// * `speed` - How fast the fan should run. 0-100(%) range with 100%
+    ///   default if `None` is used.
+    ///
+    /// [`set_fan_kick()`]: Hids::set_fan_kick()
+    /// [`set_fan_ramp_down()`]: Hids::set_fan_ramp_down()
+    pub fn set_fan_speed<S: Into<Option<u8>>>(&mut self, speed: S) -> Result {
+        let speed = speed.into().unwrap_or(100).min(100);
+        if speed > self.fan_speed {
+            if let (0, Some(kick)) = (self.fan_speed, self.fan_kick) {
+                self.fan_output(100)?;
+                sleep(kick);
+            }
+        } else if let Some(ramp) = self.fan_ramp_down {
+            let steps = (self.fan_speed - speed) as u32;
+            if steps > 0 {
+                let step = ramp / steps;
+                for current in (speed + 1..self.fan_speed).rev() {
+                    self.fan_output(current)?;
+                    sleep(step);
+                }
+                sleep(step);
+            }
+        }
+        if speed != 0 {
+            self.fan_level = speed;
         }
+        self.fan_speed = speed;
+        self.fan_output(speed)
     }
     /// Set the LEDs to show a color from a preset list of primary and secondary
     /// colors.
@@ -265,14 +643,7 @@ impl Hids {
     ///
     pub fn set_green<C: Into<Option<u8>>>(&mut self, value: C) -> Result {
         let value = value.into().unwrap_or(50).min(100);
-        if value != 0 {
-            let dc = value as f64 * 0.01f64;
-            self.led_g
-                .set_pwm_frequency(Self::FREQUENCY, dc)
-                .map_err(Rr4cError::Gpio)
-        } else {
-            self.led_g.clear_pwm().map_err(Rr4cError::Gpio)
-        }
+        Self::led_output(&self.led_g, value)
     }
     /// Sets to brightness of the red LEDs.
     ///
@@ -305,28 +676,188 @@ impl Hids {
     ///
     pub fn set_red<C: Into<Option<u8>>>(&mut self, value: C) -> Result {
         let value = value.into().unwrap_or(50).min(100);
-        if value != 0 {
-            let dc = value as f64 * 0.01f64;
-            self.led_r
-                .set_pwm_frequency(Self::FREQUENCY, dc)
-                .map_err(Rr4cError::Gpio)
-        } else {
-            self.led_r.clear_pwm().map_err(Rr4cError::Gpio)
+        Self::led_output(&self.led_r, value)
+    }
+    /// Finds the light only repeating part of a status pattern and its color.
+    ///
+    /// ## Arguments
+    ///
+    /// * `status` - Optional [Status](Status) being shown.
+    pub(crate) fn status_repeat(status: Option<Status>) -> Option<(Melody, (u8, u8, u8))> {
+        let pattern = status?.pattern();
+        Some((pattern.repeat?, pattern.color))
+    }
+    /// Access the highest priority active status if any.
+    pub fn status(&self) -> Option<Status> {
+        self.statuses.highest()
+    }
+    /// Stops any melody or Morse code being played in the background.
+    pub fn stop_playing(&mut self) {
+        if let Some(background) = self.background.take() {
+            background.stop.store(true, Ordering::Release);
+            background.handle.thread().unpark();
+            background.handle.join().ok();
         }
     }
     /// Toggle the fan on/off.
+    ///
+    /// When turned on the fan will use the last non-zero speed it was set to.
     pub fn toggle_fan(&mut self) -> Result {
-        self.fan.toggle();
-        Ok(())
+        if self.fan_speed == 0 {
+            self.set_fan_speed(self.fan_level)
+        } else {
+            self.set_fan_speed(0)
+        }
     }
     /// A short bleep from the buzzer.
     pub fn whistle(&mut self) {
         self.beep(None);
     }
+    /// Waits for any melody or Morse code being played in the background to
+    /// finish.
+    ///
+    /// Doesn't wait for the repeating part of any status being shown.
+    pub fn wait_playing(&mut self) {
+        while self.is_playing() {
+            sleep(Self::PLAY_POLL);
+        }
+    }
+    /// Drives the fan pin for the given speed.
+    ///
+    /// The fan is active low so the PWM duty cycle is inverted.
+    ///
+    /// ## Arguments
+    ///
+    /// * `speed` - Fan speed as 0-100(%).
+    fn fan_output(&mut self, speed: u8) -> Result {
+        match speed {
+            0 => {
+                self.fan.clear_pwm()?;
+                self.fan.set_high();
+            }
+            100 => {
+                self.fan.clear_pwm()?;
+                self.fan.set_low();
+            }
+            _ => {
+                let dc = 1.0 - speed as f64 * 0.01f64;
+                self.fan.set_pwm_frequency(Self::FAN_FREQUENCY, dc)?;
+            }
+        }
+        Ok(())
+    }
+    /// Sets the brightness of one color of LEDs.
+    ///
+    /// ## Arguments
+    ///
+    /// * `led` - Shared LED pin to be set.
+    /// * `brightness` - Brightness as 0-100(%).
+    fn led_output(led: &AmOutputPin, brightness: u8) -> Result {
+        let mut led = led.lock().expect("Someone broke the lock");
+        if brightness != 0 {
+            let dc = brightness.min(100) as f64 * 0.01f64;
+            led.set_pwm_frequency(Self::FREQUENCY, dc)
+                .map_err(Rr4cError::Gpio)
+        } else {
+            led.clear_pwm().map_err(Rr4cError::Gpio)
+        }
+    }
+    /// Goes back to the repeating part of any status being shown after
+    /// something else used the LEDs or buzzer.
+    fn resume_status(&mut self) {
+        if let Some(repeat) = Self::status_repeat(self.shown) {
+            self.spawn_player(
+                Melody::new(repeat.0.name(), Vec::new()),
+                false,
+                None,
+                Some(repeat),
+            );
+        }
+    }
+    /// Shows the pattern of the highest priority active status when it has
+    /// changed.
+    fn show_status(&mut self) {
+        let status = self.status();
+        if status == self.shown {
+            return;
+        }
+        self.shown = status;
+        match status {
+            Some(status) => {
+                let pattern = status.pattern();
+                let repeat = Self::status_repeat(Some(status));
+                self.spawn_player(pattern.intro, pattern.sound, Some(pattern.color), repeat);
+            }
+            None => self.stop_playing(),
+        }
+    }
+    /// Plays a melody in a background thread.
+    ///
+    /// Anything already being played is stopped first.
+    ///
+    /// ## Arguments
+    ///
+    /// * `melody` - The [Melody](Melody) to play.
+    /// * `sound` - Plays the melody on the buzzer when `true`.
+    /// * `color` - Optional RGB brightnesses used to flash the LEDs with each
+    ///   note.
+    /// * `repeat` - Optional light only melody and its color repeated after
+    ///   `melody` until stopped.
+    fn spawn_player(
+        &mut self,
+        melody: Melody,
+        sound: bool,
+        color: Option<(u8, u8, u8)>,
+        repeat: Option<(Melody, (u8, u8, u8))>,
+    ) {
+        self.stop_playing();
+        let done = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let finished = done.clone();
+        let leds = [self.led_r.clone(), self.led_g.clone(), self.led_b.clone()];
+        let player = Player {
+            buzzer: if sound {
+                Some((self.buzz_key.clone(), self.buzzer))
+            } else {
+                None
+            },
+            lights: color.map(|color| (leds.clone(), color)),
+            stop: stop.clone(),
+        };
+        let repeat = repeat
+            .filter(|(r, _)| r.total_duration() > Duration::ZERO)
+            .map(|(melody, color)| {
+                let player = Player {
+                    buzzer: None,
+                    lights: Some((leds, color)),
+                    stop: stop.clone(),
+                };
+                (melody, player)
+            });
+        let handle = thread::spawn(move || {
+            let played = player.run(&melody, true);
+            // The melody is done even if a status keeps repeating after it.
+            finished.store(true, Ordering::Release);
+            if let Some((melody, player)) = repeat.filter(|_| played) {
+                while player.run(&melody, false) {}
+            }
+        });
+        self.background = Some(Background { done, handle, stop });
+    }
     /// The combine buzzer and `KEY` button pin #.
     const BUZZ_KEY: u8 = 8;
     /// The fan pin #.
     const FAN: u8 = 2;
+    /// Frequency use for fan PWM in Hz.
+    const FAN_FREQUENCY: f64 = 100.0;
+    /// How often the `KEY` button is checked by [`on_key_hold()`].
+    ///
+    /// [`on_key_hold()`]: Hids::on_key_hold()
+    const KEY_POLL: Duration = Duration::from_millis(20);
+    /// How often [`wait_playing()`] checks if playing has finished.
+    ///
+    /// [`wait_playing()`]: Hids::wait_playing()
+    const PLAY_POLL: Duration = Duration::from_millis(5);
     /// An array of RGB tuples of LED brightnesses as percentages from 0-100% to
     /// form black(Off), white(On) plus each of the 3 primary and secondary
     /// colors.
@@ -349,3 +880,208 @@ impl Hids {
     /// Frequency use for LED PWM in Hz.
     const FREQUENCY: f64 = 300.0;
 }
+
+/// Where Morse code should be sent by [`Hids::morse()`].
+///
+/// [`Hids::morse()`]: Hids::morse()
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MorseOutput {
+    /// Sound the buzzer only.
+    Buzzer,
+    /// Flash the LEDs white only.
+    Lights,
+    /// Sound the buzzer and flash the LEDs together.
+    Both,
+}
+
+/// Handle to a melody being played in a background thread.
+#[derive(Debug)]
+struct Background {
+    /// Set by the thread once it has finished.
+    done: Arc<AtomicBool>,
+    /// Handle of the thread.
+    handle: JoinHandle<()>,
+    /// Used to ask the thread to stop early.
+    stop: Arc<AtomicBool>,
+}
+
+/// Handle to the background thread watching for long `KEY` button presses.
+#[derive(Debug)]
+struct KeyWatcher {
+    /// Handle of the thread.
+    handle: Option<JoinHandle<()>>,
+    /// Used to ask the thread to stop.
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for KeyWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            // The callback could drop the last owner of the Hids.
+            if handle.thread().id() == thread::current().id() {
+                return;
+            }
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Tracks how long the `KEY` button has been held for
+/// [`Hids::on_key_hold()`].
+///
+/// [`Hids::on_key_hold()`]: Hids::on_key_hold()
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct KeyHold {
+    /// Button was down at the last reading.
+    down: bool,
+    /// Callback has already been fired for this press.
+    fired: bool,
+    /// How long the button has to be held.
+    hold: Duration,
+    /// When the button was first seen down in this press.
+    since: Option<Instant>,
+}
+
+impl KeyHold {
+    /// Constructor.
+    ///
+    /// ## Arguments
+    ///
+    /// * `hold` - How long the button has to be held.
+    pub(crate) fn new(hold: Duration) -> Self {
+        Self {
+            down: false,
+            fired: false,
+            hold,
+            since: None,
+        }
+    }
+    /// Updates with a new reading of the button.
+    ///
+    /// Returns `true` only once per press when it has been held long enough.
+    ///
+    /// ## Arguments
+    ///
+    /// * `down` - Button is down or `None` when the pin couldn't be read
+    ///   because something else was holding its lock.
+    ///   The last reading is kept so a busy pin doesn't end the press.
+    /// * `now` - When the reading was taken.
+    pub(crate) fn update(&mut self, down: Option<bool>, now: Instant) -> bool {
+        self.down = down.unwrap_or(self.down);
+        if !self.down {
+            self.since = None;
+            self.fired = false;
+            return false;
+        }
+        let start = *self.since.get_or_insert(now);
+        if self.fired || now.duration_since(start) < self.hold {
+            return false;
+        }
+        self.fired = true;
+        true
+    }
+}
+
+/// Outputs used by a melody being played in a background thread.
+struct Player {
+    /// Shared buzzer pin and kind of buzzer when the melody is heard.
+    buzzer: Option<(AmIoPin, Buzzer)>,
+    /// Shared red, green, and blue LED pins and the color to use when the
+    /// melody is seen.
+    lights: Option<([AmOutputPin; 3], (u8, u8, u8))>,
+    /// Used to stop playing early.
+    stop: Arc<AtomicBool>,
+}
+
+impl Player {
+    /// Plays all the notes of a melody unless stopped early.
+    ///
+    /// Returns `false` if stopped before the melody finished.
+    ///
+    /// ## Arguments
+    ///
+    /// * `melody` - The [Melody](Melody) to play.
+    /// * `sound` - Plays the melody on the buzzer as well when `true`.
+    fn run(&self, melody: &Melody, sound: bool) -> bool {
+        let mut buzz_key = self
+            .buzzer
+            .as_ref()
+            .filter(|_| sound)
+            .map(|(pin, buzzer)| (pin.lock().expect("Someone broke the lock"), *buzzer));
+        let mut played = true;
+        if let Some((pin, _)) = buzz_key.as_mut() {
+            pin.set_mode(Mode::Output);
+            pin.set_high();
+        }
+        for note in melody.notes() {
+            // Leave a short gap at the end of each note so repeated notes can
+            // be heard as separate notes.
+            let gap = (note.duration / 10).min(Duration::from_millis(30));
+            if !note.is_rest() {
+                if let Some((pin, buzzer)) = buzz_key.as_mut() {
+                    match buzzer {
+                        Buzzer::Active => pin.set_low(),
+                        Buzzer::Passive => {
+                            pin.set_pwm_frequency(note.frequency, 0.5).ok();
+                        }
+                    }
+                }
+                self.lights(true);
+            }
+            played = self.pause(note.duration - gap);
+            if !note.is_rest() {
+                if let Some((pin, _)) = buzz_key.as_mut() {
+                    pin.clear_pwm().ok();
+                    pin.set_high();
+                }
+                self.lights(false);
+            }
+            played = played && self.pause(gap);
+            if !played {
+                break;
+            }
+        }
+        if let Some((pin, _)) = buzz_key.as_mut() {
+            pin.set_high();
+            pin.set_mode(Mode::Input);
+        }
+        played && !self.stop.load(Ordering::Acquire)
+    }
+    /// Turns the LEDs on or off when the melody is seen.
+    ///
+    /// ## Arguments
+    ///
+    /// * `on` - Turns the LEDs on with the player's color when `true`.
+    fn lights(&self, on: bool) {
+        if let Some((leds, (red, green, blue))) = &self.lights {
+            for (led, brightness) in leds.iter().zip([*red, *green, *blue].iter()) {
+                Hids::led_output(led, if on { *brightness } else { 0 }).ok();
+            }
+        }
+    }
+    /// Sleeps for the given time unless asked to stop.
+    ///
+    /// Returns `false` if stopped before the time had passed.
+    ///
+    /// ## Arguments
+    ///
+    /// * `dur` - How long to sleep.
+    fn pause(&self, dur: Duration) -> bool {
+        let deadline = Instant::now() + dur;
+        while !self.stop.load(Ordering::Acquire) {
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::park_timeout(deadline - now);
+        }
+        false
+    }
+}
+
+/// An `Arc` `Mutex` wrapper type for the shared buzzer and `KEY` button pin.
+type AmIoPin = Arc<Mutex<IoPin>>;
+/// An `Arc` `Mutex` wrapper type for shared LED pins.
+type AmOutputPin = Arc<Mutex<OutputPin>>;
@@ -37,25 +37,49 @@
 //! Library that can be used to control the [Yahboom 4WD smart robot] for the
 //! Raspberry Pi 4B
 //!
-//! Include modules for all of the [hids], motors, sensors, and servos
+//! Include modules for all of the [hids], motors, sensors, servos, and sounds
 //!
 //! [hids]: https://en.wikipedia.org/wiki/Human_interface_device
 //! [Yahboom 4WD smart robot]: https://category.yahboom.net/products/4wdrobot
 
 pub use crate::{
     command::Decoder,
-    error::{Result, Rr4cError, Rr4cResult},
-    hids::Hids,
+    error::{Result, Rr4cError, Rr4cResult, SonarError},
+    estop::EmergencyStop,
+    health::{DigitalHealth, HealthMonitor, HealthReport, HealthWarning, SonarStats},
+    hids::{Hids, MorseOutput},
+    line::{LinePosition, LineSide, LineState},
+    modes::{
+        AvoidState, IrFollower, LightSeeker, LightShow, LineFollower, Mode, ObstacleAvoider,
+        SeekState,
+    },
     motors::Motors,
-    sensors::Sensors,
+    obstacle::{Obstacle, ObstacleDetector},
+    range::{RangeEstimate, RangeTracker},
+    sensors::{
+        DigitalFilter, DigitalSensor, EnvironmentSource, FixedEnvironment, Polarity, Reading,
+        SensorEvent, SensorPolarity, SensorSnapshot, Sensors, Sonar, SonarFilter,
+    },
     servos::Servos,
+    sound::{Buzzer, Melody, Note},
+    status::{Pattern, Status},
+    supervisor::{ExitReason, Robot, Supervisor},
 };
 
 mod command;
 mod error;
+mod estop;
+mod health;
 mod hids;
+mod line;
+mod modes;
 mod motors;
+mod obstacle;
+mod range;
 mod sensors;
 mod servos;
+mod sound;
+mod status;
+mod supervisor;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,197 @@
+// Copyright © 2021-present, Michael Cummings
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// MIT License
+//
+// Copyright © 2021-present, Michael Cummings <mgcummings@yahoo.com>.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! Contains the line position estimator used to turn the four channel line
+//! tracker into a single steering input.
+
+/// Side of the robot the line was last seen on.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum LineSide {
+    /// Line was to the left of center.
+    Left,
+    /// Line was under the center of the robot.
+    #[default]
+    Center,
+    /// Line was to the right of center.
+    Right,
+}
+
+/// What the line tracker is seeing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LineState {
+    /// Following a single line.
+    Following,
+    /// All of the sensors see line so the robot is on top of a junction.
+    ///
+    /// Becomes an [Intersection](LineState::Intersection) or
+    /// [TJunction](LineState::TJunction) once the robot has passed over it.
+    Junction,
+    /// Passed over a junction and the line carried on beyond it.
+    ///
+    /// Only reported on the first update after leaving the junction.
+    Intersection,
+    /// A T shaped junction.
+    ///
+    /// The side is where a branch leaves the line being followed or
+    /// [Center](LineSide::Center) when the line ended at a cross bar.
+    /// A centered T-junction is only reported on the first update after
+    /// leaving it.
+    TJunction(LineSide),
+    /// None of the sensors see the line.
+    #[default]
+    Lost,
+}
+
+/// Estimates where the line is from the four channel line tracker.
+///
+/// The estimate is a signed error from -1.0 when the line is under the far
+/// left sensor to 1.0 when it is under the far right sensor with 0.0 being
+/// centered.
+/// When the line is lost the error is held at the side it was last seen on.
+///
+/// ## Examples
+///
+/// ```edition2018, no_run
+/// # #[cfg(target_arch = "arm")]
+/// # {
+/// use rust_rpi_4wd_car::{LinePosition, LineState, Result, Sensors};
+///
+/// fn main() -> Result {
+///     let sensors = Sensors::new()?;
+///     let mut position = LinePosition::new();
+///     let (error, state) = position.update(sensors.line_tracking());
+///     if state == LineState::Following {
+///         println!("Steer by {}", -error);
+///     }
+///     Ok(())
+/// }
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinePosition {
+    /// Latest error.
+    error: f32,
+    /// Used to track if the robot is on top of a junction.
+    junction: bool,
+    /// Side the line was last seen on.
+    last_side: LineSide,
+    /// Latest state.
+    state: LineState,
+}
+
+impl LinePosition {
+    /// Constructor
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Access the latest error from -1.0 (left) to 1.0 (right).
+    pub fn error(&self) -> f32 {
+        self.error
+    }
+    /// Access the side the line was last seen on.
+    pub fn last_side(&self) -> LineSide {
+        self.last_side
+    }
+    /// Access the latest [LineState](LineState).
+    pub fn state(&self) -> LineState {
+        self.state
+    }
+    /// Updates the estimate from new line tracking sensors data.
+    ///
+    /// Returns the error and [LineState](LineState).
+    ///
+    /// ## Arguments
+    ///
+    /// * `tracking` - Line tracking sensors data in the same left to right
+    ///   order as returned from [`Sensors::line_tracking()`].
+    ///
+    /// [`Sensors::line_tracking()`]: crate::Sensors::line_tracking()
+    pub fn update(&mut self, tracking: (bool, bool, bool, bool)) -> (f32, LineState) {
+        let (left1, left2, right1, right2) = tracking;
+        let seen = [left1, left2, right1, right2];
+        let count = seen.iter().filter(|s| **s).count();
+        let (error, state) = match count {
+            0 => {
+                let error = match self.last_side {
+                    LineSide::Left => -1.0,
+                    LineSide::Center => 0.0,
+                    LineSide::Right => 1.0,
+                };
+                if self.junction {
+                    self.junction = false;
+                    (error, LineState::TJunction(LineSide::Center))
+                } else {
+                    (error, LineState::Lost)
+                }
+            }
+            3 if !right2 => (0.0, LineState::TJunction(LineSide::Left)),
+            3 if !left1 => (0.0, LineState::TJunction(LineSide::Right)),
+            // Both outer sensors see the line so it crosses the path.
+            3 | 4 => {
+                self.junction = true;
+                (0.0, LineState::Junction)
+            }
+            _ => {
+                let sum: f32 = seen
+                    .iter()
+                    .zip(&Self::WEIGHTS)
+                    .filter(|(s, _)| **s)
+                    .map(|(_, w)| w)
+                    .sum();
+                let error = sum / count as f32;
+                self.last_side = if error < -Self::CENTER_BAND {
+                    LineSide::Left
+                } else if error > Self::CENTER_BAND {
+                    LineSide::Right
+                } else {
+                    LineSide::Center
+                };
+                if self.junction {
+                    self.junction = false;
+                    (error, LineState::Intersection)
+                } else {
+                    (error, LineState::Following)
+                }
+            }
+        };
+        self.error = error;
+        self.state = state;
+        (error, state)
+    }
+    /// Errors closer to 0.0 than this count as the line being centered.
+    const CENTER_BAND: f32 = 0.1;
+    /// Error for each sensor from left to right.
+    const WEIGHTS: [f32; 4] = [-1.0, -1.0 / 3.0, 1.0 / 3.0, 1.0];
+}
@@ -0,0 +1,1156 @@
+// Copyright © 2021-present, Michael Cummings
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// MIT License
+//
+// Copyright © 2021-present, Michael Cummings <mgcummings@yahoo.com>.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! Contains the [Mode](Mode) trait and the controllers behind the built-in
+//! autonomous robot modes.
+//!
+//! Each controller is fed sensor data one step at a time and returns the
+//! motor speeds to use so it can be driven by the hardware or by a simulated
+//! sensor feed.
+
+use crate::{
+    ExitReason, LinePosition, LineSide, LineState, Obstacle, ObstacleDetector, Result, Robot,
+    Rr4cResult,
+};
+use std::time::{Duration, Instant};
+
+/// A behavior that can be run in the background by a
+/// [Supervisor](crate::Supervisor).
+///
+/// Custom modes are added to a [Decoder](crate::Decoder) with
+/// [`Decoder::register_mode()`] so they can be selected the same way as the
+/// built-in ones.
+/// All of the methods are called from the supervisor thread with the target,
+/// which is normally the shared [Robot](Robot), locked.
+///
+/// ## Examples
+///
+/// ```edition2018, no_run
+/// # #[cfg(target_arch = "arm")]
+/// # {
+/// use rust_rpi_4wd_car::{Decoder, Mode, Result, Robot, Rr4cResult};
+/// use std::time::{Duration, Instant};
+///
+/// /// Spins in place for a few seconds.
+/// struct Spin {
+///     speed: i8,
+///     until: Option<Instant>,
+/// }
+///
+/// impl Mode for Spin {
+///     fn step(&mut self, robot: &mut Robot, now: Instant) -> Rr4cResult<bool> {
+///         let until = *self.until.get_or_insert(now + Duration::from_secs(3));
+///         robot.motors.movement(self.speed, -self.speed)?;
+///         Ok(now < until)
+///     }
+/// }
+///
+/// fn main() -> Result {
+///     let mut decoder = Decoder::new()?;
+///     decoder.register_mode("Spin", "71", None, |speed| {
+///         Box::new(Spin { speed, until: None })
+///     })?;
+///     decoder.yb_decode("$4WD,MODE71#")
+/// }
+/// # }
+/// ```
+///
+/// [`Decoder::register_mode()`]: crate::Decoder::register_mode()
+pub trait Mode<T = Robot>: Send {
+    /// Called once before the first step.
+    ///
+    /// The mode exits as failed if an error is returned.
+    ///
+    /// ## Arguments
+    ///
+    /// * `target` - What the mode drives.
+    fn start(&mut self, target: &mut T) -> Result {
+        let _ = target;
+        Ok(())
+    }
+    /// Called for each step of the mode.
+    ///
+    /// Returns `true` to keep running or `false` when the mode has finished.
+    /// The mode exits as failed if an error is returned.
+    ///
+    /// ## Arguments
+    ///
+    /// * `target` - What the mode drives.
+    /// * `now` - Time of the step.
+    fn step(&mut self, target: &mut T, now: Instant) -> Rr4cResult<bool>;
+    /// Called once after the mode exits for any reason to clean up.
+    ///
+    /// ## Arguments
+    ///
+    /// * `target` - What the mode drives.
+    /// * `reason` - Why the mode exited.
+    fn stop(&mut self, target: &mut T, reason: &ExitReason) -> Result {
+        let _ = (target, reason);
+        Ok(())
+    }
+}
+
+impl<T, M: Mode<T> + ?Sized> Mode<T> for Box<M> {
+    fn start(&mut self, target: &mut T) -> Result {
+        (**self).start(target)
+    }
+    fn step(&mut self, target: &mut T, now: Instant) -> Rr4cResult<bool> {
+        (**self).step(target, now)
+    }
+    fn stop(&mut self, target: &mut T, reason: &ExitReason) -> Result {
+        (**self).stop(target, reason)
+    }
+}
+
+/// What an [ObstacleAvoider](ObstacleAvoider) is currently doing.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum AvoidState {
+    /// Driving forward slowing down as obstacles get closer.
+    #[default]
+    Cruising,
+    /// Stopped and looking left then right with the front servo.
+    Scanning,
+    /// Turning toward the clearest direction found by a scan.
+    Turning,
+    /// Backing out of a dead end.
+    Reversing,
+}
+
+/// What a [LightSeeker](LightSeeker) is currently doing.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum SeekState {
+    /// Spinning to look for light.
+    #[default]
+    Searching,
+    /// Steering toward light seen by one or both sensors.
+    Steering,
+    /// Reached the light source.
+    Arrived,
+}
+
+/// Line following controller used by the line tracking mode.
+///
+/// Steers using a proportional-derivative (PD) controller on the error from a
+/// [LinePosition](LinePosition).
+/// Sharp corners show up as the line leaving under one of the outer sensors
+/// so the robot pivots toward the side the line or a branch was last seen on.
+/// When the line is lost with no side to go on it creeps forward a little to
+/// cross any gap then sweeps left and right in growing arcs.
+///
+/// ## Examples
+///
+/// ```edition2018, no_run
+/// # #[cfg(target_arch = "arm")]
+/// # {
+/// use rust_rpi_4wd_car::{LineFollower, Motors, Result, Sensors};
+/// use std::{thread::sleep, time::{Duration, Instant}};
+///
+/// fn main() -> Result {
+///     let sensors = Sensors::new()?;
+///     let mut motors = Motors::new()?;
+///     let mut follower = LineFollower::new_with_kitchen_sink(40, 1.2, None, None);
+///     while let Some((left, right)) = follower.step(sensors.line_tracking(), Instant::now()) {
+///         motors.movement(left, right)?;
+///         sleep(Duration::from_millis(10));
+///     }
+///     motors.brake()
+/// }
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineFollower {
+    /// Side of the last branch seen since the line was followed normally.
+    corner: Option<LineSide>,
+    /// Derivative gain.
+    kd: f32,
+    /// Proportional gain.
+    kp: f32,
+    /// Previous error and the time of it.
+    last: Option<(f32, Instant)>,
+    /// Time the line was lost if it hasn't been found again.
+    lost_since: Option<Instant>,
+    /// Estimator for where the line is.
+    position: LinePosition,
+    /// How long to search for a lost line before giving up.
+    search_timeout: Duration,
+    /// Cruising speed as 0-100(%).
+    speed: i8,
+}
+
+impl LineFollower {
+    /// Constructor which uses default values for all optional arguments.
+    pub fn new() -> Self {
+        Self::new_with_kitchen_sink(None, None, None, None)
+    }
+    /// Constructor with all optional arguments.
+    ///
+    /// ## Arguments
+    ///
+    /// * `speed` - Cruising speed as 0-100(%).
+    ///   Defaults to 30%.
+    /// * `kp` - Proportional gain applied to the line error.
+    ///   Defaults to 1.0 which stops the inside wheels when the line is under
+    ///   an outer sensor.
+    /// * `kd` - Derivative gain applied to the rate of change of the line
+    ///   error per second.
+    ///   Defaults to 0.05.
+    /// * `search_timeout` - Seconds to search for a lost line before giving
+    ///   up.
+    ///   Defaults to 5 seconds.
+    ///   Internally limited to 60 seconds.
+    pub fn new_with_kitchen_sink<S, P, D, T>(speed: S, kp: P, kd: D, search_timeout: T) -> Self
+    where
+        S: Into<Option<i8>>,
+        P: Into<Option<f32>>,
+        D: Into<Option<f32>>,
+        T: Into<Option<f64>>,
+    {
+        let speed = speed.into().unwrap_or(Self::SPEED).clamp(0, 100);
+        let kp = kp.into().unwrap_or(Self::KP).max(0.0);
+        let kd = kd.into().unwrap_or(Self::KD).max(0.0);
+        let search_timeout = search_timeout
+            .into()
+            .unwrap_or(Self::SEARCH_TIMEOUT)
+            .clamp(0.0, 60.0);
+        Self {
+            corner: None,
+            kd,
+            kp,
+            last: None,
+            lost_since: None,
+            position: LinePosition::new(),
+            search_timeout: Duration::from_secs_f64(search_timeout),
+            speed,
+        }
+    }
+    /// Access the [LinePosition](LinePosition) estimator.
+    pub fn position(&self) -> &LinePosition {
+        &self.position
+    }
+    /// Forgets everything seen so far so the controller can be reused.
+    pub fn reset(&mut self) {
+        self.corner = None;
+        self.last = None;
+        self.lost_since = None;
+        self.position = LinePosition::new();
+    }
+    /// Updates the controller from new line tracking sensors data.
+    ///
+    /// Returns the left and right motor speeds to use or `None` once the line
+    /// has been lost for longer than the search timeout.
+    ///
+    /// ## Arguments
+    ///
+    /// * `tracking` - Line tracking sensors data as returned from
+    ///   [`Sensors::line_tracking()`].
+    /// * `now` - Time of the sensor data.
+    ///
+    /// [`Sensors::line_tracking()`]: crate::Sensors::line_tracking()
+    pub fn step(&mut self, tracking: (bool, bool, bool, bool), now: Instant) -> Option<(i8, i8)> {
+        let (error, state) = self.position.update(tracking);
+        match state {
+            LineState::Lost | LineState::TJunction(LineSide::Center) => return self.search(now),
+            LineState::TJunction(side) => self.corner = Some(side),
+            LineState::Following => self.corner = None,
+            _ => {}
+        }
+        self.lost_since = None;
+        if state != LineState::Following {
+            // Drive straight over junctions.
+            self.last = None;
+            return Some((self.speed, self.speed));
+        }
+        let derivative = match self.last {
+            Some((last, time)) => {
+                let dt = now.saturating_duration_since(time).as_secs_f32();
+                if dt > 0.0 {
+                    (error - last) / dt
+                } else {
+                    0.0
+                }
+            }
+            None => 0.0,
+        };
+        self.last = Some((error, now));
+        let turn = (self.kp * error + self.kd * derivative).clamp(-2.0, 2.0);
+        let speed = self.speed as f32;
+        Some((
+            Self::output(speed * (1.0 + turn)),
+            Self::output(speed * (1.0 - turn)),
+        ))
+    }
+    /// Converts a motor speed to the -100(%) to +100(%) range.
+    fn output(speed: f32) -> i8 {
+        speed.round().clamp(-100.0, 100.0) as i8
+    }
+    /// Works out motor speeds while searching for a lost line.
+    ///
+    /// ## Arguments
+    ///
+    /// * `now` - Time of the sensor data.
+    fn search(&mut self, now: Instant) -> Option<(i8, i8)> {
+        let since = *self.lost_since.get_or_insert(now);
+        let lost = now.saturating_duration_since(since);
+        if lost > self.search_timeout {
+            return None;
+        }
+        self.last = None;
+        let speed = self.speed;
+        match self.corner.unwrap_or_else(|| self.position.last_side()) {
+            LineSide::Left => Some((-speed, speed)),
+            LineSide::Right => Some((speed, -speed)),
+            LineSide::Center => {
+                let mut t = lost.as_secs_f32();
+                if t < Self::GAP_TIME {
+                    return Some((speed / 2, speed / 2));
+                }
+                // Each sweep is longer than the last so it both returns to
+                // where it started and looks further to the other side.
+                t -= Self::GAP_TIME;
+                let mut sweep = 1u32;
+                while t >= Self::SWEEP_TIME * sweep as f32 {
+                    t -= Self::SWEEP_TIME * sweep as f32;
+                    sweep += 1;
+                }
+                if sweep & 1 == 1 {
+                    Some((-speed, speed))
+                } else {
+                    Some((speed, -speed))
+                }
+            }
+        }
+    }
+    /// Seconds to creep forward looking for the line after a gap.
+    const GAP_TIME: f32 = 0.2;
+    /// Default derivative gain.
+    const KD: f32 = 0.05;
+    /// Default proportional gain.
+    const KP: f32 = 1.0;
+    /// Default seconds to search for a lost line.
+    const SEARCH_TIMEOUT: f64 = 5.0;
+    /// Default cruising speed as 0-100(%).
+    const SPEED: i8 = 30;
+    /// Seconds taken by the first search sweep.
+    const SWEEP_TIME: f32 = 0.3;
+}
+
+impl Default for LineFollower {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mode for LineFollower {
+    fn step(&mut self, robot: &mut Robot, now: Instant) -> Rr4cResult<bool> {
+        let speeds = LineFollower::step(self, robot.sensors.line_tracking(), now);
+        drive(robot, speeds)
+    }
+}
+
+/// Object following controller used by the infrared follow mode.
+///
+/// Uses the sonar to hold a target distance from a hand or other object in
+/// front of the robot, backing up when it gets too close.
+/// The robot turns toward whichever infrared (IR) proximity sensor sees the
+/// object and stops when nothing is seen.
+///
+/// ## Examples
+///
+/// ```edition2018, no_run
+/// # #[cfg(target_arch = "arm")]
+/// # {
+/// use rust_rpi_4wd_car::{IrFollower, Motors, Result, Sensors};
+/// use std::{thread::sleep, time::Duration};
+///
+/// fn main() -> Result {
+///     let mut sensors = Sensors::new()?;
+///     let mut motors = Motors::new()?;
+///     let follower = IrFollower::new_with_kitchen_sink(None, 15.0, None);
+///     loop {
+///         let distance = sensors.front_distance();
+///         let (left, right) = follower.step(sensors.ir_proximity(), distance);
+///         motors.movement(left, right)?;
+///         sleep(Duration::from_millis(10));
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IrFollower {
+    /// Distance in cm beyond which the object counts as lost.
+    range: f32,
+    /// Fastest speed as 0-100(%).
+    speed: i8,
+    /// Distance in cm to hold from the object.
+    target: f32,
+}
+
+impl IrFollower {
+    /// Constructor which uses default values for all optional arguments.
+    pub fn new() -> Self {
+        Self::new_with_kitchen_sink(None, None, None)
+    }
+    /// Constructor with all optional arguments.
+    ///
+    /// ## Arguments
+    ///
+    /// * `speed` - Fastest speed as 0-100(%).
+    ///   Defaults to 30%.
+    /// * `target` - Distance in cm to hold from the object.
+    ///   Defaults to 20cm.
+    /// * `range` - Distance in cm beyond which the object counts as lost.
+    ///   Defaults to 60cm.
+    ///   Internally limited to no nearer than `target`.
+    pub fn new_with_kitchen_sink<S, T, R>(speed: S, target: T, range: R) -> Self
+    where
+        S: Into<Option<i8>>,
+        T: Into<Option<f32>>,
+        R: Into<Option<f32>>,
+    {
+        let speed = speed.into().unwrap_or(Self::SPEED).clamp(0, 100);
+        let target = target.into().unwrap_or(Self::TARGET).max(0.0);
+        let range = range.into().unwrap_or(Self::RANGE).max(target);
+        Self {
+            range,
+            speed,
+            target,
+        }
+    }
+    /// Works out motor speeds from new sensor data.
+    ///
+    /// Returns the left and right motor speeds to use.
+    ///
+    /// ## Arguments
+    ///
+    /// * `ir` - Infrared (IR) proximity sensors data as returned from
+    ///   [`Sensors::ir_proximity()`].
+    /// * `distance` - Optional distance in cm ahead as returned from
+    ///   [`Sensors::front_distance()`].
+    ///
+    /// [`Sensors::ir_proximity()`]: crate::Sensors::ir_proximity()
+    /// [`Sensors::front_distance()`]: crate::Sensors::front_distance()
+    pub fn step<D>(&self, ir: (bool, bool), distance: D) -> (i8, i8)
+    where
+        D: Into<Option<f32>>,
+    {
+        let speed = self.speed;
+        let slow = speed / 2;
+        match ir {
+            // Only one side sees it so turn toward it.
+            (true, false) => (-slow, slow),
+            (false, true) => (slow, -slow),
+            _ => match distance.into() {
+                Some(distance) if distance <= self.range => {
+                    let error = distance - self.target;
+                    if error.abs() < Self::DEAD_BAND {
+                        (0, 0)
+                    } else {
+                        // Proportional to how far off the target distance.
+                        let scale = (error / Self::FULL_SPEED_ERROR).clamp(-1.0, 1.0);
+                        let speed = (speed as f32 * scale).round() as i8;
+                        (speed, speed)
+                    }
+                }
+                // Both IR sensors see something the sonar doesn't.
+                _ if ir.0 => (-slow, -slow),
+                _ => (0, 0),
+            },
+        }
+    }
+    /// Distance in cm from the target that counts as being there.
+    const DEAD_BAND: f32 = 3.0;
+    /// Distance in cm from the target where full speed is used.
+    const FULL_SPEED_ERROR: f32 = 20.0;
+    /// Default distance in cm beyond which the object counts as lost.
+    const RANGE: f32 = 60.0;
+    /// Default fastest speed as 0-100(%).
+    const SPEED: i8 = 30;
+    /// Default distance in cm to hold from the object.
+    const TARGET: f32 = 20.0;
+}
+
+impl Default for IrFollower {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mode for IrFollower {
+    fn step(&mut self, robot: &mut Robot, _: Instant) -> Rr4cResult<bool> {
+        let distance = robot.sensors.latest_front_distance();
+        let speeds = IrFollower::step(self, robot.sensors.ir_proximity(), distance);
+        drive(robot, Some(speeds))
+    }
+}
+
+/// LED light show used by the LED colors mode.
+///
+/// Cycles through the colors of a palette with the `KEY` button moving on to
+/// the next palette.
+/// While something is within reacting distance of the sonar the LEDs instead
+/// show how close it is going from green at the edge of the range through
+/// yellow to red when it is close.
+///
+/// ## Examples
+///
+/// ```edition2018, no_run
+/// # #[cfg(target_arch = "arm")]
+/// # {
+/// use rust_rpi_4wd_car::{Hids, LightShow, Result, Sensors};
+/// use std::{thread::sleep, time::{Duration, Instant}};
+///
+/// fn main() -> Result {
+///     let mut sensors = Sensors::new()?;
+///     let mut hids = Hids::new()?;
+///     let palettes = vec![vec![(100, 0, 0), (0, 0, 100)], vec![(100, 100, 100), (0, 0, 0)]];
+///     let mut show = LightShow::new_with_kitchen_sink(palettes, 0.25, None);
+///     loop {
+///         let distance = sensors.front_distance();
+///         let (red, green, blue) = show.step(distance, hids.is_key_down(), Instant::now());
+///         hids.lights(red, green, blue)?;
+///         sleep(Duration::from_millis(20));
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct LightShow {
+    /// Used to detect when the `KEY` button is first pressed.
+    key_down: bool,
+    /// Index of the palette being shown.
+    palette: usize,
+    /// Palettes of RGB brightnesses as 0-100(%).
+    palettes: Vec<Vec<(u8, u8, u8)>>,
+    /// How long each color is shown.
+    period: Duration,
+    /// Distance in cm within which the LEDs show how close something is.
+    react: f32,
+    /// Time the current palette started being shown.
+    started: Option<Instant>,
+}
+
+impl LightShow {
+    /// Constructor which uses default values for all optional arguments.
+    pub fn new() -> Self {
+        Self::new_with_kitchen_sink(None, None, None)
+    }
+    /// Constructor with all optional arguments.
+    ///
+    /// ## Arguments
+    ///
+    /// * `palettes` - Palettes of RGB brightnesses as 0-100(%) to cycle
+    ///   through.
+    ///   Empty palettes are ignored.
+    ///   Defaults to rainbow, warm, cool, and red/blue flashing palettes.
+    /// * `period` - Seconds each color is shown.
+    ///   Defaults to 0.5 seconds.
+    ///   Internally limited between 0.05 and 10 seconds.
+    /// * `react` - Distance in cm within which the LEDs show how close
+    ///   something is.
+    ///   Defaults to 30cm.
+    ///   Use 0.0 to turn off reacting to the sonar.
+    pub fn new_with_kitchen_sink<P, T, R>(palettes: P, period: T, react: R) -> Self
+    where
+        P: Into<Option<Vec<Vec<(u8, u8, u8)>>>>,
+        T: Into<Option<f64>>,
+        R: Into<Option<f32>>,
+    {
+        let mut palettes: Vec<Vec<(u8, u8, u8)>> = palettes
+            .into()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|p| !p.is_empty())
+            .collect();
+        if palettes.is_empty() {
+            palettes = Self::PALETTES.iter().map(|p| p.to_vec()).collect();
+        }
+        let period = period.into().unwrap_or(Self::PERIOD).clamp(0.05, 10.0);
+        let react = react.into().unwrap_or(Self::REACT).max(0.0);
+        Self {
+            key_down: false,
+            palette: 0,
+            palettes,
+            period: Duration::from_secs_f64(period),
+            react,
+            started: None,
+        }
+    }
+    /// Access the index of the palette being shown.
+    pub fn palette(&self) -> usize {
+        self.palette
+    }
+    /// Works out the LED colors from new sensor data.
+    ///
+    /// Returns the red, green, and blue brightnesses as 0-100(%).
+    ///
+    /// ## Arguments
+    ///
+    /// * `distance` - Optional distance in cm ahead as returned from
+    ///   [`Sensors::front_distance()`].
+    /// * `key` - Is the `KEY` button down as returned from
+    ///   [`Hids::is_key_down()`].
+    /// * `now` - Time of the sensor data.
+    ///
+    /// [`Sensors::front_distance()`]: crate::Sensors::front_distance()
+    /// [`Hids::is_key_down()`]: crate::Hids::is_key_down()
+    pub fn step<D>(&mut self, distance: D, key: bool, now: Instant) -> (u8, u8, u8)
+    where
+        D: Into<Option<f32>>,
+    {
+        if key && !self.key_down {
+            self.palette = (self.palette + 1) % self.palettes.len();
+            self.started = Some(now);
+        }
+        self.key_down = key;
+        if let Some(distance) = distance.into().filter(|d| *d < self.react) {
+            let t = (distance / self.react).clamp(0.0, 1.0);
+            let red = ((1.0 - t) * 200.0).min(100.0) as u8;
+            let green = (t * 200.0).min(100.0) as u8;
+            return (red, green, 0);
+        }
+        let elapsed = now.saturating_duration_since(*self.started.get_or_insert(now));
+        let colors = &self.palettes[self.palette];
+        let index = (elapsed.as_secs_f64() / self.period.as_secs_f64()) as usize;
+        colors[index % colors.len()]
+    }
+    /// Built-in palettes.
+    const PALETTES: [&'static [(u8, u8, u8)]; 4] = [
+        // Rainbow
+        &[
+            (100, 0, 0),
+            (100, 100, 0),
+            (0, 100, 0),
+            (0, 100, 100),
+            (0, 0, 100),
+            (100, 0, 100),
+        ],
+        // Warm
+        &[(100, 0, 0), (100, 30, 0), (100, 60, 0), (100, 30, 0)],
+        // Cool
+        &[(0, 0, 100), (0, 50, 100), (0, 100, 100), (30, 0, 100)],
+        // Red/blue flashing
+        &[(100, 0, 0), (0, 0, 0), (0, 0, 100), (0, 0, 0)],
+    ];
+    /// Default seconds each color is shown.
+    const PERIOD: f64 = 0.5;
+    /// Default distance in cm within which the LEDs show how close something
+    /// is.
+    const REACT: f32 = 30.0;
+}
+
+impl Default for LightShow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mode for LightShow {
+    fn step(&mut self, robot: &mut Robot, now: Instant) -> Rr4cResult<bool> {
+        let distance = robot.sensors.latest_front_distance();
+        let key = robot.hids.is_key_down();
+        let (red, green, blue) = LightShow::step(self, distance, key, now);
+        robot.hids.lights(red, green, blue)?;
+        Ok(true)
+    }
+    fn stop(&mut self, robot: &mut Robot, _: &ExitReason) -> Result {
+        robot.hids.lights(0, 0, 0)
+    }
+}
+
+/// Light seeking controller used by the light seeking mode.
+///
+/// Steers toward whichever of the two light dependent resistors (LDRs) sees
+/// light and drives straight when both do.
+/// When neither sees light it spins toward the side light was last seen on.
+/// The robot has arrived once either infrared (IR) proximity sensor sees
+/// something or the sonar says something is close ahead.
+///
+/// ## Examples
+///
+/// ```edition2018, no_run
+/// # #[cfg(target_arch = "arm")]
+/// # {
+/// use rust_rpi_4wd_car::{LightSeeker, Motors, Result, SeekState, Sensors};
+/// use std::{thread::sleep, time::{Duration, Instant}};
+///
+/// fn main() -> Result {
+///     let mut sensors = Sensors::new()?;
+///     let mut motors = Motors::new()?;
+///     let mut seeker = LightSeeker::new();
+///     loop {
+///         let distance = sensors.front_distance();
+///         let ldr = sensors.ldr_tracking();
+///         match seeker.step(ldr, sensors.ir_proximity(), distance, Instant::now()) {
+///             Some((left, right)) => motors.movement(left, right)?,
+///             None => break,
+///         }
+///         sleep(Duration::from_millis(10));
+///     }
+///     if seeker.state() == SeekState::Arrived {
+///         println!("Found the light");
+///     }
+///     motors.brake()
+/// }
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightSeeker {
+    /// Distance in cm ahead that counts as having arrived.
+    arrive: f32,
+    /// Side light was last seen on.
+    last_side: LineSide,
+    /// How long to search for light before giving up.
+    search_timeout: Duration,
+    /// Time the light was lost if it hasn't been found again.
+    searching_since: Option<Instant>,
+    /// Cruising speed as 0-100(%).
+    speed: i8,
+    /// Latest state.
+    state: SeekState,
+}
+
+impl LightSeeker {
+    /// Constructor which uses default values for all optional arguments.
+    pub fn new() -> Self {
+        Self::new_with_kitchen_sink(None, None, None)
+    }
+    /// Constructor with all optional arguments.
+    ///
+    /// ## Arguments
+    ///
+    /// * `speed` - Cruising speed as 0-100(%).
+    ///   Defaults to 30%.
+    /// * `arrive` - Distance in cm ahead that counts as having arrived.
+    ///   Defaults to 15cm.
+    /// * `search_timeout` - Seconds to spin looking for light before giving
+    ///   up.
+    ///   Defaults to 10 seconds.
+    ///   Internally limited to 60 seconds.
+    pub fn new_with_kitchen_sink<S, A, T>(speed: S, arrive: A, search_timeout: T) -> Self
+    where
+        S: Into<Option<i8>>,
+        A: Into<Option<f32>>,
+        T: Into<Option<f64>>,
+    {
+        let speed = speed.into().unwrap_or(Self::SPEED).clamp(0, 100);
+        let arrive = arrive.into().unwrap_or(Self::ARRIVE).max(0.0);
+        let search_timeout = search_timeout
+            .into()
+            .unwrap_or(Self::SEARCH_TIMEOUT)
+            .clamp(0.0, 60.0);
+        Self {
+            arrive,
+            last_side: LineSide::Center,
+            search_timeout: Duration::from_secs_f64(search_timeout),
+            searching_since: None,
+            speed,
+            state: SeekState::Searching,
+        }
+    }
+    /// Access the latest [SeekState](SeekState).
+    pub fn state(&self) -> SeekState {
+        self.state
+    }
+    /// Updates the controller from new sensor data.
+    ///
+    /// Returns the left and right motor speeds to use or `None` once the robot
+    /// has arrived or has searched for light for longer than the search
+    /// timeout.
+    ///
+    /// ## Arguments
+    ///
+    /// * `ldr` - Light dependent resistor (LDR) sensors data as returned from
+    ///   [`Sensors::ldr_tracking()`].
+    /// * `ir` - Infrared (IR) proximity sensors data as returned from
+    ///   [`Sensors::ir_proximity()`].
+    /// * `distance` - Optional distance in cm ahead as returned from
+    ///   [`Sensors::front_distance()`].
+    /// * `now` - Time of the sensor data.
+    ///
+    /// [`Sensors::ldr_tracking()`]: crate::Sensors::ldr_tracking()
+    /// [`Sensors::ir_proximity()`]: crate::Sensors::ir_proximity()
+    /// [`Sensors::front_distance()`]: crate::Sensors::front_distance()
+    pub fn step<D>(
+        &mut self,
+        ldr: (bool, bool),
+        ir: (bool, bool),
+        distance: D,
+        now: Instant,
+    ) -> Option<(i8, i8)>
+    where
+        D: Into<Option<f32>>,
+    {
+        let near = matches!(distance.into(), Some(distance) if distance < self.arrive);
+        if ir.0 || ir.1 || near {
+            self.state = SeekState::Arrived;
+            return None;
+        }
+        let speed = self.speed;
+        let (left, right) = ldr;
+        if left || right {
+            self.searching_since = None;
+            self.state = SeekState::Steering;
+            return Some(match (left, right) {
+                (true, false) => {
+                    self.last_side = LineSide::Left;
+                    (speed / 3, speed)
+                }
+                (false, true) => {
+                    self.last_side = LineSide::Right;
+                    (speed, speed / 3)
+                }
+                _ => {
+                    self.last_side = LineSide::Center;
+                    (speed, speed)
+                }
+            });
+        }
+        self.state = SeekState::Searching;
+        let since = *self.searching_since.get_or_insert(now);
+        if now.saturating_duration_since(since) > self.search_timeout {
+            return None;
+        }
+        match self.last_side {
+            LineSide::Left => Some((-speed, speed)),
+            _ => Some((speed, -speed)),
+        }
+    }
+    /// Default distance in cm ahead that counts as having arrived.
+    const ARRIVE: f32 = 15.0;
+    /// Default seconds to search for light.
+    const SEARCH_TIMEOUT: f64 = 10.0;
+    /// Default cruising speed as 0-100(%).
+    const SPEED: i8 = 30;
+}
+
+impl Default for LightSeeker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mode for LightSeeker {
+    fn step(&mut self, robot: &mut Robot, now: Instant) -> Rr4cResult<bool> {
+        let distance = robot.sensors.latest_front_distance();
+        let ldr = robot.sensors.ldr_tracking();
+        let ir = robot.sensors.ir_proximity();
+        let speeds = LightSeeker::step(self, ldr, ir, distance, now);
+        drive(robot, speeds)
+    }
+}
+
+/// Obstacle avoidance controller used by the ultrasonic avoid mode.
+///
+/// Cruises forward using an [ObstacleDetector](ObstacleDetector) to fuse the
+/// front sonar with the infrared (IR) proximity sensors.
+/// The robot slows down as something ahead gets closer and turns away from
+/// anything the IR sensors see.
+/// Once something ahead is close the robot stops and uses the front servo to
+/// look left then right before turning toward the clearest side.
+/// When both sides are blocked or the IR sensors are both triggered it backs
+/// out and scans again.
+///
+/// ## Examples
+///
+/// ```edition2018, no_run
+/// # #[cfg(target_arch = "arm")]
+/// # {
+/// use rust_rpi_4wd_car::{Motors, ObstacleAvoider, Result, Sensors, Servos};
+/// use std::{thread::sleep, time::{Duration, Instant}};
+///
+/// fn main() -> Result {
+///     let mut sensors = Sensors::new()?;
+///     let mut motors = Motors::new()?;
+///     let mut servos = Servos::new()?;
+///     let mut avoider = ObstacleAvoider::new();
+///     loop {
+///         let distance = sensors.front_distance();
+///         let (left, right) = avoider.step(distance, sensors.ir_proximity(), Instant::now());
+///         servos.set_front(avoider.look())?;
+///         motors.movement(left, right)?;
+///         sleep(Duration::from_millis(10));
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObstacleAvoider {
+    /// Cruising speed as 0-100(%).
+    cruise: i8,
+    /// Fuses the sonar and IR sensors.
+    detector: ObstacleDetector,
+    /// Distance in cm where the robot starts slowing down.
+    far: f32,
+    /// Front servo angle the sonar should be pointed at.
+    look: u8,
+    /// Motor speeds used while turning or reversing.
+    motion: (i8, i8),
+    /// Distance in cm where the robot stops to scan.
+    near: f32,
+    /// Distances seen to the left and right during the latest scan.
+    scan: (Option<f32>, Option<f32>),
+    /// Time the current state started.
+    since: Option<Instant>,
+    /// Latest state.
+    state: AvoidState,
+}
+
+impl ObstacleAvoider {
+    /// Constructor which uses default values for all optional arguments.
+    pub fn new() -> Self {
+        Self::new_with_kitchen_sink(None, None, None)
+    }
+    /// Constructor with all optional arguments.
+    ///
+    /// ## Arguments
+    ///
+    /// * `speed` - Cruising speed as 0-100(%).
+    ///   Defaults to 30%.
+    /// * `near` - Distance in cm where the robot stops to scan.
+    ///   Defaults to 20cm.
+    /// * `far` - Distance in cm where the robot starts slowing down.
+    ///   Defaults to 50cm.
+    ///   Internally limited to no nearer than `near`.
+    pub fn new_with_kitchen_sink<S, N, F>(speed: S, near: N, far: F) -> Self
+    where
+        S: Into<Option<i8>>,
+        N: Into<Option<f32>>,
+        F: Into<Option<f32>>,
+    {
+        let cruise = speed.into().unwrap_or(Self::SPEED).clamp(0, 100);
+        let near = near.into().unwrap_or(Self::NEAR).max(0.0);
+        let far = far.into().unwrap_or(Self::FAR).max(near);
+        Self {
+            cruise,
+            detector: ObstacleDetector::new_with_thresholds(near, far, None, None),
+            far,
+            look: Self::AHEAD,
+            motion: (0, 0),
+            near,
+            scan: (None, None),
+            since: None,
+            state: AvoidState::Cruising,
+        }
+    }
+    /// Access the front servo angle the sonar should be pointed at.
+    ///
+    /// Should be passed to [`Servos::set_front()`] after each step.
+    ///
+    /// [`Servos::set_front()`]: crate::Servos::set_front()
+    pub fn look(&self) -> u8 {
+        self.look
+    }
+    /// Access the latest [AvoidState](AvoidState).
+    pub fn state(&self) -> AvoidState {
+        self.state
+    }
+    /// Updates the controller from new sensor data.
+    ///
+    /// Returns the left and right motor speeds to use.
+    ///
+    /// ## Arguments
+    ///
+    /// * `distance` - Optional distance in cm seen by the sonar in the
+    ///   direction given by [`look()`] as returned from
+    ///   [`Sensors::front_distance()`].
+    /// * `ir` - Infrared (IR) proximity sensors data as returned from
+    ///   [`Sensors::ir_proximity()`].
+    /// * `now` - Time of the sensor data.
+    ///
+    /// [`look()`]: ObstacleAvoider::look()
+    /// [`Sensors::front_distance()`]: crate::Sensors::front_distance()
+    /// [`Sensors::ir_proximity()`]: crate::Sensors::ir_proximity()
+    pub fn step<D>(&mut self, distance: D, ir: (bool, bool), now: Instant) -> (i8, i8)
+    where
+        D: Into<Option<f32>>,
+    {
+        let distance = distance.into();
+        let elapsed = now.saturating_duration_since(*self.since.get_or_insert(now));
+        let slow = self.cruise / 2;
+        match self.state {
+            AvoidState::Cruising => match self.detector.update(distance, ir, now) {
+                Obstacle::Clear => (self.cruise, self.cruise),
+                Obstacle::FrontFar => {
+                    let distance = distance.unwrap_or(self.far);
+                    let scale = if self.far > self.near {
+                        ((distance - self.near) / (self.far - self.near)).clamp(Self::SLOWEST, 1.0)
+                    } else {
+                        1.0
+                    };
+                    let speed = (self.cruise as f32 * scale).round() as i8;
+                    (speed, speed)
+                }
+                // Turn away from whichever side is blocked.
+                Obstacle::Left => (slow, -slow),
+                Obstacle::Right => (-slow, slow),
+                Obstacle::FrontNear => {
+                    self.enter(AvoidState::Scanning, now);
+                    self.look = Self::LOOK_LEFT;
+                    (0, 0)
+                }
+                Obstacle::BoxedIn => self.reverse(now),
+            },
+            AvoidState::Scanning => {
+                // Give the servo and sonar time to settle at each angle.
+                if elapsed < Self::SETTLE {
+                    return (0, 0);
+                }
+                if self.look == Self::LOOK_LEFT {
+                    self.scan.0 = distance;
+                    self.look = Self::LOOK_RIGHT;
+                    self.since = Some(now);
+                    return (0, 0);
+                }
+                self.scan.1 = distance;
+                self.look = Self::AHEAD;
+                // Nothing heard means nothing is there.
+                let left = self.scan.0.unwrap_or(f32::INFINITY);
+                let right = self.scan.1.unwrap_or(f32::INFINITY);
+                if left.max(right) < self.near {
+                    return self.reverse(now);
+                }
+                self.enter(AvoidState::Turning, now);
+                self.motion = if left >= right {
+                    (-slow, slow)
+                } else {
+                    (slow, -slow)
+                };
+                self.motion
+            }
+            AvoidState::Turning => {
+                if elapsed < Self::TURN {
+                    return self.motion;
+                }
+                self.enter(AvoidState::Cruising, now);
+                (self.cruise, self.cruise)
+            }
+            AvoidState::Reversing => {
+                if elapsed < Self::REVERSE {
+                    return self.motion;
+                }
+                self.enter(AvoidState::Scanning, now);
+                self.look = Self::LOOK_LEFT;
+                (0, 0)
+            }
+        }
+    }
+    /// Changes to a new state.
+    ///
+    /// ## Arguments
+    ///
+    /// * `state` - The new [AvoidState](AvoidState).
+    /// * `now` - Time the state started.
+    fn enter(&mut self, state: AvoidState, now: Instant) {
+        self.state = state;
+        self.since = Some(now);
+    }
+    /// Starts backing out of a dead end.
+    ///
+    /// ## Arguments
+    ///
+    /// * `now` - Time to start reversing.
+    fn reverse(&mut self, now: Instant) -> (i8, i8) {
+        self.enter(AvoidState::Reversing, now);
+        self.look = Self::AHEAD;
+        self.motion = (-self.cruise, -self.cruise);
+        self.motion
+    }
+    /// Front servo angle to look straight ahead.
+    const AHEAD: u8 = 90;
+    /// Default distance in cm where the robot starts slowing down.
+    const FAR: f32 = 50.0;
+    /// Front servo angle used to look left.
+    const LOOK_LEFT: u8 = 160;
+    /// Front servo angle used to look right.
+    const LOOK_RIGHT: u8 = 20;
+    /// Default distance in cm where the robot stops to scan.
+    const NEAR: f32 = 20.0;
+    /// How long to back out of a dead end.
+    const REVERSE: Duration = Duration::from_millis(500);
+    /// How long to wait for the servo and sonar at each scan angle.
+    const SETTLE: Duration = Duration::from_millis(300);
+    /// Slowest cruising speed as a fraction of the full cruising speed.
+    const SLOWEST: f32 = 0.4;
+    /// Default cruising speed as 0-100(%).
+    const SPEED: i8 = 30;
+    /// How long to turn toward the clearest direction.
+    const TURN: Duration = Duration::from_millis(400);
+}
+
+impl Default for ObstacleAvoider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mode for ObstacleAvoider {
+    fn start(&mut self, robot: &mut Robot) -> Result {
+        robot.servos.set_front(self.look)
+    }
+    fn step(&mut self, robot: &mut Robot, now: Instant) -> Rr4cResult<bool> {
+        let distance = robot.sensors.latest_front_distance();
+        let look = self.look;
+        let speeds = ObstacleAvoider::step(self, distance, robot.sensors.ir_proximity(), now);
+        if self.look != look {
+            robot.servos.set_front(self.look)?;
+        }
+        drive(robot, Some(speeds))
+    }
+    fn stop(&mut self, robot: &mut Robot, _: &ExitReason) -> Result {
+        robot.servos.set_front(None)
+    }
+}
+
+/// Drives the motors for a step of one of the built-in modes.
+///
+/// Returns `false` when the mode has finished or the `KEY` button is pressed.
+///
+/// ## Arguments
+///
+/// * `robot` - The shared [Robot](Robot).
+/// * `speeds` - Left and right motor speeds from the mode or `None` when it
+///   has finished.
+fn drive(robot: &mut Robot, speeds: Option<(i8, i8)>) -> Rr4cResult<bool> {
+    if robot.hids.is_key_down() {
+        return Ok(false);
+    }
+    match speeds {
+        Some((left, right)) => {
+            robot.motors.movement(left, right)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
@@ -35,7 +35,10 @@
 // SOFTWARE.
 //! Contains all the motor related components.
 
-use crate::error::{Result, Rr4cError, Rr4cResult};
+use crate::{
+    error::{Result, Rr4cError, Rr4cResult},
+    EmergencyStop,
+};
 use embedded_hal::PwmPin;
 use rppal::gpio::{Gpio, OutputPin};
 
@@ -77,6 +80,8 @@ pub struct Motors {
     ///
     /// [`movement()`]: Motors::movement()
     default_speed: i8,
+    /// Emergency stop latch checked before any movement.
+    emergency_stop: EmergencyStop,
     /// Speed scale factor
     ///
     /// Used to scale actual speed so given speeds are always 0-100%.
@@ -115,6 +120,7 @@ impl Motors {
             b_in2,
             b_pwm,
             default_speed,
+            emergency_stop: EmergencyStop::new(),
             speed_scale,
         })
     }
@@ -127,6 +133,10 @@ impl Motors {
         self.a_pwm.clear_pwm()?;
         self.b_pwm.clear_pwm().map_err(Rr4cError::Gpio)
     }
+    /// Access to the emergency stop latch used by the motors.
+    pub fn emergency_stop(&self) -> &EmergencyStop {
+        &self.emergency_stop
+    }
     /// Used to enable/disable robot moving.
     ///
     /// ## Arguments
@@ -173,12 +183,20 @@ impl Motors {
     /// The left motors going backwards and the right motors forward.
     /// Both motors will be at ~50% speed.
     ///
+    /// __NOTE:__ While the [`emergency_stop()`] latch is engaged the motors are
+    /// braked instead and [`Rr4cError::EmergencyStop`] is returned.
+    ///
     /// [`enable(true)`]: Motors::enable()
+    /// [`emergency_stop()`]: Motors::emergency_stop()
     pub fn movement<L, R>(&mut self, left: L, right: R) -> Result
     where
         L: Into<Option<i8>>,
         R: Into<Option<i8>>,
     {
+        if let Err(e) = self.emergency_stop.check() {
+            self.brake()?;
+            return Err(e);
+        }
         let left = left.into().unwrap_or(self.default_speed);
         let right = right.into().unwrap_or(self.default_speed);
         let left_dc: f64;
@@ -223,6 +241,14 @@ impl Motors {
         self.b_pwm.set_duty(right_dc);
         Ok(())
     }
+    /// Replaces the emergency stop latch so it can be shared with the rest of
+    /// the robot.
+    ///
+    /// ## Arguments
+    /// * `estop` - Latch to use from now on.
+    pub fn set_emergency_stop(&mut self, estop: EmergencyStop) {
+        self.emergency_stop = estop;
+    }
     /// Access the current speeds of the left and right motors.
     ///
     /// __NOTE:__ Speeds will be return even when motors are _not_ actively
@@ -0,0 +1,228 @@
+// Copyright © 2021-present, Michael Cummings
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// MIT License
+//
+// Copyright © 2021-present, Michael Cummings <mgcummings@yahoo.com>.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! Contains the obstacle detector used to fuse the front sonar with the left
+//! and right infrared (IR) proximity sensors.
+
+use std::time::{Duration, Instant};
+
+/// Classified obstacle state from an [ObstacleDetector](ObstacleDetector).
+///
+/// Listed from least to most blocked.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Obstacle {
+    /// Nothing detected.
+    #[default]
+    Clear,
+    /// Sonar sees something ahead but not close yet.
+    FrontFar,
+    /// Only the right IR sensor sees something.
+    Right,
+    /// Only the left IR sensor sees something.
+    Left,
+    /// Sonar sees something close ahead.
+    FrontNear,
+    /// Both IR sensors see something so there is no clear way to turn.
+    BoxedIn,
+}
+
+/// Fuses the front sonar with the left and right infrared (IR) proximity
+/// sensors into a single [Obstacle](Obstacle) state.
+///
+/// Sonar distances use hysteresis so the state doesn't flicker when an
+/// obstacle is right at a threshold and IR detections are held for a short
+/// time after they clear.
+///
+/// ## Examples
+///
+/// ```edition2018, no_run
+/// # #[cfg(target_arch = "arm")]
+/// # {
+/// use rust_rpi_4wd_car::{Obstacle, ObstacleDetector, Result, Sensors};
+/// use std::time::Instant;
+///
+/// fn main() -> Result {
+///     let mut sensors = Sensors::new()?;
+///     let mut detector = ObstacleDetector::new_with_thresholds(15.0, 40.0, None, None);
+///     let distance = sensors.sonar_distance().ok().map(|r| r.filtered);
+///     match detector.update(distance, sensors.ir_proximity(), Instant::now()) {
+///         Obstacle::Clear => println!("All clear"),
+///         obstacle => println!("Watch out: {:?}", obstacle),
+///     }
+///     Ok(())
+/// }
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObstacleDetector {
+    /// Distance in cm where something ahead is first reported.
+    far: f32,
+    /// Current front sonar state.
+    front: Obstacle,
+    /// Distance in cm an obstacle has to move beyond a threshold to clear it.
+    hysteresis: f32,
+    /// How long an IR detection is held after it clears.
+    ir_hold: Duration,
+    /// Time the left IR sensor last saw something.
+    left_seen: Option<Instant>,
+    /// Distance in cm where something ahead is close.
+    near: f32,
+    /// Time the right IR sensor last saw something.
+    right_seen: Option<Instant>,
+    /// Latest state.
+    state: Obstacle,
+}
+
+impl ObstacleDetector {
+    /// Constructor which uses default values for all optional arguments.
+    pub fn new() -> Self {
+        Self::new_with_thresholds(None, None, None, None)
+    }
+    /// Constructor with all optional arguments.
+    ///
+    /// ## Arguments
+    ///
+    /// * `near` - Distance in cm where something ahead is close.
+    ///   Defaults to 20cm.
+    /// * `far` - Distance in cm where something ahead is first reported.
+    ///   Defaults to 50cm.
+    ///   Internally limited to no nearer than `near`.
+    /// * `hysteresis` - Distance in cm an obstacle has to move beyond a
+    ///   threshold to clear it.
+    ///   Defaults to 5cm.
+    /// * `ir_hold` - Seconds an IR detection is held after it clears.
+    ///   Defaults to 0.1 seconds.
+    ///   Internally limited to 2 seconds.
+    pub fn new_with_thresholds<N, F, H, I>(near: N, far: F, hysteresis: H, ir_hold: I) -> Self
+    where
+        N: Into<Option<f32>>,
+        F: Into<Option<f32>>,
+        H: Into<Option<f32>>,
+        I: Into<Option<f64>>,
+    {
+        let near = near.into().unwrap_or(Self::NEAR).max(0.0);
+        let far = far.into().unwrap_or(Self::FAR).max(near);
+        let hysteresis = hysteresis.into().unwrap_or(Self::HYSTERESIS).max(0.0);
+        let ir_hold = ir_hold.into().unwrap_or(Self::IR_HOLD).clamp(0.0, 2.0);
+        Self {
+            far,
+            front: Obstacle::Clear,
+            hysteresis,
+            ir_hold: Duration::from_secs_f64(ir_hold),
+            left_seen: None,
+            near,
+            right_seen: None,
+            state: Obstacle::Clear,
+        }
+    }
+    /// Access the latest [Obstacle](Obstacle) state.
+    pub fn state(&self) -> Obstacle {
+        self.state
+    }
+    /// Updates the state from new sensor data.
+    ///
+    /// Returns the new [Obstacle](Obstacle) state.
+    ///
+    /// ## Arguments
+    ///
+    /// * `distance` - Optional front sonar distance in cm.
+    ///   A `None` value means nothing was heard so the previous front state
+    ///   is kept until a real reading arrives.
+    /// * `ir` - Infrared (IR) proximity sensors data as returned from
+    ///   [`Sensors::ir_proximity()`].
+    /// * `now` - Time of the sensor data.
+    ///
+    /// [`Sensors::ir_proximity()`]: crate::Sensors::ir_proximity()
+    pub fn update<D>(&mut self, distance: D, ir: (bool, bool), now: Instant) -> Obstacle
+    where
+        D: Into<Option<f32>>,
+    {
+        self.front = match distance.into() {
+            Some(distance) => {
+                // Thresholds move out once crossed so the obstacle has to back
+                // off further before it clears.
+                let near = match self.front {
+                    Obstacle::FrontNear => self.near + self.hysteresis,
+                    _ => self.near,
+                };
+                let far = match self.front {
+                    Obstacle::Clear => self.far,
+                    _ => self.far + self.hysteresis,
+                };
+                if distance < near {
+                    Obstacle::FrontNear
+                } else if distance < far {
+                    Obstacle::FrontFar
+                } else {
+                    Obstacle::Clear
+                }
+            }
+            None => self.front,
+        };
+        let (left, right) = ir;
+        if left {
+            self.left_seen = Some(now);
+        }
+        if right {
+            self.right_seen = Some(now);
+        }
+        let held = |seen: Option<Instant>| match seen {
+            Some(seen) => now.saturating_duration_since(seen) <= self.ir_hold,
+            None => false,
+        };
+        self.state = match (held(self.left_seen), held(self.right_seen)) {
+            (true, true) => Obstacle::BoxedIn,
+            _ if self.front == Obstacle::FrontNear => Obstacle::FrontNear,
+            (true, false) => Obstacle::Left,
+            (false, true) => Obstacle::Right,
+            (false, false) => self.front,
+        };
+        self.state
+    }
+    /// Default distance in cm where something ahead is first reported.
+    const FAR: f32 = 50.0;
+    /// Default distance in cm an obstacle has to move beyond a threshold.
+    const HYSTERESIS: f32 = 5.0;
+    /// Default seconds an IR detection is held.
+    const IR_HOLD: f64 = 0.1;
+    /// Default distance in cm where something ahead is close.
+    const NEAR: f32 = 20.0;
+}
+
+impl Default for ObstacleDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright © 2021-present, Michael Cummings
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// MIT License
+//
+// Copyright © 2021-present, Michael Cummings <mgcummings@yahoo.com>.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! Contains the range tracker used to estimate closing speed and
+//! time-to-collision from timestamped sonar readings.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Estimate from a [RangeTracker](RangeTracker).
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RangeEstimate {
+    /// Estimated distance in cm.
+    pub distance: f32,
+    /// Estimated speed in cm per second the obstacle is getting closer.
+    ///
+    /// Negative when it is getting farther away.
+    pub closing_speed: f32,
+    /// Estimated time until the distance reaches zero if still closing.
+    pub time_to_collision: Option<Duration>,
+    /// Timestamp of the sonar reading the estimate was last updated from.
+    pub timestamp: Duration,
+}
+
+/// Alpha-beta tracking filter over timestamped sonar distances.
+///
+/// ## Examples
+///
+/// ```edition2018, no_run
+/// # #[cfg(target_arch = "arm")]
+/// # {
+/// use rust_rpi_4wd_car::{RangeTracker, Result, Sonar};
+///
+/// fn main() -> Result {
+///     let mut sonar = Sonar::new()?;
+///     let mut tracker = RangeTracker::new();
+///     for _ in 0..10 {
+///         let reading = sonar.distance()?;
+///         let estimate = tracker.update(reading.filtered, reading.timestamp);
+///         if let Some(ttc) = estimate.time_to_collision {
+///             println!("Collision in {:?}", ttc);
+///         }
+///     }
+///     Ok(())
+/// }
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RangeTracker {
+    /// Gain used to correct the distance.
+    alpha: f32,
+    /// Gain used to correct the velocity.
+    beta: f32,
+    /// Latest estimate.
+    estimate: Option<RangeEstimate>,
+}
+
+impl RangeTracker {
+    /// Constructor which uses default values for all optional arguments.
+    pub fn new() -> Self {
+        Self::new_with_gains(None, None)
+    }
+    /// Constructor with all optional arguments.
+    ///
+    /// ## Arguments
+    ///
+    /// * `alpha` - Gain used to correct the distance.
+    ///   Defaults to 0.5.
+    ///   Internally limited to between 0.0 and 1.0.
+    /// * `beta` - Gain used to correct the velocity.
+    ///   Defaults to 0.1.
+    ///   Internally limited to between 0.0 and 1.0.
+    pub fn new_with_gains<A, B>(alpha: A, beta: B) -> Self
+    where
+        A: Into<Option<f32>>,
+        B: Into<Option<f32>>,
+    {
+        Self {
+            alpha: alpha.into().unwrap_or(Self::ALPHA).clamp(0.0, 1.0),
+            beta: beta.into().unwrap_or(Self::BETA).clamp(0.0, 1.0),
+            estimate: None,
+        }
+    }
+    /// Access the latest [RangeEstimate](RangeEstimate) if any.
+    pub fn estimate(&self) -> Option<RangeEstimate> {
+        self.estimate
+    }
+    /// Forgets the tracked state so the next update starts fresh.
+    pub fn reset(&mut self) {
+        self.estimate = None;
+    }
+    /// Updates the tracked state from a new sonar distance.
+    ///
+    /// The tracked state starts over when the previous distance is older than
+    /// [MAX_GAP](RangeTracker::MAX_GAP).
+    ///
+    /// ## Arguments
+    ///
+    /// * `distance` - Measured distance in cm.
+    /// * `timestamp` - Time of the measurement like from
+    ///   [Reading](crate::Reading).
+    pub fn update(&mut self, distance: f32, timestamp: Duration) -> RangeEstimate {
+        let (distance, velocity) = match self.estimate {
+            Some(last) if timestamp > last.timestamp => {
+                let dt = (timestamp - last.timestamp).as_secs_f32();
+                if timestamp - last.timestamp > Self::MAX_GAP {
+                    (distance, 0.0)
+                } else {
+                    let velocity = -last.closing_speed;
+                    let predicted = last.distance + velocity * dt;
+                    let residual = distance - predicted;
+                    (
+                        predicted + self.alpha * residual,
+                        velocity + self.beta * residual / dt,
+                    )
+                }
+            }
+            // Same or older timestamp adds nothing new.
+            Some(last) => return last,
+            None => (distance, 0.0),
+        };
+        let closing_speed = -velocity;
+        let time_to_collision = if closing_speed > Self::MIN_CLOSING_SPEED {
+            Some(Duration::from_secs_f32(distance.max(0.0) / closing_speed))
+        } else {
+            None
+        };
+        let estimate = RangeEstimate {
+            distance,
+            closing_speed,
+            time_to_collision,
+            timestamp,
+        };
+        self.estimate = Some(estimate);
+        estimate
+    }
+    /// Longest gap between distances before the tracked state starts over.
+    pub const MAX_GAP: Duration = Duration::from_secs(1);
+    /// Default gain used to correct the distance.
+    const ALPHA: f32 = 0.5;
+    /// Default gain used to correct the velocity.
+    const BETA: f32 = 0.1;
+    /// Closing speed in cm per second below which there is no
+    /// time-to-collision.
+    const MIN_CLOSING_SPEED: f32 = 1.0;
+}
+
+impl Default for RangeTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
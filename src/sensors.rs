@@ -35,19 +35,40 @@
 // SOFTWARE.
 //! Contains all sensor related components.
 
-use crate::Rr4cResult;
+use crate::{
+    DigitalHealth, EmergencyStop, HealthMonitor, HealthReport, LinePosition, LineState, Obstacle,
+    ObstacleDetector, RangeEstimate, RangeTracker, Rr4cError, Rr4cResult, SonarError, SonarStats,
+};
 use embedded_hal::PwmPin;
 use rppal::gpio::{Gpio, InputPin, Level, OutputPin, Trigger::Both};
+use serde::{Deserialize, Serialize};
 use std::{
-    ops::Add,
-    sync::{atomic::AtomicBool, atomic::Ordering, Arc, Mutex},
-    thread::sleep,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicU32, AtomicU64, Ordering},
+        mpsc::{channel, Receiver},
+        Arc, Mutex, Weak,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
 };
 
 /// Simplifies working with robot's ultrasonic, tracking, and proximity sensors.
 #[derive(Debug)]
 pub struct Sensors {
+    /// Emergency stop latch reported in the `*_postback_with_estop()` responses.
+    emergency_stop: EmergencyStop,
+    /// Monotonic time the sensors were created used for snapshot timestamps.
+    epoch: Instant,
+    /// Shared with the interrupts to deliver [SensorEvent](SensorEvent)s.
+    events: Arc<SensorEvents>,
+    /// Additional [Sonar](Sonar)s beyond the built-in one.
+    extra_sonars: Vec<Sonar>,
+    /// Used to track if the background filter thread has been started.
+    filtering: bool,
+    /// Checks sensor statistics for problems.
+    health_monitor: HealthMonitor,
     /// Instance of [IrProximity](IrProximity).
     ir_proximity: IrProximity,
     /// Instance of [InputPin] connected to left infrared (IR) proximity pin.
@@ -68,8 +89,20 @@ pub struct Sensors {
     ///
     /// [InputPin]: rppal::gpio::InputPin
     ldr_right: InputPin,
+    /// Estimates the line position from line tracking sensors data.
+    line_position: LinePosition,
+    /// Fuses sonar and IR proximity sensors data.
+    obstacle_detector: ObstacleDetector,
+    /// Which level each digital sensor uses to signal detection.
+    polarity: SensorPolarity,
+    /// Tracks closing speed from sonar readings.
+    range_tracker: RangeTracker,
     /// Instance of [Sonar](Sonar).
     sonar: Sonar,
+    /// Used to track if active sonar pinging has been turned on.
+    sonar_active: bool,
+    /// Staggers active pings when there is more than one sonar.
+    sonar_scheduler: Option<SonarScheduler>,
     /// Instance of [LineTracking](LineTracking).
     tracking: LineTracking,
     /// Instance of [InputPin] connected to left line tracking input 1 pin.
@@ -112,24 +145,101 @@ impl Sensors {
         T: Into<Option<f32>>,
         H: Into<Option<f32>>,
     {
+        Self::new_with_kitchen_sink(temperature, humidity, None)
+    }
+    /// Constructor with all optional arguments.
+    ///
+    /// ## Arguments
+    ///
+    /// The `temperature` and `humidity` values are used to increase the
+    /// accuracy of ultrasonic distance measurements.
+    ///
+    /// * `temperature` - Temperature in °C.
+    ///   A `None` value will set a default of 20°C.
+    ///   Temperatures are limited to between -40 and +65.5°C.
+    /// * `humidity` - Relative humidity as %.
+    ///   A `None` value will set a default of 40%.
+    /// * `polarity` - Which level each digital sensor uses to signal
+    ///   detection.
+    ///   A `None` value will use the levels of the stock Yahboom sensors.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018, no_run
+    /// # #[cfg(target_arch = "arm")]
+    /// # {
+    /// use rust_rpi_4wd_car::{Polarity, Result, SensorPolarity, Sensors};
+    ///
+    /// fn main() -> Result {
+    ///     // Replacement IR modules pull their outputs high on detection.
+    ///     let polarity = SensorPolarity {
+    ///         ir_left: Polarity::ActiveHigh,
+    ///         ir_right: Polarity::ActiveHigh,
+    ///         ..Default::default()
+    ///     };
+    ///     let sensors = Sensors::new_with_kitchen_sink(None, None, polarity)?;
+    ///     println!("{:?}", sensors.ir_proximity());
+    ///     Ok(())
+    /// }
+    /// # }
+    /// ```
+    pub fn new_with_kitchen_sink<T, H, P>(
+        temperature: T,
+        humidity: H,
+        polarity: P,
+    ) -> Rr4cResult<Self>
+    where
+        T: Into<Option<f32>>,
+        H: Into<Option<f32>>,
+        P: Into<Option<SensorPolarity>>,
+    {
+        let polarity = polarity.into().unwrap_or_default();
         let gpio = Gpio::new()?;
+        let epoch = Instant::now();
+        let ir_proximity = IrProximity::new();
+        let tracking = LineTracking::new();
+        let events = Arc::new(SensorEvents::new(
+            epoch,
+            [
+                ir_proximity.left.clone(),
+                ir_proximity.right.clone(),
+                tracking.left1.clone(),
+                tracking.left2.clone(),
+                tracking.right1.clone(),
+                tracking.right2.clone(),
+            ],
+        ));
         // IR
-        let (ir_left, ir_right, ir_proximity) = Sensors::ir_init(&gpio)?;
+        let (ir_left, ir_right) = Sensors::ir_init(&gpio, &events, &polarity)?;
         // LDR Tracking
         let ldr_left = gpio.get(Self::LDR_LEFT)?.into_input();
         let ldr_right = gpio.get(Self::LDR_RIGHT)?.into_input();
         // line tracking
-        let (track_left1, track_left2, track_right1, track_right2, tracking) =
-            Sensors::line_tracking_init(&gpio)?;
+        let (track_left1, track_left2, track_right1, track_right2) =
+            Sensors::line_tracking_init(&gpio, &events, &polarity)?;
         // Sonar
-        let sonar = Sonar::new_with_temp_hum(temperature, humidity)?;
+        let mut sonar = Sonar::new_with_temp_hum(temperature, humidity)?;
+        // Share the time base so sonar and digital sensor timestamps line up.
+        sonar.epoch = epoch;
         Ok(Self {
+            emergency_stop: EmergencyStop::new(),
+            epoch,
+            events,
+            extra_sonars: Vec::new(),
+            filtering: false,
+            health_monitor: HealthMonitor::new(),
             ir_proximity,
             ir_left,
             ir_right,
             ldr_left,
             ldr_right,
+            line_position: LinePosition::new(),
+            obstacle_detector: ObstacleDetector::new(),
+            polarity,
+            range_tracker: RangeTracker::new(),
             sonar,
+            sonar_active: false,
+            sonar_scheduler: None,
             tracking,
             track_left1,
             track_left2,
@@ -137,43 +247,130 @@ impl Sensors {
             track_right2,
         })
     }
+    /// Adds another ultrasonic sensor.
+    ///
+    /// Once there is more than one sonar, active pinging takes turns between
+    /// them so one can't hear the echo from another.
+    /// Returns the index used with [`sonar_mut()`] to access it.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sonar` - The [Sonar](Sonar) to add.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018, no_run
+    /// # #[cfg(target_arch = "arm")]
+    /// # {
+    /// use rust_rpi_4wd_car::{Result, Sensors, Sonar};
+    ///
+    /// fn main() -> Result {
+    ///     let mut sensors = Sensors::new()?;
+    ///     let rear = sensors.add_sonar(Sonar::new_with_kitchen_sink(5, 6, None, None)?)?;
+    ///     sensors.sonar_active(true)?;
+    ///     if let Some(sonar) = sensors.sonar_mut(rear) {
+    ///         println!("{:?}", sonar.distance());
+    ///     }
+    ///     Ok(())
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// [`sonar_mut()`]: Sensors::sonar_mut()
+    pub fn add_sonar(&mut self, mut sonar: Sonar) -> Rr4cResult<usize> {
+        sonar.epoch = self.epoch;
+        // Its own pinging would be heard by the other sonars.
+        sonar.set_sonar_active(false)?;
+        self.unschedule_sonars()?;
+        self.extra_sonars.push(sonar);
+        self.schedule_sonars()?;
+        Ok(self.extra_sonars.len())
+    }
     /// Produces an Rr4c compatible postback response of sensor data.
     pub fn as_rr_postback(&mut self) -> String {
-        let distance = self.sonar.distance().unwrap_or(-1.0);
-        let (ir_l, ir_r) = self.ir_proximity();
-        let (ldr_l, ldr_r) = self.ldr_tracking();
-        let (line_l1, line_l2, line_r1, line_r2) = self.line_tracking();
-        format!(
-            "$RR4W,SNR{},LNF{}:{}:{}:{},IRP{}:{},LDR{}:{}#",
-            distance as i16,
-            line_l1 as u8,
-            line_l2 as u8,
-            line_r1 as u8,
-            line_r2 as u8,
-            ir_l as u8,
-            ir_r as u8,
-            ldr_l as u8,
-            ldr_r as u8
-        )
+        self.snapshot().as_rr_postback()
+    }
+    /// Produces an Rr4c postback response with the emergency stop latch
+    /// appended.
+    ///
+    /// See [`SensorSnapshot::as_rr_postback_with_estop()`] for the format.
+    pub fn as_rr_postback_with_estop(&mut self) -> String {
+        self.snapshot().as_rr_postback_with_estop()
     }
     /// Produces an Yahboom compatible postback response of sensor data.
     pub fn as_yb_postback(&mut self) -> String {
-        let distance = self.sonar.distance().unwrap_or(-1.0);
-        let (ir_l, ir_r) = self.ir_proximity();
-        let (ldr_l, ldr_r) = self.ldr_tracking();
-        let (line_l1, line_l2, line_r1, line_r2) = self.line_tracking();
-        format!(
-            "$4WD,CSB{},PV8.3,GS0,LF{}{}{}{},HW{}{},GM{}{}#",
-            distance as i16,
-            line_l1 as u8,
-            line_l2 as u8,
-            line_r1 as u8,
-            line_r2 as u8,
-            ir_l as u8,
-            ir_r as u8,
-            ldr_l as u8,
-            ldr_r as u8
-        )
+        self.snapshot().as_yb_postback()
+    }
+    /// Produces an Yahboom postback response with the emergency stop latch
+    /// appended.
+    ///
+    /// See [`SensorSnapshot::as_yb_postback_with_estop()`] for the format.
+    pub fn as_yb_postback_with_estop(&mut self) -> String {
+        self.snapshot().as_yb_postback_with_estop()
+    }
+    /// Clears all event subscribers and callbacks.
+    pub fn clear_subscribers(&self) {
+        self.events.clear_subscribers();
+    }
+    /// Access the filter being used for a digital sensor.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sensor` - Which [DigitalSensor](DigitalSensor) to access.
+    pub fn filter(&self, sensor: DigitalSensor) -> DigitalFilter {
+        self.events.filters.lock().expect("Someone broke the lock")[sensor as usize]
+    }
+    /// Used to acquire the filtered distance in cm to anything in front of the
+    /// robot.
+    ///
+    /// Takes a new sonar measurement.
+    /// Unlike [`sonar_distance()`] an echo which is out of range still gives
+    /// its distance so something too near to measure isn't missed.
+    /// `None` is returned when nothing was heard.
+    ///
+    /// [`sonar_distance()`]: Sensors::sonar_distance()
+    pub fn front_distance(&mut self) -> Option<f32> {
+        match self.sonar_update() {
+            Ok(reading) => Some(reading.filtered),
+            // Too near still needs to count.
+            Err(SonarError::OutOfRange(distance)) => Some(distance),
+            Err(_) => None,
+        }
+    }
+    /// Produces a [HealthReport](HealthReport) from the built-in sonar and
+    /// digital sensors statistics with warnings for any sensors that look
+    /// stuck or dead.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018, no_run
+    /// # #[cfg(target_arch = "arm")]
+    /// # {
+    /// use rust_rpi_4wd_car::{Result, Sensors};
+    ///
+    /// fn main() -> Result {
+    ///     let mut sensors = Sensors::new()?;
+    ///     let report = sensors.health();
+    ///     for warning in &report.warnings {
+    ///         eprintln!("{}", warning);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// # }
+    /// ```
+    pub fn health(&mut self) -> HealthReport {
+        let now = self.epoch.elapsed();
+        let digital = DigitalSensor::ALL.map(|sensor| {
+            let i = sensor as usize;
+            let edge = Duration::from_micros(self.events.raw_edges[i].load(Ordering::Acquire));
+            DigitalHealth {
+                sensor,
+                state: self.events.raw[i].load(Ordering::Acquire),
+                since_edge: now.saturating_sub(edge),
+                raw_transitions: self.events.raw_transitions[i].load(Ordering::Acquire),
+            }
+        });
+        self.health_monitor.check(now, self.sonar.stats(), digital)
     }
     /// Used to acquire latest infrared (IR) proximity sensors data.
     pub fn ir_proximity(&self) -> (bool, bool) {
@@ -182,14 +379,60 @@ impl Sensors {
             self.ir_proximity.right.load(Ordering::Acquire),
         )
     }
+    /// Used to check if active background sonar pinging is turned on.
+    pub fn is_sonar_active(&self) -> bool {
+        self.sonar_active
+    }
+    /// Used to acquire the filtered distance in cm to anything in front of the
+    /// robot without waiting on an echo.
+    ///
+    /// With active sonar any readings already queued are used and otherwise
+    /// the latest distance from the [RangeTracker](RangeTracker) is given
+    /// while it is still fresh.
+    /// Without active sonar this takes a new measurement the same as
+    /// [`front_distance()`].
+    /// `None` is returned when nothing was heard recently.
+    ///
+    /// [`front_distance()`]: Sensors::front_distance()
+    pub fn latest_front_distance(&mut self) -> Option<f32> {
+        if !self.sonar_active {
+            return self.front_distance();
+        }
+        match self.sonar.latest_distance() {
+            Ok(reading) => {
+                self.range_tracker
+                    .update(reading.filtered, reading.timestamp);
+                Some(reading.filtered)
+            }
+            Err(SonarError::OutOfRange(distance)) => Some(distance),
+            Err(_) => {
+                let (_, stale_age) = Sonar::wait_times(self.sonar.ping_period());
+                let now = self.epoch.elapsed();
+                self.range_tracker
+                    .estimate()
+                    .filter(|estimate| now.saturating_sub(estimate.timestamp) <= stale_age)
+                    .map(|estimate| estimate.distance)
+            }
+        }
+    }
     /// Used to acquire latest light dependant resister (LDR) tracking sensors
     /// data.
     pub fn ldr_tracking(&self) -> (bool, bool) {
         (
-            self.ldr_left.read() == Level::High,
-            self.ldr_right.read() == Level::High,
+            self.polarity.ldr_left.is_active(self.ldr_left.read()),
+            self.polarity.ldr_right.is_active(self.ldr_right.read()),
         )
     }
+    /// Used to acquire the estimated position of the line from the latest
+    /// line tracking sensors data.
+    ///
+    /// Returns the error from -1.0 (left) to 1.0 (right) and
+    /// [LineState](LineState).
+    /// See [LinePosition](LinePosition) for more info.
+    pub fn line_position(&mut self) -> (f32, LineState) {
+        let tracking = self.line_tracking();
+        self.line_position.update(tracking)
+    }
     /// Used to acquire latest line tracking sensors data.
     pub fn line_tracking(&self) -> (bool, bool, bool, bool) {
         (
@@ -199,69 +442,413 @@ impl Sensors {
             self.tracking.right2.load(Ordering::Acquire),
         )
     }
+    /// Used to acquire the fused [Obstacle](Obstacle) state from the front sonar
+    /// and infrared (IR) proximity sensors.
+    ///
+    /// Takes a new sonar measurement.
+    /// See [ObstacleDetector](ObstacleDetector) for more info.
+    pub fn obstacle(&mut self) -> Obstacle {
+        let distance = self.front_distance();
+        let ir = self.ir_proximity();
+        self.obstacle_detector.update(distance, ir, Instant::now())
+    }
+    /// Registers a callback which is called with each
+    /// [SensorEvent](SensorEvent).
+    ///
+    /// __NOTE:__ Callbacks are called from the interrupt threads so should
+    /// return quickly.
+    ///
+    /// ## Arguments
+    ///
+    /// * `callback` - Called every time one of the digital sensors changes
+    ///   state.
+    pub fn on_event<F>(&self, callback: F)
+    where
+        F: FnMut(SensorEvent) + Send + 'static,
+    {
+        self.events.on_event(callback);
+    }
+    /// Access which level each digital sensor uses to signal detection.
+    pub fn polarity(&self) -> SensorPolarity {
+        self.polarity
+    }
+    /// Access the latest estimated distance, closing speed and time-to-collision
+    /// from the built-in sonar if any.
+    ///
+    /// Updated every time a sonar measurement is taken through `Sensors`,
+    /// check the timestamp to see how current it is.
+    /// See [RangeTracker](RangeTracker) for more info.
+    pub fn range_estimate(&self) -> Option<RangeEstimate> {
+        self.range_tracker.estimate()
+    }
+    /// Access the unfiltered state of a digital sensor for diagnostics.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sensor` - Which [DigitalSensor](DigitalSensor) to access.
+    pub fn raw_state(&self, sensor: DigitalSensor) -> bool {
+        self.events.raw[sensor as usize].load(Ordering::Acquire)
+    }
+    /// Access the number of unfiltered changes of state seen from a digital
+    /// sensor for diagnostics.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sensor` - Which [DigitalSensor](DigitalSensor) to access.
+    pub fn raw_transitions(&self, sensor: DigitalSensor) -> u64 {
+        self.events.raw_transitions[sensor as usize].load(Ordering::Acquire)
+    }
+    /// Reads all of the sensors once into a [SensorSnapshot](SensorSnapshot).
+    pub fn snapshot(&mut self) -> SensorSnapshot {
+        let sonar = self.sonar_update().ok().map(|r| r.filtered);
+        let (ir_left, ir_right) = self.ir_proximity();
+        let (ldr_left, ldr_right) = self.ldr_tracking();
+        let (line_left1, line_left2, line_right1, line_right2) = self.line_tracking();
+        SensorSnapshot {
+            timestamp: self.epoch.elapsed(),
+            estop: self.emergency_stop.is_engaged(),
+            sonar,
+            ir_left,
+            ir_right,
+            ldr_left,
+            ldr_right,
+            line_left1,
+            line_left2,
+            line_right1,
+            line_right2,
+        }
+    }
+    /// Subscribes to the changes of state of the digital sensors.
+    ///
+    /// The subscription is removed once the returned `Receiver` is dropped.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018, no_run
+    /// # #[cfg(target_arch = "arm")]
+    /// # {
+    /// use rust_rpi_4wd_car::{Result, Sensors};
+    ///
+    /// fn main() -> Result {
+    ///     let sensors = Sensors::new()?;
+    ///     let events = sensors.subscribe();
+    ///     for event in events.iter().take(10) {
+    ///         println!("{:?} is now {} at {:?}", event.sensor, event.state, event.timestamp);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// # }
+    /// ```
+    pub fn subscribe(&self) -> Receiver<SensorEvent> {
+        self.events.subscribe()
+    }
+    /// Access the number of changes of state seen from a digital sensor.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sensor` - Which [DigitalSensor](DigitalSensor) to access.
+    pub fn transitions(&self, sensor: DigitalSensor) -> u64 {
+        self.events.transitions(sensor)
+    }
+    /// Sets the emergency stop latch reported by the `*_postback_with_estop()`
+    /// methods.
+    ///
+    /// ## Arguments
+    /// * `estop` - Latch usually shared with [Motors](crate::Motors).
+    pub fn set_emergency_stop(&mut self, estop: EmergencyStop) {
+        self.emergency_stop = estop;
+    }
+    /// Sets a source that is periodically read for the temperature and
+    /// humidity used to compensate ultrasonic distance measurements.
+    ///
+    /// The source is shared by every sonar added so far.
+    /// Sonars added later keep their own settings.
+    /// See [`Sonar::set_environment_source()`] for more info.
+    ///
+    /// [`Sonar::set_environment_source()`]: Sonar::set_environment_source()
+    pub fn set_environment_source<S>(&mut self, source: S)
+    where
+        S: Into<Option<Box<dyn EnvironmentSource>>>,
+    {
+        let shared = source.into().map(SharedEnvironment::new);
+        for sonar in std::iter::once(&mut self.sonar).chain(&mut self.extra_sonars) {
+            let source = shared.clone();
+            sonar.set_environment_source(source.map(|s| Box::new(s) as Box<dyn EnvironmentSource>));
+        }
+    }
+    /// Sets the [HealthMonitor](HealthMonitor) used by [`health()`].
+    ///
+    /// Use [`HealthMonitor::new_with_thresholds()`] to change when warnings
+    /// are raised.
+    ///
+    /// [`health()`]: Sensors::health()
+    /// [`HealthMonitor::new_with_thresholds()`]: HealthMonitor::new_with_thresholds()
+    pub fn set_health_monitor(&mut self, monitor: HealthMonitor) {
+        self.health_monitor = monitor;
+    }
+    /// Sets the [ObstacleDetector](ObstacleDetector) used by [`obstacle()`].
+    ///
+    /// Use [`ObstacleDetector::new_with_thresholds()`] to change the
+    /// thresholds and hysteresis.
+    ///
+    /// [`obstacle()`]: Sensors::obstacle()
+    /// [`ObstacleDetector::new_with_thresholds()`]: ObstacleDetector::new_with_thresholds()
+    pub fn set_obstacle_detector(&mut self, detector: ObstacleDetector) {
+        self.obstacle_detector = detector;
+    }
+    /// Sets the [RangeTracker](RangeTracker) used by [`range_estimate()`].
+    ///
+    /// Use [`RangeTracker::new_with_gains()`] to change the gains.
+    ///
+    /// [`range_estimate()`]: Sensors::range_estimate()
+    /// [`RangeTracker::new_with_gains()`]: RangeTracker::new_with_gains()
+    pub fn set_range_tracker(&mut self, tracker: RangeTracker) {
+        self.range_tracker = tracker;
+    }
+    /// Sets the filter stage options used for ultrasonic distance
+    /// measurements.
+    ///
+    /// See [`Sonar::set_filter()`] for more info.
+    ///
+    /// [`Sonar::set_filter()`]: Sonar::set_filter()
+    pub fn set_sonar_filter(&mut self, filter: SonarFilter) {
+        self.sonar.set_filter(filter);
+    }
+    /// Sets the rate used for active sonar pinging of every ultrasonic sensor.
+    ///
+    /// With more than one sonar the rate is lowered as needed so each one
+    /// gets its own time slot.
+    /// See [`Sonar::set_ping_rate()`] for more info.
+    ///
+    /// [`Sonar::set_ping_rate()`]: Sonar::set_ping_rate()
+    pub fn set_sonar_ping_rate<R: Into<Option<f64>>>(&mut self, rate: R) -> Rr4cResult<()> {
+        let rate = rate.into();
+        self.unschedule_sonars()?;
+        self.sonar.set_ping_rate(rate)?;
+        for sonar in &mut self.extra_sonars {
+            sonar.set_ping_rate(rate)?;
+        }
+        self.schedule_sonars()
+    }
+    /// Sets the temperature and humidity used to compensate ultrasonic
+    /// distance measurements at runtime.
+    ///
+    /// Applies to every sonar added so far.
+    /// Sonars added later keep their own settings.
+    /// See [`Sonar::set_temp_hum()`] for more info.
+    ///
+    /// [`Sonar::set_temp_hum()`]: Sonar::set_temp_hum()
+    pub fn set_temp_hum<T, H>(&mut self, temperature: T, humidity: H)
+    where
+        T: Into<Option<f32>>,
+        H: Into<Option<f32>>,
+    {
+        let (temperature, humidity) = (temperature.into(), humidity.into());
+        for sonar in std::iter::once(&mut self.sonar).chain(&mut self.extra_sonars) {
+            sonar.set_temp_hum(temperature, humidity);
+        }
+    }
+    /// Sets the filter used to clean up the state of a digital sensor before
+    /// it is stored and any events are delivered.
+    ///
+    /// Filtered sensors are sampled every millisecond by a background thread
+    /// while unfiltered ones are updated directly from their interrupts.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sensor` - Which [DigitalSensor](DigitalSensor) to filter.
+    /// * `filter` - The [DigitalFilter](DigitalFilter) to use.
+    ///   Use `DigitalFilter::default()` to turn off filtering.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018, no_run
+    /// # #[cfg(target_arch = "arm")]
+    /// # {
+    /// use rust_rpi_4wd_car::{DigitalFilter, DigitalSensor, Result, Sensors};
+    /// use std::time::Duration;
+    ///
+    /// fn main() -> Result {
+    ///     let mut sensors = Sensors::new()?;
+    ///     let filter = DigitalFilter {
+    ///         stable_for: Some(Duration::from_millis(5)),
+    ///         ..Default::default()
+    ///     };
+    ///     sensors.set_filter(DigitalSensor::LineLeft1, filter);
+    ///     Ok(())
+    /// }
+    /// # }
+    /// ```
+    pub fn set_filter(&mut self, sensor: DigitalSensor, filter: DigitalFilter) {
+        let i = sensor as usize;
+        self.events.filters.lock().expect("Someone broke the lock")[i] = filter;
+        self.events.filtered[i].store(!filter.is_none(), Ordering::Release);
+        if filter.is_none() {
+            // Catch up with anything the filter was holding back.
+            let raw = self.events.raw[i].load(Ordering::Acquire);
+            self.events.update(sensor, raw);
+        } else if !self.filtering {
+            self.filtering = true;
+            let events = Arc::downgrade(&self.events);
+            thread::spawn(move || SensorEvents::filter_loop(events));
+        }
+    }
     /// Enable/disable active background sonar pinging.
     ///
     /// ## Arguments
     ///
     /// * `enable` -Turns on active background sonar pinging when `true`.
-    pub fn sonar_active(&mut self, enable: bool) {
-        self.sonar.set_sonar_active(enable);
+    pub fn sonar_active(&mut self, enable: bool) -> Rr4cResult<()> {
+        self.sonar_active = enable;
+        self.unschedule_sonars()?;
+        self.schedule_sonars()
+    }
+    /// Access the number of ultrasonic sensors including the built-in one.
+    pub fn sonar_count(&self) -> usize {
+        self.extra_sonars.len() + 1
+    }
+    /// Used to acquire ultrasonic distance measurement.
+    ///
+    /// See [`Sonar::distance()`] for more info.
+    ///
+    /// [`Sonar::distance()`]: Sonar::distance()
+    pub fn sonar_distance(&mut self) -> Result<Reading, SonarError> {
+        self.sonar_update()
+    }
+    /// Access an ultrasonic sensor.
+    ///
+    /// While several sonars are active they are pinged in turn so their
+    /// [`Sonar::set_ping_rate()`] and [`Sonar::set_sonar_active()`] return
+    /// [Rr4cError::SonarScheduled](Rr4cError::SonarScheduled).
+    /// Use [`set_sonar_ping_rate()`] and [`sonar_active()`] instead.
+    ///
+    /// ## Arguments
+    ///
+    /// * `index` - 0 for the built-in sonar or the index returned from
+    ///   [`add_sonar()`].
+    ///
+    /// [`add_sonar()`]: Sensors::add_sonar()
+    /// [`set_sonar_ping_rate()`]: Sensors::set_sonar_ping_rate()
+    /// [`sonar_active()`]: Sensors::sonar_active()
+    /// [`Sonar::set_ping_rate()`]: Sonar::set_ping_rate()
+    /// [`Sonar::set_sonar_active()`]: Sonar::set_sonar_active()
+    pub fn sonar_mut(&mut self, index: usize) -> Option<&mut Sonar> {
+        match index {
+            0 => Some(&mut self.sonar),
+            _ => self.extra_sonars.get_mut(index - 1),
+        }
+    }
+    /// Takes a measurement from the built-in sonar and feeds it to the range
+    /// tracker.
+    fn sonar_update(&mut self) -> Result<Reading, SonarError> {
+        let reading = self.sonar.distance()?;
+        self.range_tracker
+            .update(reading.filtered, reading.timestamp);
+        Ok(reading)
+    }
+    /// Starts active pinging to match the number of sonars when it is
+    /// enabled.
+    ///
+    /// A single sonar does its own pinging while several are handed to a
+    /// [SonarScheduler](SonarScheduler).
+    /// Expects [`unschedule_sonars()`] to have been called first.
+    ///
+    /// [`unschedule_sonars()`]: Sensors::unschedule_sonars()
+    fn schedule_sonars(&mut self) -> Rr4cResult<()> {
+        let enable = self.sonar_active;
+        if self.extra_sonars.is_empty() {
+            return self.sonar.set_sonar_active(enable);
+        }
+        if !enable {
+            return Ok(());
+        }
+        // Each sonar is pinged once per round of slots.
+        let sonars = self.sonar_count();
+        let period = SonarScheduler::slot(sonars, self.sonar.ping_rate) * sonars as u32;
+        self.sonar.set_scheduled(Some(period))?;
+        for sonar in &mut self.extra_sonars {
+            sonar.set_scheduled(Some(period))?;
+        }
+        let triggers = std::iter::once(&self.sonar)
+            .chain(&self.extra_sonars)
+            .map(|sonar| sonar.trigger.clone())
+            .collect();
+        self.sonar_scheduler = Some(SonarScheduler::new(triggers, self.sonar.ping_rate));
+        Ok(())
     }
-    /// Used to acquire ultrasonic distance measurement if available.
-    pub fn sonar_distance(&mut self) -> Option<f32> {
-        self.sonar.distance()
+    /// Stops any [SonarScheduler](SonarScheduler) and hands the sonars back
+    /// so their own settings can be changed.
+    fn unschedule_sonars(&mut self) -> Rr4cResult<()> {
+        // Dropping the scheduler stops it.
+        self.sonar_scheduler = None;
+        self.sonar.set_scheduled(None)?;
+        for sonar in &mut self.extra_sonars {
+            sonar.set_scheduled(None)?;
+        }
+        Ok(())
     }
     /// Initialize all infrared (IR) proximity sensors related pins and data.
-    fn ir_init(gpio: &Gpio) -> Rr4cResult<(InputPin, InputPin, IrProximity)> {
+    fn ir_init(
+        gpio: &Gpio,
+        events: &Arc<SensorEvents>,
+        polarity: &SensorPolarity,
+    ) -> Rr4cResult<(InputPin, InputPin)> {
         let mut ir_left = gpio.get(Self::INFRARED_LEFT)?.into_input();
         let mut ir_right = gpio.get(Self::INFRARED_RIGHT)?.into_input();
-        let ir_proximity = IrProximity::new();
-        let sense = ir_proximity.left.clone();
-        sense.store(ir_left.is_low(), Ordering::SeqCst);
-        ir_left.set_async_interrupt(Both, move |level| {
-            sense.store(level == Level::Low, Ordering::Release);
-        })?;
-        let sense = ir_proximity.right.clone();
-        sense.store(ir_right.is_low(), Ordering::SeqCst);
-        ir_right.set_async_interrupt(Both, move |level| {
-            sense.store(level == Level::Low, Ordering::Release);
-        })?;
-        Ok((ir_left, ir_right, ir_proximity))
+        Self::watch(&mut ir_left, DigitalSensor::IrLeft, events, polarity)?;
+        Self::watch(&mut ir_right, DigitalSensor::IrRight, events, polarity)?;
+        Ok((ir_left, ir_right))
     }
     /// Initialize all line tracking sensors related pins and data.
-    fn line_tracking_init(gpio: &Gpio) -> Rr4cResult<LineInitResult> {
+    fn line_tracking_init(
+        gpio: &Gpio,
+        events: &Arc<SensorEvents>,
+        polarity: &SensorPolarity,
+    ) -> Rr4cResult<LineInitResult> {
         let mut track_left1 = gpio.get(Sensors::LINE_LEFT_1)?.into_input();
         let mut track_left2 = gpio.get(Sensors::LINE_LEFT_2)?.into_input();
         let mut track_right1 = gpio.get(Sensors::LINE_RIGHT_1)?.into_input();
         let mut track_right2 = gpio.get(Sensors::LINE_RIGHT_2)?.into_input();
-        let tracking = LineTracking::new();
-        let sense = tracking.left1.clone();
-        sense.store(track_left1.is_low(), Ordering::SeqCst);
-        track_left1.set_async_interrupt(Both, move |level| {
-            sense.store(level == Level::Low, Ordering::Release);
-        })?;
-        let sense = tracking.left2.clone();
-        sense.store(track_left2.is_low(), Ordering::SeqCst);
-        track_left2.set_async_interrupt(Both, move |level| {
-            sense.store(level == Level::Low, Ordering::Release);
-        })?;
-        let sense = tracking.right1.clone();
-        sense.store(track_right1.is_low(), Ordering::SeqCst);
-        track_right1.set_async_interrupt(Both, move |level| {
-            sense.store(level == Level::Low, Ordering::Release);
-        })?;
-        let sense = tracking.right2.clone();
-        sense.store(track_right2.is_low(), Ordering::SeqCst);
-        track_right2.set_async_interrupt(Both, move |level| {
-            sense.store(level == Level::Low, Ordering::Release);
+        Self::watch(&mut track_left1, DigitalSensor::LineLeft1, events, polarity)?;
+        Self::watch(&mut track_left2, DigitalSensor::LineLeft2, events, polarity)?;
+        Self::watch(
+            &mut track_right1,
+            DigitalSensor::LineRight1,
+            events,
+            polarity,
+        )?;
+        Self::watch(
+            &mut track_right2,
+            DigitalSensor::LineRight2,
+            events,
+            polarity,
+        )?;
+        Ok((track_left1, track_left2, track_right1, track_right2))
+    }
+    /// Sets up the interrupt used to track the state of a digital sensor.
+    ///
+    /// ## Arguments
+    ///
+    /// * `pin` - Input pin of the sensor.
+    /// * `sensor` - Which [DigitalSensor](DigitalSensor) it is.
+    /// * `events` - Used to store the state and deliver
+    ///   [SensorEvent](SensorEvent)s.
+    /// * `polarity` - Which level each sensor uses to signal detection.
+    fn watch(
+        pin: &mut InputPin,
+        sensor: DigitalSensor,
+        events: &Arc<SensorEvents>,
+        polarity: &SensorPolarity,
+    ) -> Rr4cResult<()> {
+        let events = events.clone();
+        let polarity = polarity.of(sensor);
+        let state = polarity.is_active(pin.read());
+        events.raw[sensor as usize].store(state, Ordering::SeqCst);
+        events.senses[sensor as usize].store(state, Ordering::SeqCst);
+        pin.set_async_interrupt(Both, move |level| {
+            events.raw_update(sensor, polarity.is_active(level));
         })?;
-        Ok((
-            track_left1,
-            track_left2,
-            track_right1,
-            track_right2,
-            tracking,
-        ))
+        Ok(())
     }
     /// Left infrared obstacle input pin #.
     const INFRARED_LEFT: u8 = 12;
@@ -281,122 +868,652 @@ impl Sensors {
     const LINE_RIGHT_2: u8 = 18;
 }
 
-/// Simple overwriting ring buffer used to queue ultrasonic distance readings
-/// from active sonar.
-#[derive(Clone, Copy, Debug)]
-struct CircularQueue {
-    depth: usize,
-    read: usize,
-    queue: [f32; 6],
-    write: usize,
-}
-
-impl CircularQueue {
-    pub fn new() -> Self {
-        Self {
-            depth: 0,
-            read: 0,
-            queue: [0.0; 6],
-            write: 0,
-        }
-    }
-    pub fn pop(&mut self) -> Option<f32> {
-        // eprintln!("read: {}, write: {}", self.read, self.write);
-        // If the reader has caught up the writer return None.
-        if self.depth == 0 {
-            None
-        } else {
-            let value = self.queue[self.read];
-            self.read = self.read.saturating_add(1) % 6;
-            self.depth = self.depth.saturating_sub(1);
-            Some(value)
-        }
-    }
-    pub fn push<V: Into<f32>>(&mut self, value: V) {
-        self.queue[self.write] = value.into();
-        let inc = self.depth.saturating_add(1).min(6);
-        // If the writer is starting to lap the reader move the read forward to
-        // oldest write
-        if self.depth == inc {
-            self.read = self.read.saturating_add(1) % 6;
-        } else {
-            self.depth = inc;
-        }
-        self.write = self.write.saturating_add(1) % 6;
-    }
+/// Identifies one of the interrupt driven digital sensors.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum DigitalSensor {
+    /// Left infrared (IR) proximity sensor.
+    IrLeft,
+    /// Right infrared (IR) proximity sensor.
+    IrRight,
+    /// Left line tracking sensor 1.
+    LineLeft1,
+    /// Left line tracking sensor 2.
+    LineLeft2,
+    /// Right line tracking sensor 1.
+    LineRight1,
+    /// Right line tracking sensor 2.
+    LineRight2,
 }
 
-/// Holds data related to infrared (IR) proximity sensors.
-#[derive(Debug)]
-pub struct IrProximity {
-    pub left: Arc<AtomicBool>,
-    pub right: Arc<AtomicBool>,
+impl DigitalSensor {
+    /// All of the digital sensors.
+    pub const ALL: [DigitalSensor; 6] = [
+        DigitalSensor::IrLeft,
+        DigitalSensor::IrRight,
+        DigitalSensor::LineLeft1,
+        DigitalSensor::LineLeft2,
+        DigitalSensor::LineRight1,
+        DigitalSensor::LineRight2,
+    ];
 }
 
-impl IrProximity {
-    pub fn new() -> Self {
-        let left = Arc::new(AtomicBool::new(false));
-        let right = Arc::new(AtomicBool::new(false));
-        Self { left, right }
-    }
+/// A timestamped change of state from one of the digital sensors.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SensorEvent {
+    /// Which sensor changed.
+    pub sensor: DigitalSensor,
+    /// New state of the sensor with `true` meaning something was detected.
+    pub state: bool,
+    /// Monotonic time since the [Sensors](Sensors) were created.
+    pub timestamp: Duration,
 }
 
-/// Holds data related to line tracking sensors.
-#[derive(Debug)]
-pub struct LineTracking {
-    pub left1: Arc<AtomicBool>,
-    pub left2: Arc<AtomicBool>,
-    pub right1: Arc<AtomicBool>,
-    pub right2: Arc<AtomicBool>,
+/// Shared data used to store the state of the digital sensors and deliver
+/// [SensorEvent](SensorEvent)s from the interrupt threads.
+///
+/// All arrays are indexed by [DigitalSensor](DigitalSensor).
+pub(crate) struct SensorEvents {
+    /// Monotonic time the sensors were created used for event timestamps.
+    epoch: Instant,
+    /// Filters used for each sensor.
+    filters: Mutex<[DigitalFilter; 6]>,
+    /// Used to track which sensors are being filtered.
+    filtered: [AtomicBool; 6],
+    /// Latest unfiltered state of each sensor.
+    raw: [AtomicBool; 6],
+    /// Monotonic time in µs since `epoch` of the latest unfiltered change of
+    /// state for each sensor.
+    raw_edges: [AtomicU64; 6],
+    /// Count of unfiltered changes of state for each sensor.
+    raw_transitions: [AtomicU64; 6],
+    /// Latest filtered state of each sensor shared with the
+    /// [IrProximity](IrProximity) and [LineTracking](LineTracking) structures.
+    senses: [Arc<AtomicBool>; 6],
+    /// Subscriber callbacks which are removed when they return `false`.
+    pub(crate) subscribers: Mutex<Vec<Subscriber>>,
+    /// Count of filtered changes of state for each sensor.
+    transitions: [AtomicU64; 6],
 }
 
-impl LineTracking {
-    pub fn new() -> Self {
-        let left1 = Arc::new(AtomicBool::new(false));
-        let right1 = Arc::new(AtomicBool::new(false));
-        let left2 = Arc::new(AtomicBool::new(false));
-        let right2 = Arc::new(AtomicBool::new(false));
+impl SensorEvents {
+    /// Constructor
+    ///
+    /// ## Arguments
+    ///
+    /// * `epoch` - Monotonic time used as zero for event timestamps.
+    /// * `senses` - Shared filtered state of each sensor.
+    pub(crate) fn new(epoch: Instant, senses: [Arc<AtomicBool>; 6]) -> Self {
         Self {
-            left1,
-            left2,
-            right1,
-            right2,
+            epoch,
+            filters: Mutex::new(Default::default()),
+            filtered: Default::default(),
+            raw: Default::default(),
+            raw_edges: Default::default(),
+            raw_transitions: Default::default(),
+            senses,
+            subscribers: Mutex::new(Vec::new()),
+            transitions: Default::default(),
         }
     }
-}
-
-/// Ultrasonic sonar device driver for Yahboom ultrasonic sensor or similar
-/// devices like the HC-SR04 or HC-SR05.
-#[derive(Debug)]
-pub struct Sonar {
-    /// Boolean used to track active sonar status.
-    active_sonar: bool,
-    /// Instance of [AmUltrasonic](AmUltrasonic).
-    ultrasonic: AmUltrasonic,
-    /// Instance of [InputPin] connected to ultrasonic echo input pin.
+    /// Removes all subscribers and callbacks.
+    pub(crate) fn clear_subscribers(&self) {
+        self.subscribers
+            .lock()
+            .expect("Someone broke the lock")
+            .clear();
+    }
+    /// Samples and filters the sensors until the [Sensors](Sensors) are
+    /// dropped.
     ///
-    /// [InputPin]: rppal::gpio::InputPin
-    echo: InputPin,
-    /// Instance of [OutputPin] connected to ultrasonic trigger output pin.
+    /// ## Arguments
     ///
-    /// [OutputPin]: rppal::gpio::OutputPin
-    trigger: OutputPin,
-}
-impl Sonar {
-    /// Constructor which uses default values for all optional arguments.
-    pub fn new() -> Rr4cResult<Self> {
-        Self::new_with_kitchen_sink(None, None, None, None)
+    /// * `events` - Weak reference to the shared data.
+    fn filter_loop(events: Weak<SensorEvents>) {
+        let mut states: [FilterState; 6] = Default::default();
+        while let Some(events) = events.upgrade() {
+            let filters = *events.filters.lock().expect("Someone broke the lock");
+            let now = Instant::now();
+            for (i, sensor) in DigitalSensor::ALL.iter().enumerate() {
+                let current = events.senses[i].load(Ordering::Acquire);
+                if filters[i].is_none() {
+                    states[i] = FilterState::new(current);
+                    continue;
+                }
+                let raw = events.raw[i].load(Ordering::Acquire);
+                let transitions = events.raw_transitions[i].load(Ordering::Acquire);
+                let state = states[i].apply(&filters[i], raw, transitions, now, current);
+                events.update(*sensor, state);
+            }
+            drop(events);
+            sleep(FilterState::SAMPLE_PERIOD);
+        }
     }
-    /// Constructor with just `temperature` and `humidity` options.
+    /// Adds a callback called with each event.
     ///
     /// ## Arguments
     ///
-    /// The `temperature` and `humidity` values are used to increase the
-    /// accuracy of ultrasonic distance measurements.
+    /// * `callback` - Called from the thread delivering the event.
+    pub(crate) fn on_event<F>(&self, mut callback: F)
+    where
+        F: FnMut(SensorEvent) + Send + 'static,
+    {
+        self.subscribers
+            .lock()
+            .expect("Someone broke the lock")
+            .push(Box::new(move |event| {
+                callback(event);
+                true
+            }));
+    }
+    /// Stores the new unfiltered state of a sensor from its interrupt.
     ///
-    /// * `temperature` - Temperature in °C.
-    /// A `None` value will set a default of 20°C.
-    /// Temperatures are limited to between -40 and +65.5°C.
+    /// Unfiltered sensors are updated right away.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sensor` - Which [DigitalSensor](DigitalSensor) it is.
+    /// * `state` - New state of the sensor.
+    fn raw_update(&self, sensor: DigitalSensor, state: bool) {
+        let i = sensor as usize;
+        if self.raw[i].swap(state, Ordering::AcqRel) != state {
+            let micros = self.epoch.elapsed().as_micros() as u64;
+            self.raw_edges[i].store(micros, Ordering::Release);
+            self.raw_transitions[i].fetch_add(1, Ordering::AcqRel);
+        }
+        if !self.filtered[i].load(Ordering::Acquire) {
+            self.update(sensor, state);
+        }
+    }
+    /// Adds a subscriber which is removed once its `Receiver` is dropped.
+    pub(crate) fn subscribe(&self) -> Receiver<SensorEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers
+            .lock()
+            .expect("Someone broke the lock")
+            .push(Box::new(move |event| sender.send(event).is_ok()));
+        receiver
+    }
+    /// Access the number of filtered changes of state of a sensor.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sensor` - Which [DigitalSensor](DigitalSensor) to access.
+    pub(crate) fn transitions(&self, sensor: DigitalSensor) -> u64 {
+        self.transitions[sensor as usize].load(Ordering::Acquire)
+    }
+    /// Stores the new filtered state of a sensor and delivers an event when it
+    /// changed.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sensor` - Which [DigitalSensor](DigitalSensor) it is.
+    /// * `state` - New state of the sensor.
+    pub(crate) fn update(&self, sensor: DigitalSensor, state: bool) {
+        if self.senses[sensor as usize].swap(state, Ordering::AcqRel) == state {
+            return;
+        }
+        let event = SensorEvent {
+            sensor,
+            state,
+            timestamp: self.epoch.elapsed(),
+        };
+        self.transitions[sensor as usize].fetch_add(1, Ordering::AcqRel);
+        let mut subscribers = self.subscribers.lock().expect("Someone broke the lock");
+        let mut i = 0;
+        while i < subscribers.len() {
+            if (subscribers[i])(event) {
+                i += 1;
+            } else {
+                drop(subscribers.remove(i));
+            }
+        }
+    }
+}
+
+impl fmt::Debug for SensorEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SensorEvents")
+            .field("epoch", &self.epoch)
+            .field("filters", &self.filters)
+            .field("raw", &self.raw)
+            .field("raw_edges", &self.raw_edges)
+            .field("raw_transitions", &self.raw_transitions)
+            .field("senses", &self.senses)
+            .field("transitions", &self.transitions)
+            .finish()
+    }
+}
+
+/// Options used to filter out noise and chatter from a digital sensor.
+///
+/// All of the options can be combined and are applied in the order rate
+/// limit, majority, then stable time.
+/// The default is to not do any filtering.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DigitalFilter {
+    /// Optional minimum time a new state has to be stable before it is
+    /// accepted.
+    pub stable_for: Option<Duration>,
+    /// Optional number of the latest samples, taken every millisecond, that
+    /// vote on the state.
+    /// Limited to between 1 and 32 samples.
+    pub majority_of: Option<u8>,
+    /// Optional high and low rates in changes of state per second.
+    /// Above the high rate the sensor is treated as chattering and held at its
+    /// last state until the rate drops back below the low rate.
+    pub rate_limit: Option<(f32, f32)>,
+}
+
+impl DigitalFilter {
+    /// Used to check if no filtering will be done.
+    pub fn is_none(&self) -> bool {
+        self.stable_for.is_none() && self.majority_of.is_none() && self.rate_limit.is_none()
+    }
+}
+
+/// Running state of a [DigitalFilter](DigitalFilter) for one sensor.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct FilterState {
+    /// State that is waiting to become stable.
+    candidate: bool,
+    /// Used to track if the sensor is chattering.
+    chattering: bool,
+    /// Latest samples with the newest in the lowest bit.
+    samples: u32,
+    /// Time the `candidate` was first seen.
+    since: Option<Instant>,
+    /// Start time and unfiltered transitions count of the current rate window.
+    window: Option<(Instant, u64)>,
+}
+
+impl FilterState {
+    /// Constructor
+    ///
+    /// ## Arguments
+    ///
+    /// * `current` - Current filtered state of the sensor.
+    pub(crate) fn new(current: bool) -> Self {
+        Self {
+            candidate: current,
+            samples: if current { u32::MAX } else { 0 },
+            ..Default::default()
+        }
+    }
+    /// Applies the filter to a new sample returning the filtered state.
+    ///
+    /// ## Arguments
+    ///
+    /// * `filter` - The [DigitalFilter](DigitalFilter) to apply.
+    /// * `raw` - Latest unfiltered state.
+    /// * `transitions` - Count of unfiltered changes of state.
+    /// * `now` - Time of the sample.
+    /// * `current` - Current filtered state.
+    pub(crate) fn apply(
+        &mut self,
+        filter: &DigitalFilter,
+        raw: bool,
+        transitions: u64,
+        now: Instant,
+        current: bool,
+    ) -> bool {
+        if let Some((high, low)) = filter.rate_limit {
+            let (start, count) = *self.window.get_or_insert((now, transitions));
+            let elapsed = now.saturating_duration_since(start);
+            if elapsed >= Self::RATE_WINDOW {
+                let rate = transitions.saturating_sub(count) as f32 / elapsed.as_secs_f32();
+                if rate > high {
+                    self.chattering = true;
+                } else if rate < low {
+                    self.chattering = false;
+                }
+                self.window = Some((now, transitions));
+            }
+            if self.chattering {
+                return current;
+            }
+        }
+        let mut state = raw;
+        if let Some(n) = filter.majority_of {
+            let n = n.clamp(1, 32) as u32;
+            self.samples = (self.samples << 1) | raw as u32;
+            let mask = u32::MAX >> (32 - n);
+            state = (self.samples & mask).count_ones() * 2 > n;
+        }
+        if let Some(stable_for) = filter.stable_for {
+            if state != self.candidate || self.since.is_none() {
+                self.candidate = state;
+                self.since = Some(now);
+            }
+            let since = self.since.unwrap_or(now);
+            if now.saturating_duration_since(since) < stable_for {
+                return current;
+            }
+        }
+        state
+    }
+    /// How often filtered sensors are sampled.
+    pub(crate) const SAMPLE_PERIOD: Duration = Duration::from_millis(1);
+    /// Length of time used when measuring the rate of change.
+    const RATE_WINDOW: Duration = Duration::from_millis(100);
+}
+
+/// Which level a digital sensor output uses to signal detection.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Polarity {
+    /// Output is pulled low on detection.
+    ActiveLow,
+    /// Output is pulled high on detection.
+    ActiveHigh,
+}
+
+impl Polarity {
+    /// Used to check if a level means detection.
+    ///
+    /// ## Arguments
+    ///
+    /// * `level` - Level read from the sensor output.
+    pub fn is_active(&self, level: Level) -> bool {
+        match self {
+            Polarity::ActiveLow => level == Level::Low,
+            Polarity::ActiveHigh => level == Level::High,
+        }
+    }
+}
+
+/// Which level each digital sensor uses to signal detection.
+///
+/// The defaults match the stock Yahboom sensors.
+/// Can be deserialized with any [serde] format so it can be kept in a config
+/// file where any missing sensors use their default.
+///
+/// [serde]: https://serde.rs/
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SensorPolarity {
+    /// Left infrared (IR) proximity sensor.
+    /// Defaults to active low.
+    pub ir_left: Polarity,
+    /// Right infrared (IR) proximity sensor.
+    /// Defaults to active low.
+    pub ir_right: Polarity,
+    /// Left light dependant resister (LDR) sensor.
+    /// Defaults to active high.
+    pub ldr_left: Polarity,
+    /// Right light dependant resister (LDR) sensor.
+    /// Defaults to active high.
+    pub ldr_right: Polarity,
+    /// Left line tracking sensor 1.
+    /// Defaults to active low.
+    pub line_left1: Polarity,
+    /// Left line tracking sensor 2.
+    /// Defaults to active low.
+    pub line_left2: Polarity,
+    /// Right line tracking sensor 1.
+    /// Defaults to active low.
+    pub line_right1: Polarity,
+    /// Right line tracking sensor 2.
+    /// Defaults to active low.
+    pub line_right2: Polarity,
+}
+
+impl SensorPolarity {
+    /// Access the [Polarity](Polarity) of a digital sensor.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sensor` - Which [DigitalSensor](DigitalSensor) to access.
+    pub fn of(&self, sensor: DigitalSensor) -> Polarity {
+        match sensor {
+            DigitalSensor::IrLeft => self.ir_left,
+            DigitalSensor::IrRight => self.ir_right,
+            DigitalSensor::LineLeft1 => self.line_left1,
+            DigitalSensor::LineLeft2 => self.line_left2,
+            DigitalSensor::LineRight1 => self.line_right1,
+            DigitalSensor::LineRight2 => self.line_right2,
+        }
+    }
+}
+
+impl Default for SensorPolarity {
+    fn default() -> Self {
+        Self {
+            ir_left: Polarity::ActiveLow,
+            ir_right: Polarity::ActiveLow,
+            ldr_left: Polarity::ActiveHigh,
+            ldr_right: Polarity::ActiveHigh,
+            line_left1: Polarity::ActiveLow,
+            line_left2: Polarity::ActiveLow,
+            line_right1: Polarity::ActiveLow,
+            line_right2: Polarity::ActiveLow,
+        }
+    }
+}
+
+/// All of the sensor readings taken at the same time.
+///
+/// Can be serialized with any [serde] format like JSON or CBOR and is used to
+/// produce both of the postback formats.
+///
+/// [serde]: https://serde.rs/
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SensorSnapshot {
+    /// Monotonic time since the [Sensors](Sensors) were created.
+    pub timestamp: Duration,
+    /// Emergency stop latch was engaged.
+    #[serde(default)]
+    pub estop: bool,
+    /// Ultrasonic distance in cm if a measurement was available.
+    pub sonar: Option<f32>,
+    /// Left infrared (IR) proximity sensor detected an obstacle.
+    pub ir_left: bool,
+    /// Right infrared (IR) proximity sensor detected an obstacle.
+    pub ir_right: bool,
+    /// Left light dependant resister (LDR) tracking sensor sees light.
+    pub ldr_left: bool,
+    /// Right light dependant resister (LDR) tracking sensor sees light.
+    pub ldr_right: bool,
+    /// Left line tracking sensor 1 detected the line.
+    pub line_left1: bool,
+    /// Left line tracking sensor 2 detected the line.
+    pub line_left2: bool,
+    /// Right line tracking sensor 1 detected the line.
+    pub line_right1: bool,
+    /// Right line tracking sensor 2 detected the line.
+    pub line_right2: bool,
+}
+
+impl SensorSnapshot {
+    /// Produces an Rr4c compatible postback response of sensor data.
+    ///
+    /// The field layout is unchanged so apps parsing the fields by position
+    /// keep working. Use [`as_rr_postback_with_estop()`] to also report the
+    /// emergency stop latch.
+    ///
+    /// [`as_rr_postback_with_estop()`]: SensorSnapshot::as_rr_postback_with_estop()
+    pub fn as_rr_postback(&self) -> String {
+        format!("$RR4W,{}#", self.rr_fields())
+    }
+    /// Produces an Rr4c postback response with the emergency stop latch
+    /// appended as an extra `STOP0` or `STOP1` field.
+    ///
+    /// Only apps that know about the extra field should be sent this.
+    pub fn as_rr_postback_with_estop(&self) -> String {
+        format!("$RR4W,{},STOP{}#", self.rr_fields(), self.estop as u8)
+    }
+    /// Produces an Yahboom compatible postback response of sensor data.
+    ///
+    /// The field layout is unchanged so apps parsing the fields by position
+    /// keep working. Use [`as_yb_postback_with_estop()`] to also report the
+    /// emergency stop latch.
+    ///
+    /// [`as_yb_postback_with_estop()`]: SensorSnapshot::as_yb_postback_with_estop()
+    pub fn as_yb_postback(&self) -> String {
+        format!("$4WD,{}#", self.yb_fields())
+    }
+    /// Produces an Yahboom postback response with the emergency stop latch
+    /// appended as an extra `ES0` or `ES1` field.
+    ///
+    /// Only apps that know about the extra field should be sent this.
+    pub fn as_yb_postback_with_estop(&self) -> String {
+        format!("$4WD,{},ES{}#", self.yb_fields(), self.estop as u8)
+    }
+    /// Fields of the Rr4c postback without the framing.
+    fn rr_fields(&self) -> String {
+        format!(
+            "SNR{},LNF{}:{}:{}:{},IRP{}:{},LDR{}:{}",
+            self.sonar.unwrap_or(-1.0) as i16,
+            self.line_left1 as u8,
+            self.line_left2 as u8,
+            self.line_right1 as u8,
+            self.line_right2 as u8,
+            self.ir_left as u8,
+            self.ir_right as u8,
+            self.ldr_left as u8,
+            self.ldr_right as u8
+        )
+    }
+    /// Fields of the Yahboom postback without the framing.
+    fn yb_fields(&self) -> String {
+        format!(
+            "CSB{},PV8.3,GS0,LF{}{}{}{},HW{}{},GM{}{}",
+            self.sonar.unwrap_or(-1.0) as i16,
+            self.line_left1 as u8,
+            self.line_left2 as u8,
+            self.line_right1 as u8,
+            self.line_right2 as u8,
+            self.ir_left as u8,
+            self.ir_right as u8,
+            self.ldr_left as u8,
+            self.ldr_right as u8
+        )
+    }
+}
+
+/// Simple overwriting ring buffer used to queue ultrasonic distance readings
+/// from active sonar along with when they were measured.
+#[derive(Clone, Debug)]
+struct CircularQueue {
+    depth: usize,
+    queue: VecDeque<(f32, Instant)>,
+}
+
+impl CircularQueue {
+    pub fn new() -> Self {
+        Self::with_depth(Self::DEPTH)
+    }
+    pub fn with_depth(depth: usize) -> Self {
+        let depth = depth.clamp(1, Self::MAX_DEPTH);
+        Self {
+            depth,
+            queue: VecDeque::with_capacity(depth),
+        }
+    }
+    pub fn drain(&mut self) -> Vec<(f32, Instant)> {
+        self.queue.drain(..).collect()
+    }
+    pub fn push(&mut self, value: (f32, Instant)) {
+        // If the writer is starting to lap the reader drop the oldest write.
+        if self.queue.len() >= self.depth {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(value);
+    }
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth.clamp(1, Self::MAX_DEPTH);
+        while self.queue.len() > self.depth {
+            self.queue.pop_front();
+        }
+    }
+    /// Default number of readings held.
+    const DEPTH: usize = 6;
+    /// Maximum number of readings that can be held.
+    const MAX_DEPTH: usize = 64;
+}
+
+/// Holds data related to infrared (IR) proximity sensors.
+#[derive(Debug)]
+pub struct IrProximity {
+    pub left: Arc<AtomicBool>,
+    pub right: Arc<AtomicBool>,
+}
+
+impl IrProximity {
+    pub fn new() -> Self {
+        let left = Arc::new(AtomicBool::new(false));
+        let right = Arc::new(AtomicBool::new(false));
+        Self { left, right }
+    }
+}
+
+/// Holds data related to line tracking sensors.
+#[derive(Debug)]
+pub struct LineTracking {
+    pub left1: Arc<AtomicBool>,
+    pub left2: Arc<AtomicBool>,
+    pub right1: Arc<AtomicBool>,
+    pub right2: Arc<AtomicBool>,
+}
+
+impl LineTracking {
+    pub fn new() -> Self {
+        let left1 = Arc::new(AtomicBool::new(false));
+        let right1 = Arc::new(AtomicBool::new(false));
+        let left2 = Arc::new(AtomicBool::new(false));
+        let right2 = Arc::new(AtomicBool::new(false));
+        Self {
+            left1,
+            left2,
+            right1,
+            right2,
+        }
+    }
+}
+
+/// Ultrasonic sonar device driver for Yahboom ultrasonic sensor or similar
+/// devices like the HC-SR04 or HC-SR05.
+#[derive(Debug)]
+pub struct Sonar {
+    /// Boolean used to track active sonar status.
+    active_sonar: bool,
+    /// Start of the time base used for reading timestamps.
+    epoch: Instant,
+    /// Turns [Echo](Echo)s published by the echo interrupt into readings.
+    echoes: EchoTracker,
+    /// Rate in Hz used for active sonar pinging.
+    ping_rate: f64,
+    /// Time between pings when a [SonarScheduler](SonarScheduler) is doing
+    /// the pinging.
+    scheduled: Option<Duration>,
+    /// Counts of measurement results.
+    stats: SonarStats,
+    /// Instance of [Ultrasonic](Ultrasonic).
+    ultrasonic: Ultrasonic,
+    /// Instance of [InputPin] connected to ultrasonic echo input pin.
+    ///
+    /// [InputPin]: rppal::gpio::InputPin
+    echo: InputPin,
+    /// Instance of [OutputPin] connected to ultrasonic trigger output pin.
+    ///
+    /// Shared so a [SonarScheduler](SonarScheduler) can ping it.
+    ///
+    /// [OutputPin]: rppal::gpio::OutputPin
+    trigger: AmOutputPin,
+}
+impl Sonar {
+    /// Constructor which uses default values for all optional arguments.
+    pub fn new() -> Rr4cResult<Self> {
+        Self::new_with_kitchen_sink(None, None, None, None)
+    }
+    /// Constructor with just `temperature` and `humidity` options.
+    ///
+    /// ## Arguments
+    ///
+    /// The `temperature` and `humidity` values are used to increase the
+    /// accuracy of ultrasonic distance measurements.
+    ///
+    /// * `temperature` - Temperature in °C.
+    /// A `None` value will set a default of 20°C.
+    /// Temperatures are limited to between -40 and +65.5°C.
     /// * `humidity` - Relative humidity as %.
     /// A `None` value will set a default of 40%.
     pub fn new_with_temp_hum<T, H>(temperature: T, humidity: H) -> Rr4cResult<Self>
@@ -437,105 +1554,848 @@ impl Sonar {
             .get(trigger.into().unwrap_or(Self::TRIGGER))?
             .into_output();
         trigger.set_low();
-        trigger.set_pwm_frequency(Self::ACTIVE_SONIC_FREQUENCY, Self::ACTIVE_SONIC_DUTY_CYCLE)?;
-        let ultrasonic = Arc::new(Mutex::new(Ultrasonic::new(temperature, humidity)));
-        let sense = ultrasonic.clone();
+        let ultrasonic = Ultrasonic::new(temperature, humidity);
+        let speed_of_sound = ultrasonic.speed_of_sound.clone();
+        // Bounded and lock-free so nothing grows when no one is reading and the
+        // interrupt never blocks. When full the oldest echoes are overwritten
+        // so the newest are always kept.
+        let echoes = Arc::new(EchoQueue::new(Self::ECHO_CAPACITY));
+        let queue = echoes.clone();
+        let mut rising = None;
         let echo_closure = move |level| {
-            let mut ultrasonic = sense.lock().expect("Someone broke the lock");
-            let dur = (SystemTime::now())
-                .duration_since(UNIX_EPOCH)
-                .expect("Bad robot!!! No time traveling to the past!");
-            match level {
-                Level::Low => {
-                    // Only process a falling edge when there was a leading edge.
-                    if let Some(rising) = ultrasonic.rising {
-                        ultrasonic.rising = None;
-                        // Only process falling edge that happened after the
-                        // leading edge.
-                        if let Some(diff) = dur.checked_sub(rising) {
-                            let distance = diff.as_secs_f32() * ultrasonic.speed_of_sound;
-                            if distance > 2.0 && distance < 500.0 {
-                                ultrasonic.queue.push(distance);
-                            }
-                        }
-                    }
-                }
-                Level::High => {
-                    ultrasonic.rising = Some(dur);
-                }
+            // Monotonic so clock adjustments can't corrupt echo timing.
+            let now = Instant::now();
+            let speed = f32::from_bits(speed_of_sound.load(Ordering::Relaxed));
+            if let Some(echo) = Echo::from_edge(level, now, &mut rising, speed) {
+                queue.push(echo);
             }
         };
         echo.set_async_interrupt(Both, echo_closure)?;
         Ok(Self {
             active_sonar: false,
+            epoch: Instant::now(),
+            echoes: EchoTracker::new(echoes),
+            ping_rate: Self::ACTIVE_SONIC_FREQUENCY,
+            scheduled: None,
+            stats: SonarStats::default(),
             ultrasonic,
             echo,
-            trigger,
+            trigger: Arc::new(Mutex::new(trigger)),
         })
     }
+    /// Used to acquire latest ultrasonic distance measurement.
+    ///
+    /// Every reading queued by active sonar is passed through the filter stage
+    /// with the result from the newest one being returned.
+    /// Blocks waiting on the echo interrupt for up to
+    /// [ULTRASONIC_TIMEOUT](Sonar::ULTRASONIC_TIMEOUT) when no reading is
+    /// queued.
+    /// In active mode the timeout and [STALE_AGE](Sonar::STALE_AGE) are
+    /// stretched to 1.5 times the time between pings.
+    ///
+    /// ## Errors
+    ///
+    /// * [SonarError::NoEcho] - No echo was heard before the timeout.
+    /// * [SonarError::OutOfRange] - The only echo heard was too near or far.
+    /// * [SonarError::Stale] - The newest queued reading is older than
+    ///   [STALE_AGE](Sonar::STALE_AGE).
+    /// * [SonarError::Timeout] - An echo started but did not finish before the
+    ///   timeout.
+    pub fn distance(&mut self) -> Result<Reading, SonarError> {
+        let result = self.measure();
+        self.stats.record(&result);
+        result
+    }
+    /// Access the filter stage options used for distance measurements.
+    pub fn filter(&self) -> SonarFilter {
+        self.echoes.filter
+    }
+    /// Access the relative humidity as % currently used to compensate
+    /// distance measurements.
+    pub fn humidity(&self) -> f32 {
+        self.ultrasonic.humidity
+    }
+    /// Used to acquire the newest distance measurement without waiting.
+    ///
+    /// Only readings already queued by active sonar are used so it never
+    /// blocks.
+    /// Only results from echoes that were heard are counted in the
+    /// [`stats()`].
+    ///
+    /// ## Errors
+    ///
+    /// Same as [`distance()`] except [SonarError::NoEcho] and
+    /// [SonarError::Timeout] mean nothing new has been queued yet.
+    ///
+    /// [`distance()`]: Sonar::distance()
+    /// [`stats()`]: Sonar::stats()
+    pub fn latest_distance(&mut self) -> Result<Reading, SonarError> {
+        self.ultrasonic.refresh_environment(Instant::now());
+        let (_, stale_age) = Self::wait_times(self.ping_period());
+        let result = self
+            .echoes
+            .measure(self.epoch, Instant::now(), Duration::ZERO, stale_age);
+        if !matches!(result, Err(SonarError::NoEcho | SonarError::Timeout)) {
+            self.stats.record(&result);
+        }
+        result
+    }
+    /// Access the rate in Hz used for active sonar pinging.
+    pub fn ping_rate(&self) -> f64 {
+        self.ping_rate
+    }
+    /// Sets a source that is periodically read for the temperature and
+    /// humidity used to compensate distance measurements.
+    ///
+    /// The source is read before the next measurement and then again every
+    /// [ENVIRONMENT_REFRESH](Sonar::ENVIRONMENT_REFRESH).
+    /// Any value the source doesn't know leaves the current one unchanged.
+    ///
+    /// ## Arguments
+    ///
+    /// * `source` - Optional [EnvironmentSource](EnvironmentSource).
+    ///   A `None` value stops reading from any previous source.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018, no_run
+    /// # #[cfg(target_arch = "arm")]
+    /// # {
+    /// use rust_rpi_4wd_car::{FixedEnvironment, Result, Sonar};
+    ///
+    /// fn main() -> Result {
+    ///     let mut sonar = Sonar::new()?;
+    ///     sonar.set_environment_source(Box::new(FixedEnvironment {
+    ///         temperature: Some(32.0),
+    ///         humidity: Some(75.0),
+    ///     }));
+    ///     println!("{:?}", sonar.distance());
+    ///     Ok(())
+    /// }
+    /// # }
+    /// ```
+    pub fn set_environment_source<S>(&mut self, source: S)
+    where
+        S: Into<Option<Box<dyn EnvironmentSource>>>,
+    {
+        self.ultrasonic.environment = source.into().map(|source| (source, None));
+    }
+    /// Sets the filter stage options used for distance measurements.
+    ///
+    /// Any history from the previous filter is cleared.
+    ///
+    /// ## Arguments
+    ///
+    /// * `filter` - The [SonarFilter](SonarFilter) to use.
+    ///   Use `SonarFilter::default()` to turn off filtering.
+    pub fn set_filter(&mut self, filter: SonarFilter) {
+        self.echoes.filter = filter;
+        self.echoes.filter_state = SonarFilterState::default();
+    }
+    /// Sets how many readings from active sonar can be queued before the
+    /// oldest ones are overwritten.
+    ///
+    /// ## Arguments
+    ///
+    /// * `depth` - Number of readings.
+    ///   Defaults to 6 readings.
+    ///   Internally limited between 1 and 64 readings.
+    pub fn set_queue_depth<D: Into<Option<usize>>>(&mut self, depth: D) {
+        self.echoes
+            .queue
+            .set_depth(depth.into().unwrap_or(CircularQueue::DEPTH));
+    }
+    /// Sets the rate used for active sonar pinging.
+    ///
+    /// Takes effect immediately when active sonar is already on.
+    ///
+    /// ## Arguments
+    ///
+    /// * `rate` - Pings per second in Hz.
+    ///   Defaults to 30Hz.
+    ///   Internally limited to between 1 and 30Hz so an echo can finish before
+    ///   the next ping.
+    ///
+    /// ## Errors
+    ///
+    /// * [Rr4cError::SonarScheduled] - The sonar is being pinged by
+    ///   [Sensors](Sensors) in turn with other sonars.
+    ///   Use [`Sensors::set_sonar_ping_rate()`] instead.
+    ///
+    /// [`Sensors::set_sonar_ping_rate()`]: Sensors::set_sonar_ping_rate()
+    pub fn set_ping_rate<R: Into<Option<f64>>>(&mut self, rate: R) -> Rr4cResult<()> {
+        if self.scheduled.is_some() {
+            return Err(Rr4cError::SonarScheduled);
+        }
+        self.ping_rate = rate
+            .into()
+            .unwrap_or(Self::ACTIVE_SONIC_FREQUENCY)
+            .clamp(Self::MIN_PING_RATE, Self::MAX_PING_RATE);
+        if self.active_sonar {
+            self.trigger_pwm(true)?;
+        }
+        Ok(())
+    }
     /// Sets if active sonar pinging should be used.
     ///
     /// ## Arguments
     ///
     /// * `enable` -Turns on active background sonar pinging when `true`.
-    pub fn set_sonar_active(&mut self, enable: bool) {
-        self.active_sonar = enable;
-        if enable {
-            self.trigger.enable();
-        } else {
-            self.trigger.disable();
+    ///
+    /// ## Errors
+    ///
+    /// * [Rr4cError::SonarScheduled] - The sonar is being pinged by
+    ///   [Sensors](Sensors) in turn with other sonars.
+    ///   Use [`Sensors::sonar_active()`] instead.
+    ///
+    /// [`Sensors::sonar_active()`]: Sensors::sonar_active()
+    pub fn set_sonar_active(&mut self, enable: bool) -> Rr4cResult<()> {
+        if self.scheduled.is_some() {
+            return Err(Rr4cError::SonarScheduled);
         }
+        self.active_sonar = enable;
+        self.trigger_pwm(enable)
     }
-    /// Used to acquire latest ultrasonic distance measurement if available.
+    /// Sets the temperature and humidity used to compensate distance
+    /// measurements.
+    ///
+    /// ## Arguments
     ///
-    /// Polls for distance measurement in a loop with a timeout.
-    pub fn distance(&mut self) -> Option<f32> {
-        let timeout = (SystemTime::now()).add(Duration::from_nanos(Self::ULTRASONIC_TIMEOUT));
-        let dur = Duration::from_micros(10);
+    /// * `temperature` - Temperature in °C.
+    ///   A `None` value will set a default of 20°C.
+    ///   Temperatures are limited to between -40 and +65.5°C.
+    /// * `humidity` - Relative humidity as %.
+    ///   A `None` value will set a default of 40%.
+    pub fn set_temp_hum<T, H>(&mut self, temperature: T, humidity: H)
+    where
+        T: Into<Option<f32>>,
+        H: Into<Option<f32>>,
+    {
+        self.ultrasonic.set_temp_hum(temperature, humidity);
+    }
+    /// Access the counts of measurement results used for health
+    /// diagnostics.
+    pub fn stats(&self) -> SonarStats {
+        self.stats
+    }
+    /// Access the temperature in °C currently used to compensate distance
+    /// measurements.
+    pub fn temperature(&self) -> f32 {
+        self.ultrasonic.temperature
+    }
+    /// Takes a distance measurement.
+    ///
+    /// See [`distance()`](Sonar::distance()) for more info.
+    fn measure(&mut self) -> Result<Reading, SonarError> {
+        self.ultrasonic.refresh_environment(Instant::now());
+        let start = Instant::now();
         if !self.active_sonar {
-            // Ping
-            self.trigger.set_high();
-            sleep(Duration::from_nanos(10000));
-            self.trigger.set_low();
-            sleep(Duration::from_nanos(2000));
-        }
-        while SystemTime::now() < timeout {
-            // Release lock as early as possible so echo interrupt thread can
-            // grab it.
-            {
-                let mut ultrasonic = self.ultrasonic.lock().expect("Someone broke the lock");
-                if let Some(distance) = ultrasonic.queue.pop() {
-                    return Some(distance);
-                }
+            Self::ping(&self.trigger);
+        }
+        let (timeout, stale_age) = Self::wait_times(self.ping_period());
+        self.echoes.measure(self.epoch, start, timeout, stale_age)
+    }
+    /// Time between pings in active mode or `None` when not active.
+    fn ping_period(&self) -> Option<Duration> {
+        if !self.active_sonar {
+            return None;
+        }
+        let own = || Duration::from_secs_f64(self.ping_rate.recip());
+        Some(self.scheduled.unwrap_or_else(own))
+    }
+    /// Sends a single ping on a trigger pin.
+    fn ping(trigger: &AmOutputPin) {
+        let mut trigger = trigger.lock().expect("Someone broke the lock");
+        trigger.set_high();
+        sleep(Duration::from_nanos(10000));
+        trigger.set_low();
+    }
+    /// Puts sonar in active mode without starting its own pinging so a
+    /// [SonarScheduler](SonarScheduler) can ping it instead.
+    ///
+    /// ## Arguments
+    ///
+    /// * `period` - Time between pings from the scheduler or `None` to stop
+    ///   active mode.
+    fn set_scheduled(&mut self, period: Option<Duration>) -> Rr4cResult<()> {
+        self.active_sonar = period.is_some();
+        self.scheduled = period;
+        self.trigger_pwm(false)
+    }
+    /// Time to wait for an echo and the age after which a queued reading is
+    /// no longer trusted.
+    ///
+    /// In active mode both are stretched to cover the time between pings so
+    /// slow ping rates don't cause false errors.
+    ///
+    /// ## Arguments
+    ///
+    /// * `period` - Time between pings in active mode or `None` when each
+    ///   measurement sends its own ping.
+    pub(crate) fn wait_times(period: Option<Duration>) -> (Duration, Duration) {
+        let timeout = Duration::from_nanos(Self::ULTRASONIC_TIMEOUT);
+        match period {
+            Some(period) => {
+                let period = period.mul_f64(Self::PERIOD_MARGIN);
+                (timeout.max(period), Self::STALE_AGE.max(period))
             }
-            sleep(dur);
+            None => (timeout, Self::STALE_AGE),
         }
-        None
     }
-    /// Timeout in nanoseconds (ns) ≈ 30 Hz
+    /// Turns PWM pinging of the trigger pin on or off.
+    fn trigger_pwm(&mut self, enable: bool) -> Rr4cResult<()> {
+        let mut trigger = self.trigger.lock().expect("Someone broke the lock");
+        if enable {
+            // Keep the same pulse width whatever the rate.
+            let duty_cycle = Self::ACTIVE_SONIC_PULSE * self.ping_rate;
+            trigger.set_pwm_frequency(self.ping_rate, duty_cycle)?;
+        } else {
+            trigger.disable();
+            trigger.set_low();
+        }
+        Ok(())
+    }
+    /// How often an [EnvironmentSource](EnvironmentSource) is read.
+    pub const ENVIRONMENT_REFRESH: Duration = Duration::from_secs(10);
+    /// Shortest age after which a queued reading is no longer trusted.
+    ///
+    /// Stretched to 1.5 times the time between pings in active mode.
+    pub const STALE_AGE: Duration = Duration::from_millis(100);
+    /// Shortest timeout in nanoseconds (ns) ≈ 30 Hz
+    ///
+    /// Stretched to 1.5 times the time between pings in active mode.
     pub const ULTRASONIC_TIMEOUT: u64 = 33_333_000;
     /// Ultrasonic echo input pin #.
     const ECHO: u8 = 0;
+    /// Number of [Echo](Echo)s the interrupt can publish before the oldest
+    /// ones are dropped.
+    const ECHO_CAPACITY: usize = 128;
     /// Ultrasonic trigger output pin #.
     const TRIGGER: u8 = 1;
-    /// Frequency for active sonic pings in Hz.
+    /// Default frequency for active sonic pings in Hz.
     const ACTIVE_SONIC_FREQUENCY: f64 = 30.0;
-    /// PWM Duty cycle in % used for active sonic.
-    const ACTIVE_SONIC_DUTY_CYCLE: f64 = 0.003;
+    /// Pulse width in seconds used for active sonic.
+    const ACTIVE_SONIC_PULSE: f64 = 0.0001;
+    /// Fastest allowed ping rate in Hz.
+    const MAX_PING_RATE: f64 = 30.0;
+    /// Slowest allowed ping rate in Hz.
+    const MIN_PING_RATE: f64 = 1.0;
+    /// Farthest distance in cm that is trusted.
+    const MAX_DISTANCE: f32 = 500.0;
+    /// Nearest distance in cm that is trusted.
+    const MIN_DISTANCE: f32 = 2.0;
+    /// Multiple of the time between pings allowed for an echo to arrive in
+    /// active mode.
+    const PERIOD_MARGIN: f64 = 1.5;
 }
 
-/// Holds data related to ultrasonic measurements.
+/// Turns the [Echo](Echo)s published by a [Sonar](Sonar) echo interrupt into
+/// filtered readings.
+///
+/// Kept apart from the pins so measurements can be classified without any
+/// hardware.
+#[derive(Debug)]
+pub(crate) struct EchoTracker {
+    /// Used to track if an echo has started but not yet finished.
+    echo_pending: bool,
+    /// Queue of [Echo](Echo)s published by the echo interrupt.
+    echoes: Arc<EchoQueue>,
+    /// Filter stage options used for distance measurements.
+    filter: SonarFilter,
+    /// Running state of the filter stage.
+    filter_state: SonarFilterState,
+    /// Latest distances received but not yet used.
+    queue: CircularQueue,
+}
+
+impl EchoTracker {
+    /// Constructor
+    ///
+    /// ## Arguments
+    ///
+    /// * `echoes` - Queue of echoes published by the echo interrupt.
+    pub fn new(echoes: Arc<EchoQueue>) -> Self {
+        Self {
+            echo_pending: false,
+            echoes,
+            filter: SonarFilter::default(),
+            filter_state: SonarFilterState::default(),
+            queue: CircularQueue::new(),
+        }
+    }
+    /// Waits for a reading from the echoes.
+    ///
+    /// See [`Sonar::distance()`] for the errors.
+    ///
+    /// ## Arguments
+    ///
+    /// * `epoch` - Start of the time base used for reading timestamps.
+    /// * `start` - When the measurement started.
+    ///   Out of range echoes heard before this are ignored.
+    /// * `timeout` - How long after `start` to wait for an echo.
+    /// * `stale_age` - Age after which a queued reading is no longer trusted.
+    ///
+    /// [`Sonar::distance()`]: Sonar::distance()
+    pub fn measure(
+        &mut self,
+        epoch: Instant,
+        start: Instant,
+        timeout: Duration,
+        stale_age: Duration,
+    ) -> Result<Reading, SonarError> {
+        let timeout = start + timeout;
+        let mut out_of_range = None;
+        loop {
+            // Take everything already waiting before blocking for more.
+            while let Some(echo) = self.echoes.try_recv() {
+                self.receive(echo, start, &mut out_of_range);
+            }
+            let samples = self.queue.drain();
+            if !samples.is_empty() {
+                let filter = self.filter;
+                let reading = samples
+                    .into_iter()
+                    .map(|(raw, at)| self.filter_state.apply(&filter, raw, epoch, at))
+                    .last()
+                    .expect("Samples can't be empty");
+                let age = epoch.elapsed().saturating_sub(reading.timestamp);
+                if age > stale_age {
+                    return Err(SonarError::Stale(age));
+                }
+                return Ok(Reading { age, ..reading });
+            }
+            let remaining = timeout.saturating_duration_since(Instant::now());
+            match self.echoes.recv_timeout(remaining) {
+                Some(echo) => self.receive(echo, start, &mut out_of_range),
+                None => break,
+            }
+        }
+        match (self.echo_pending, out_of_range) {
+            (true, _) => Err(SonarError::Timeout),
+            (false, Some(distance)) => Err(SonarError::OutOfRange(distance)),
+            _ => Err(SonarError::NoEcho),
+        }
+    }
+    /// Handles an [Echo](Echo) from the echo interrupt.
+    pub(crate) fn receive(&mut self, echo: Echo, start: Instant, out_of_range: &mut Option<f32>) {
+        match echo {
+            Echo::Started => self.echo_pending = true,
+            Echo::Measured(distance, at) => {
+                self.echo_pending = false;
+                self.queue.push((distance, at));
+            }
+            Echo::OutOfRange(distance, at) => {
+                self.echo_pending = false;
+                if at >= start {
+                    *out_of_range = Some(distance);
+                }
+            }
+        }
+    }
+}
+
+/// Takes turns pinging several [Sonar](Sonar)s so one can't hear the echo
+/// from another.
+///
+/// Each sonar gets its own time slot long enough for an echo to finish.
+/// The background thread is stopped when this is dropped.
+#[derive(Debug)]
+pub(crate) struct SonarScheduler {
+    /// Background thread doing the pinging.
+    handle: Option<JoinHandle<()>>,
+    /// Used to tell the background thread to stop.
+    stop: Arc<AtomicBool>,
+}
+
+impl SonarScheduler {
+    /// Starts pinging the trigger pins in turn.
+    ///
+    /// ## Arguments
+    ///
+    /// * `triggers` - Trigger pins of the sonars in the order to ping them.
+    /// * `rate` - Pings per second in Hz for each sonar.
+    ///   Slowed down as needed so every sonar gets a full slot.
+    pub fn new(triggers: Vec<AmOutputPin>, rate: f64) -> Self {
+        let pings = triggers
+            .into_iter()
+            .map(|trigger| move || Sonar::ping(&trigger))
+            .collect();
+        Self::new_with_pings(pings, rate)
+    }
+    /// Starts calling the pings in turn.
+    ///
+    /// ## Arguments
+    ///
+    /// * `pings` - Sends a ping from each sonar in the order to call them.
+    /// * `rate` - Pings per second in Hz for each sonar.
+    ///   Slowed down as needed so every sonar gets a full slot.
+    pub(crate) fn new_with_pings<P>(mut pings: Vec<P>, rate: f64) -> Self
+    where
+        P: FnMut() + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let handle = thread::spawn(move || {
+            let slot = Self::slot(pings.len(), rate);
+            let mut next = Instant::now();
+            while !flag.load(Ordering::Acquire) {
+                for ping in &mut pings {
+                    ping();
+                    next += slot;
+                    loop {
+                        if flag.load(Ordering::Acquire) {
+                            return;
+                        }
+                        let now = Instant::now();
+                        if now >= next {
+                            break;
+                        }
+                        thread::park_timeout(next - now);
+                    }
+                }
+            }
+        });
+        Self {
+            handle: Some(handle),
+            stop,
+        }
+    }
+    /// Time each sonar is given for its echo.
+    ///
+    /// ## Arguments
+    ///
+    /// * `sonars` - Number of sonars taking turns.
+    /// * `rate` - Wanted pings per second in Hz for each sonar.
+    pub(crate) fn slot(sonars: usize, rate: f64) -> Duration {
+        let sonars = sonars.max(1) as f64;
+        let wanted = 1.0 / (rate.max(Sonar::MIN_PING_RATE) * sonars);
+        Duration::from_secs_f64(wanted.max(1.0 / Sonar::MAX_PING_RATE))
+    }
+}
+
+impl Drop for SonarScheduler {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+/// An ultrasonic distance measurement from the [Sonar](Sonar) filter stage.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Reading {
+    /// Unfiltered distance in cm.
+    pub raw: f32,
+    /// Filtered distance in cm.
+    pub filtered: f32,
+    /// How much the recent readings agree with the filtered distance from 0.0
+    /// (not at all) to 1.0 (completely).
+    pub confidence: f32,
+    /// Time of the echo measured on a monotonic clock from the start of the
+    /// sensor time base.
+    pub timestamp: Duration,
+    /// How old the reading was when it was returned.
+    pub age: Duration,
+}
+
+/// Options for the filter stage used with ultrasonic distance measurements.
+///
+/// All of the options can be combined and are applied in the order outlier
+/// rejection, median, then exponential moving average.
+/// The default is to not do any filtering.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SonarFilter {
+    /// Optional number of the latest accepted readings to take the median of.
+    /// Limited to between 1 and 64 readings.
+    pub median_of: Option<usize>,
+    /// Optional smoothing factor for an exponential moving average.
+    /// Limited to between 0.0 (never changes) and 1.0 (no smoothing).
+    pub ema_alpha: Option<f32>,
+    /// Optional maximum believable rate of change in cm per second.
+    /// Readings that change faster are rejected as outliers unless several in
+    /// a row agree.
+    pub max_rate: Option<f32>,
+}
+
+/// Running state of a [SonarFilter](SonarFilter).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SonarFilterState {
+    /// Latest exponential moving average.
+    ema: Option<f32>,
+    /// Latest accepted readings with the newest at the back.
+    history: VecDeque<f32>,
+    /// Latest accepted reading and the time it was accepted.
+    last: Option<(f32, Instant)>,
+    /// Number of outliers rejected in a row.
+    rejected: u8,
+}
+
+impl SonarFilterState {
+    /// Applies the filter to a new raw reading.
+    ///
+    /// ## Arguments
+    ///
+    /// * `filter` - The [SonarFilter](SonarFilter) to apply.
+    /// * `raw` - Unfiltered distance in cm.
+    /// * `epoch` - Start of the time base used for the reading timestamp.
+    /// * `now` - Time of the reading.
+    pub(crate) fn apply(
+        &mut self,
+        filter: &SonarFilter,
+        raw: f32,
+        epoch: Instant,
+        now: Instant,
+    ) -> Reading {
+        let mut accepted = true;
+        if let (Some(max_rate), Some((last, at))) = (filter.max_rate, self.last) {
+            let secs = now.saturating_duration_since(at).as_secs_f32().max(0.001);
+            if (raw - last).abs() / secs > max_rate && self.rejected < Self::MAX_REJECTED {
+                accepted = false;
+                self.rejected += 1;
+            }
+        }
+        let depth = filter
+            .median_of
+            .unwrap_or(1)
+            .clamp(1, CircularQueue::MAX_DEPTH);
+        if accepted {
+            self.rejected = 0;
+            self.last = Some((raw, now));
+            self.history.push_back(raw);
+        }
+        while self.history.len() > depth {
+            self.history.pop_front();
+        }
+        let mut filtered = match filter.median_of {
+            Some(_) => {
+                let mut sorted: Vec<f32> = self.history.iter().copied().collect();
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                let len = sorted.len();
+                (sorted[(len - 1) / 2] + sorted[len / 2]) / 2.0
+            }
+            None => self.last.map_or(raw, |(last, _)| last),
+        };
+        if let Some(alpha) = filter.ema_alpha {
+            let alpha = alpha.clamp(0.0, 1.0);
+            let ema = self
+                .ema
+                .map_or(filtered, |ema| ema + alpha * (filtered - ema));
+            self.ema = Some(ema);
+            filtered = ema;
+        }
+        // Share of the recent readings close to the filtered distance where a
+        // rejected reading always counts against it.
+        let tolerance = (filtered * 0.1).max(Self::TOLERANCE);
+        let close = self
+            .history
+            .iter()
+            .filter(|d| (*d - filtered).abs() <= tolerance)
+            .count();
+        let total = self.history.len() + !accepted as usize;
+        let confidence = close as f32 / total.max(1) as f32;
+        Reading {
+            raw,
+            filtered,
+            confidence,
+            timestamp: now.saturating_duration_since(epoch),
+            age: now.elapsed(),
+        }
+    }
+    /// Number of outliers in a row after which readings are trusted again.
+    const MAX_REJECTED: u8 = 3;
+    /// Minimum distance in cm a reading can be from the filtered distance and
+    /// still agree with it.
+    const TOLERANCE: f32 = 5.0;
+}
+
+/// Published by the echo interrupt to the [Sonar](Sonar) reader.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Echo {
+    /// An echo has started.
+    Started,
+    /// Distance in cm of a finished echo and when it was heard.
+    Measured(f32, Instant),
+    /// Distance in cm of a finished echo that was too near or far to trust and
+    /// when it was heard.
+    OutOfRange(f32, Instant),
+}
+
+impl Echo {
+    /// Turns an edge seen by the echo interrupt into an echo.
+    ///
+    /// Returns `None` for a falling edge without a rising edge before it.
+    ///
+    /// ## Arguments
+    ///
+    /// * `level` - New level of the echo pin.
+    /// * `now` - When the edge was seen.
+    /// * `rising` - When the latest rising edge was seen if still unmatched.
+    /// * `speed` - Speed of sound in cm/s halved for the out and back trip.
+    pub(crate) fn from_edge(
+        level: Level,
+        now: Instant,
+        rising: &mut Option<Instant>,
+        speed: f32,
+    ) -> Option<Self> {
+        match level {
+            Level::Low => {
+                // Only process a falling edge when there was a leading edge.
+                let diff = now.saturating_duration_since(rising.take()?);
+                let distance = diff.as_secs_f32() * speed;
+                if distance > Sonar::MIN_DISTANCE && distance < Sonar::MAX_DISTANCE {
+                    Some(Echo::Measured(distance, now))
+                } else {
+                    Some(Echo::OutOfRange(distance, now))
+                }
+            }
+            Level::High => {
+                *rising = Some(now);
+                Some(Echo::Started)
+            }
+        }
+    }
+}
+
+/// Bounded lock-free queue used to publish [Echo](Echo)s from the echo
+/// interrupt.
+///
+/// A single producer ring that never blocks the interrupt and overwrites the
+/// oldest echo when full so a reader that falls behind always gets the newest
+/// ones.
+/// Each slot is guarded by a sequence number so the reader can tell when a
+/// slot was overwritten while it was being read.
 #[derive(Debug)]
-struct Ultrasonic {
-    /// Time of latest rising edge from echo pin.
+pub(crate) struct EchoQueue {
+    /// Time echoes are stored relative to.
+    epoch: Instant,
+    /// Count of echoes ever pushed which is also the next slot to write.
+    head: AtomicU64,
+    /// Fixed ring of slots.
+    slots: Box<[EchoSlot]>,
+    /// Count of echoes ever taken or dropped which is also the next slot to
+    /// read.
+    tail: AtomicU64,
+}
+
+impl EchoQueue {
+    /// Constructor
+    ///
+    /// ## Arguments
+    ///
+    /// * `capacity` - Most echoes held before the oldest are overwritten.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            epoch: Instant::now(),
+            head: AtomicU64::new(0),
+            slots: (0..capacity.max(1)).map(|_| EchoSlot::default()).collect(),
+            tail: AtomicU64::new(0),
+        }
+    }
+    /// Adds an echo overwriting the oldest one when full.
     ///
-    /// This is used in calculating `distance` along with the time of the
-    /// falling edge.
-    pub rising: Option<Duration>,
+    /// Must only be called from one thread at a time.
+    pub fn push(&self, echo: Echo) {
+        let (kind, distance, heard) = match echo {
+            Echo::Started => (0, 0.0, self.epoch),
+            Echo::Measured(distance, heard) => (1, distance, heard),
+            Echo::OutOfRange(distance, heard) => (2, distance, heard),
+        };
+        let heard = heard.saturating_duration_since(self.epoch).as_nanos() as u64;
+        let position = self.head.load(Ordering::Relaxed);
+        let slot = &self.slots[(position % self.slots.len() as u64) as usize];
+        // Odd while being written.
+        slot.sequence.store(2 * position + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        slot.echo
+            .store(kind << 32 | distance.to_bits() as u64, Ordering::Relaxed);
+        slot.heard.store(heard, Ordering::Relaxed);
+        slot.sequence.store(2 * position + 2, Ordering::Release);
+        self.head.store(position + 1, Ordering::Release);
+    }
+    /// Takes the oldest echo polling up to `timeout` for one.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Echo> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(echo) = self.try_recv() {
+                return Some(echo);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            sleep(Self::POLL.min(deadline - now));
+        }
+    }
+    /// Takes the oldest echo if there is one.
+    ///
+    /// Must only be called from one thread at a time.
+    pub fn try_recv(&self) -> Option<Echo> {
+        let capacity = self.slots.len() as u64;
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            // Skip anything that has already been overwritten.
+            let position = self
+                .tail
+                .load(Ordering::Relaxed)
+                .max(head.saturating_sub(capacity));
+            if position >= head {
+                return None;
+            }
+            let slot = &self.slots[(position % capacity) as usize];
+            let before = slot.sequence.load(Ordering::Acquire);
+            let echo = slot.echo.load(Ordering::Relaxed);
+            let heard = slot.heard.load(Ordering::Relaxed);
+            fence(Ordering::Acquire);
+            let after = slot.sequence.load(Ordering::Relaxed);
+            if before != 2 * position + 2 || after != before {
+                // Overwritten while reading so try again with the newer ones.
+                std::hint::spin_loop();
+                continue;
+            }
+            self.tail.store(position + 1, Ordering::Relaxed);
+            let distance = f32::from_bits(echo as u32);
+            let heard = self.epoch + Duration::from_nanos(heard);
+            return Some(match echo >> 32 {
+                0 => Echo::Started,
+                1 => Echo::Measured(distance, heard),
+                _ => Echo::OutOfRange(distance, heard),
+            });
+        }
+    }
+    /// How often [`recv_timeout()`] checks for a new echo.
+    ///
+    /// [`recv_timeout()`]: EchoQueue::recv_timeout()
+    const POLL: Duration = Duration::from_micros(500);
+}
+
+/// A slot in an [EchoQueue](EchoQueue).
+#[derive(Debug, Default)]
+struct EchoSlot {
+    /// Kind of echo in the high 32 bits and the bits of its `f32` distance in
+    /// the low ones.
+    echo: AtomicU64,
+    /// Nanoseconds after the queue epoch the echo was heard.
+    heard: AtomicU64,
+    /// Twice the position written plus 1 while being written or 2 once done.
+    sequence: AtomicU64,
+}
+
+/// Holds data related to ultrasonic measurements.
+#[derive(Debug)]
+pub(crate) struct Ultrasonic {
+    /// Optional source of temperature and humidity with when it was last read.
+    pub environment: Option<(Box<dyn EnvironmentSource>, Option<Instant>)>,
+    /// Relative humidity as % used in `speed_of_sound`.
+    pub humidity: f32,
     /// Used in `distance` calculation.
-    pub speed_of_sound: f32,
-    /// Queue of latest available distances.
-    pub queue: CircularQueue,
+    ///
+    /// Held as the bits of a `f32` so the echo interrupt can read it without
+    /// a lock.
+    pub speed_of_sound: Arc<AtomicU32>,
+    /// Temperature in °C used in `speed_of_sound`.
+    pub temperature: f32,
 }
 
 impl Ultrasonic {
@@ -552,20 +2412,121 @@ impl Ultrasonic {
     /// * `humidity` - Relative humidity as %.
     /// A `None` value will set a default of 40%.
     pub fn new<T: Into<Option<f32>>, H: Into<Option<f32>>>(temperature: T, humidity: H) -> Self {
-        let temperature = temperature.into().unwrap_or(20.0).min(65.5).max(-40.0);
-        let humidity = humidity.into().unwrap_or(40.0).min(100.0).max(0.0);
+        let mut ultrasonic = Self {
+            environment: None,
+            humidity: 0.0,
+            speed_of_sound: Arc::new(AtomicU32::new(0)),
+            temperature: 0.0,
+        };
+        ultrasonic.set_temp_hum(temperature, humidity);
+        ultrasonic
+    }
+    /// Reads the environment source when one is set and due.
+    ///
+    /// ## Arguments
+    ///
+    /// * `now` - Current time used to decide if the source is due.
+    pub fn refresh_environment(&mut self, now: Instant) {
+        let (source, read) = match self.environment.as_mut() {
+            Some(environment) => environment,
+            None => return,
+        };
+        if let Some(read) = read {
+            if now.saturating_duration_since(*read) < Sonar::ENVIRONMENT_REFRESH {
+                return;
+            }
+        }
+        *read = Some(now);
+        let temperature = source.temperature().unwrap_or(self.temperature);
+        let humidity = source.humidity().unwrap_or(self.humidity);
+        self.set_temp_hum(temperature, humidity);
+    }
+    /// Updates the speed of sound used in `distance` calculation.
+    ///
+    /// ## Arguments
+    ///
+    /// * `temperature` - Temperature in °C.
+    ///   A `None` value will set a default of 20°C.
+    ///   Temperatures are limited to between -40 and +65.5°C.
+    /// * `humidity` - Relative humidity as %.
+    ///   A `None` value will set a default of 40%.
+    pub fn set_temp_hum<T: Into<Option<f32>>, H: Into<Option<f32>>>(
+        &mut self,
+        temperature: T,
+        humidity: H,
+    ) {
+        self.temperature = temperature.into().unwrap_or(20.0).clamp(-40.0, 65.5);
+        self.humidity = humidity.into().unwrap_or(40.0).clamp(0.0, 100.0);
         // (331.3m/s + 0.606m/°C * temperature°C + 0.0124m/% * humidity%)
         // * (100 cm/meter / 2 out and back)
-        let speed_of_sound = (331.3 + 0.606 * temperature + 0.0124 * humidity) * 50.0;
-        Self {
-            rising: None,
-            speed_of_sound,
-            queue: CircularQueue::new(),
-        }
+        let speed_of_sound = (331.3 + 0.606 * self.temperature + 0.0124 * self.humidity) * 50.0;
+        self.speed_of_sound
+            .store(f32::to_bits(speed_of_sound), Ordering::Relaxed);
+    }
+}
+
+/// Provides temperature and humidity values used to compensate ultrasonic
+/// distance measurements for changes in the speed of sound.
+///
+/// Implement this for an add-on thermometer or anything else that knows the
+/// current conditions and hand it to [`Sonar::set_environment_source()`].
+///
+/// [`Sonar::set_environment_source()`]: Sonar::set_environment_source()
+pub trait EnvironmentSource: fmt::Debug + Send {
+    /// Current temperature in °C if known.
+    fn temperature(&mut self) -> Option<f32>;
+    /// Current relative humidity as % if known.
+    fn humidity(&mut self) -> Option<f32> {
+        None
+    }
+}
+
+/// An [EnvironmentSource](EnvironmentSource) with fixed values like those
+/// from a weather report.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FixedEnvironment {
+    /// Temperature in °C.
+    pub temperature: Option<f32>,
+    /// Relative humidity as %.
+    pub humidity: Option<f32>,
+}
+
+impl EnvironmentSource for FixedEnvironment {
+    fn temperature(&mut self) -> Option<f32> {
+        self.temperature
+    }
+    fn humidity(&mut self) -> Option<f32> {
+        self.humidity
+    }
+}
+
+/// An [EnvironmentSource](EnvironmentSource) shared by more than one sonar.
+#[derive(Clone, Debug)]
+pub(crate) struct SharedEnvironment(Arc<Mutex<Box<dyn EnvironmentSource>>>);
+
+impl SharedEnvironment {
+    /// Constructor
+    ///
+    /// ## Arguments
+    ///
+    /// * `source` - The [EnvironmentSource](EnvironmentSource) to share.
+    pub fn new(source: Box<dyn EnvironmentSource>) -> Self {
+        Self(Arc::new(Mutex::new(source)))
+    }
+}
+
+impl EnvironmentSource for SharedEnvironment {
+    fn temperature(&mut self) -> Option<f32> {
+        self.0.lock().expect("Someone broke the lock").temperature()
+    }
+    fn humidity(&mut self) -> Option<f32> {
+        self.0.lock().expect("Someone broke the lock").humidity()
     }
 }
 
-/// An `Arc` `Mutex` wrapper type for `Ultrasonic` measurement structure.
-type AmUltrasonic = Arc<Mutex<Ultrasonic>>;
+/// An `Arc` `Mutex` wrapper type for shared trigger pins.
+type AmOutputPin = Arc<Mutex<OutputPin>>;
+/// Callback type used to deliver [SensorEvent](SensorEvent)s.
+type Subscriber = Box<dyn FnMut(SensorEvent) -> bool + Send>;
 /// Result type from `tracking_init()` function.
-type LineInitResult = (InputPin, InputPin, InputPin, InputPin, LineTracking);
+type LineInitResult = (InputPin, InputPin, InputPin, InputPin);
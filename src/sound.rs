@@ -0,0 +1,374 @@
+// Copyright © 2021-present, Michael Cummings
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// MIT License
+//
+// Copyright © 2021-present, Michael Cummings <mgcummings@yahoo.com>.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! Contains the note, melody, ring tone text transfer language (RTTTL), and
+//! Morse code components used to play sounds on the buzzer.
+
+use crate::{Rr4cError, Rr4cResult};
+use std::time::Duration;
+
+/// Kind of buzzer the sounds are played on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Buzzer {
+    /// Stock buzzer that makes its own fixed tone whenever it is on so only
+    /// the rhythm of a melody can be played.
+    Active,
+    /// Add-on buzzer or speaker that needs to be driven at the frequency of
+    /// each note.
+    Passive,
+}
+
+/// A single note or rest in a [Melody](Melody).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Note {
+    /// Frequency of the note in Hz with `0.0` used for a rest.
+    pub frequency: f64,
+    /// How long the note is played including any gap before the next one.
+    pub duration: Duration,
+}
+
+impl Note {
+    /// Constructor
+    ///
+    /// ## Arguments
+    ///
+    /// * `frequency` - Frequency in Hz with `0.0` used for a rest.
+    /// * `millis` - Length of the note in milliseconds.
+    pub fn new(frequency: f64, millis: u64) -> Self {
+        Self {
+            frequency: frequency.max(0.0),
+            duration: Duration::from_millis(millis),
+        }
+    }
+    /// Constructor for a rest (silent) note.
+    ///
+    /// ## Arguments
+    ///
+    /// * `millis` - Length of the rest in milliseconds.
+    pub fn rest(millis: u64) -> Self {
+        Self::new(0.0, millis)
+    }
+    /// Used to check if note is a rest.
+    pub fn is_rest(&self) -> bool {
+        self.frequency == 0.0
+    }
+}
+
+/// A sequence of notes which can be played on the buzzer.
+///
+/// ## Examples
+///
+/// ```edition2018
+/// use rust_rpi_4wd_car::Melody;
+///
+/// let melody = Melody::from_rtttl("scale:d=8,o=5,b=120:c,d,e,f,g,a,b,c6").unwrap();
+/// assert_eq!(melody.notes().len(), 8);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Melody {
+    /// Name of the melody.
+    name: String,
+    /// Notes of the melody in the order they are played.
+    notes: Vec<Note>,
+}
+
+impl Melody {
+    /// Constructor
+    ///
+    /// ## Arguments
+    ///
+    /// * `name` - Name of the melody.
+    /// * `notes` - Notes of the melody in the order they are played.
+    pub fn new<N: Into<String>>(name: N, notes: Vec<Note>) -> Self {
+        Self {
+            name: name.into(),
+            notes,
+        }
+    }
+    /// Jingle used to signal an error.
+    pub fn error() -> Self {
+        Self::from_rtttl(Self::ERROR).expect("Built-in error jingle is broken")
+    }
+    /// Encodes text as a Morse code melody.
+    ///
+    /// Letters, digits, and common punctuation are encoded with any other
+    /// characters being skipped.
+    /// Uses standard timing where a dash is three dots long, the gaps between
+    /// letters are three dots, and the gaps between words are seven dots.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - Text to be encoded.
+    /// * `wpm` - Optional speed in words per minute.
+    ///   Defaults to 15 words per minute.
+    ///   Internally limited between 5 and 40 words per minute.
+    ///
+    /// ## Examples
+    ///
+    /// ```edition2018
+    /// use rust_rpi_4wd_car::Melody;
+    ///
+    /// // Three dots each followed by a gap then a word gap at the end.
+    /// let melody = Melody::from_morse("S", 20);
+    /// assert_eq!(melody.notes().len(), 7);
+    /// ```
+    pub fn from_morse<'a, T, W>(text: T, wpm: W) -> Self
+    where
+        T: Into<&'a str>,
+        W: Into<Option<u8>>,
+    {
+        let text = text.into();
+        let wpm = wpm.into().unwrap_or(15).clamp(5, 40);
+        let dot = 1200 / wpm as u64;
+        let mut notes: Vec<Note> = Vec::new();
+        for word in text.split_whitespace() {
+            if !notes.is_empty() {
+                // Stretch the letter gap into a word gap.
+                notes.push(Note::rest(dot * 4));
+            }
+            let mut first = true;
+            for code in word.chars().filter_map(Self::morse_code) {
+                if !first {
+                    // Stretch the symbol gap into a letter gap.
+                    notes.push(Note::rest(dot * 2));
+                }
+                first = false;
+                for symbol in code.chars() {
+                    let length = if symbol == '-' { dot * 3 } else { dot };
+                    notes.push(Note::new(Self::MORSE_FREQUENCY, length));
+                    notes.push(Note::rest(dot));
+                }
+            }
+        }
+        if !notes.is_empty() {
+            // Finish with a word gap so messages can be repeated back to back.
+            notes.push(Note::rest(dot * 6));
+        }
+        Self::new(text, notes)
+    }
+    /// Parses a ring tone text transfer language (RTTTL) string into a melody.
+    ///
+    /// RTTTL strings have three colon separated sections: a name, the defaults
+    /// for duration, octave, and beats per minute, and the comma separated
+    /// notes.
+    ///
+    /// ## Arguments
+    ///
+    /// * `rtttl` - RTTTL string like `"beep:d=4,o=5,b=120:c,p,8e,8g."`.
+    pub fn from_rtttl<'a, R: Into<&'a str>>(rtttl: R) -> Rr4cResult<Self> {
+        let rtttl = rtttl.into().trim();
+        let bad = || Rr4cError::BadRtttl(rtttl.to_string());
+        let mut sections = rtttl.splitn(3, ':');
+        let name = sections.next().ok_or_else(bad)?.trim();
+        let defaults = sections.next().ok_or_else(bad)?;
+        let notes = sections.next().ok_or_else(bad)?;
+        let mut duration = 4u32;
+        let mut octave = 6u32;
+        let mut bpm = 63u32;
+        for default in defaults.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut pair = default.splitn(2, '=');
+            let key = pair.next().ok_or_else(bad)?.trim();
+            let value: u32 = pair
+                .next()
+                .ok_or_else(bad)?
+                .trim()
+                .parse()
+                .map_err(|_| bad())?;
+            match key {
+                "d" if Self::DURATIONS.contains(&value) => duration = value,
+                "o" if value <= 8 => octave = value,
+                "b" if value > 0 => bpm = value,
+                _ => return Err(bad()),
+            }
+        }
+        // Length of a whole note in milliseconds.
+        let whole = 240_000.0 / bpm as f64;
+        let mut result = Vec::new();
+        for note in notes.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let note = note.to_ascii_lowercase();
+            let mut chars = note.chars().peekable();
+            let mut digits = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+                digits.push(c);
+            }
+            let note_duration = if digits.is_empty() {
+                duration
+            } else {
+                let value: u32 = digits.parse().map_err(|_| bad())?;
+                if !Self::DURATIONS.contains(&value) {
+                    return Err(bad());
+                }
+                value
+            };
+            let mut semitone: Option<i32> = match chars.next() {
+                Some('c') => Some(0),
+                Some('d') => Some(2),
+                Some('e') => Some(4),
+                Some('f') => Some(5),
+                Some('g') => Some(7),
+                Some('a') => Some(9),
+                Some('b') | Some('h') => Some(11),
+                Some('p') => None,
+                _ => return Err(bad()),
+            };
+            if chars.next_if_eq(&'#').is_some() {
+                semitone = semitone.map(|s| s + 1);
+            }
+            let mut dotted = chars.next_if_eq(&'.').is_some();
+            let note_octave = match chars.next_if(|c| c.is_ascii_digit()) {
+                Some(c) => c.to_digit(10).ok_or_else(bad)?,
+                None => octave,
+            };
+            dotted |= chars.next_if_eq(&'.').is_some();
+            if chars.next().is_some() {
+                return Err(bad());
+            }
+            let mut millis = whole / note_duration as f64;
+            if dotted {
+                millis *= 1.5;
+            }
+            let frequency = semitone.map_or(0.0, |s| {
+                // Semitones away from A4 (440 Hz).
+                let steps = s - 9 + (note_octave as i32 - 4) * 12;
+                440.0 * 2f64.powf(steps as f64 / 12.0)
+            });
+            result.push(Note::new(frequency, millis.round() as u64));
+        }
+        if result.is_empty() {
+            return Err(bad());
+        }
+        Ok(Self::new(name, result))
+    }
+    /// Jingle used to signal a change of mode.
+    pub fn mode_change() -> Self {
+        Self::from_rtttl(Self::MODE_CHANGE).expect("Built-in mode change jingle is broken")
+    }
+    /// Access the name of the melody.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Access the notes of the melody.
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+    /// Jingle used to signal the robot has started.
+    pub fn startup() -> Self {
+        Self::from_rtttl(Self::STARTUP).expect("Built-in startup jingle is broken")
+    }
+    /// Total time it takes to play the melody.
+    pub fn total_duration(&self) -> Duration {
+        self.notes.iter().map(|n| n.duration).sum()
+    }
+    /// Looks up the Morse code of a character as dots and dashes.
+    ///
+    /// ## Arguments
+    ///
+    /// * `c` - Character to look up.
+    fn morse_code(c: char) -> Option<&'static str> {
+        let code = match c.to_ascii_uppercase() {
+            'A' => ".-",
+            'B' => "-...",
+            'C' => "-.-.",
+            'D' => "-..",
+            'E' => ".",
+            'F' => "..-.",
+            'G' => "--.",
+            'H' => "....",
+            'I' => "..",
+            'J' => ".---",
+            'K' => "-.-",
+            'L' => ".-..",
+            'M' => "--",
+            'N' => "-.",
+            'O' => "---",
+            'P' => ".--.",
+            'Q' => "--.-",
+            'R' => ".-.",
+            'S' => "...",
+            'T' => "-",
+            'U' => "..-",
+            'V' => "...-",
+            'W' => ".--",
+            'X' => "-..-",
+            'Y' => "-.--",
+            'Z' => "--..",
+            '0' => "-----",
+            '1' => ".----",
+            '2' => "..---",
+            '3' => "...--",
+            '4' => "....-",
+            '5' => ".....",
+            '6' => "-....",
+            '7' => "--...",
+            '8' => "---..",
+            '9' => "----.",
+            '.' => ".-.-.-",
+            ',' => "--..--",
+            '?' => "..--..",
+            '\'' => ".----.",
+            '!' => "-.-.--",
+            '/' => "-..-.",
+            '(' => "-.--.",
+            ')' => "-.--.-",
+            '&' => ".-...",
+            ':' => "---...",
+            ';' => "-.-.-.",
+            '=' => "-...-",
+            '+' => ".-.-.",
+            '-' => "-....-",
+            '_' => "..--.-",
+            '"' => ".-..-.",
+            '@' => ".--.-.",
+            _ => return None,
+        };
+        Some(code)
+    }
+    /// RTTTL string for the built-in [`error()`] jingle.
+    ///
+    /// [`error()`]: Melody::error()
+    pub const ERROR: &'static str = "error:d=8,o=4,b=120:g,p,g,p,4c";
+    /// RTTTL string for the built-in [`mode_change()`] jingle.
+    ///
+    /// [`mode_change()`]: Melody::mode_change()
+    pub const MODE_CHANGE: &'static str = "mode:d=16,o=6,b=160:e,p,g";
+    /// RTTTL string for the built-in [`startup()`] jingle.
+    ///
+    /// [`startup()`]: Melody::startup()
+    pub const STARTUP: &'static str = "startup:d=16,o=5,b=140:c,e,g,8c6";
+    /// Frequency in Hz used for Morse code tones.
+    const MORSE_FREQUENCY: f64 = 700.0;
+    /// Note durations allowed by RTTTL.
+    const DURATIONS: [u32; 6] = [1, 2, 4, 8, 16, 32];
+}
@@ -0,0 +1,177 @@
+// Copyright © 2021-present, Michael Cummings
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// MIT License
+//
+// Copyright © 2021-present, Michael Cummings <mgcummings@yahoo.com>.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! Contains the robot status components used to give consistent light and
+//! sound feedback.
+
+use crate::{Melody, Note};
+
+/// Robot states that can be shown with the LEDs and buzzer.
+///
+/// Listed from lowest to highest priority.
+/// When more than one status is active only the highest priority one is shown.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Status {
+    /// Nothing happening and waiting for commands.
+    Idle,
+    /// Robot is still starting up.
+    Booting,
+    /// A remote control client is connected.
+    RemoteConnected,
+    /// An autonomous mode is running.
+    Autonomous,
+    /// An obstacle has been detected.
+    Obstacle,
+    /// Battery needs to be charged.
+    LowBattery,
+    /// Something has gone wrong.
+    Fault,
+    /// A watchdog timer has tripped because commands stopped arriving.
+    WatchdogTripped,
+}
+
+impl Status {
+    /// Light and sound pattern used to show the status.
+    pub fn pattern(&self) -> Pattern {
+        let tone = |millis| Note::new(Self::TONE, millis);
+        let rest = Note::rest;
+        let melody = |notes: Vec<Note>| Melody::new(format!("{:?}", self), notes);
+        match self {
+            Status::Idle => Pattern {
+                color: (0, 100, 0),
+                intro: melody(vec![tone(100)]),
+                sound: true,
+                repeat: None,
+            },
+            Status::Booting => Pattern {
+                color: (100, 100, 0),
+                intro: melody(vec![]),
+                sound: false,
+                repeat: Some(melody(vec![tone(250), rest(250)])),
+            },
+            Status::RemoteConnected => Pattern {
+                color: (0, 0, 100),
+                intro: melody(vec![tone(100), rest(100), tone(100)]),
+                sound: true,
+                repeat: None,
+            },
+            Status::Autonomous => Pattern {
+                color: (0, 100, 100),
+                intro: melody(vec![tone(300)]),
+                sound: false,
+                repeat: None,
+            },
+            Status::Obstacle => Pattern {
+                color: (100, 50, 0),
+                intro: melody(vec![tone(50), rest(50), tone(50), rest(50), tone(50)]),
+                sound: true,
+                repeat: None,
+            },
+            Status::LowBattery => Pattern {
+                color: (100, 100, 100),
+                intro: melody(vec![tone(100), rest(100), tone(100)]),
+                sound: true,
+                repeat: Some(melody(vec![tone(100), rest(100), tone(100), rest(2700)])),
+            },
+            Status::Fault => Pattern {
+                color: (100, 0, 0),
+                intro: melody(vec![tone(1000)]),
+                sound: true,
+                repeat: Some(melody(vec![tone(500), rest(1500)])),
+            },
+            Status::WatchdogTripped => Pattern {
+                color: (100, 0, 100),
+                intro: melody(vec![tone(100), rest(100), tone(100), rest(100), tone(100)]),
+                sound: true,
+                repeat: Some(melody(vec![tone(100), rest(100)])),
+            },
+        }
+    }
+    /// Bit used for the status in a set of active statuses.
+    pub(crate) fn bit(&self) -> u8 {
+        1 << *self as u8
+    }
+    /// All statuses from lowest to highest priority.
+    pub const ALL: [Status; 8] = [
+        Status::Idle,
+        Status::Booting,
+        Status::RemoteConnected,
+        Status::Autonomous,
+        Status::Obstacle,
+        Status::LowBattery,
+        Status::Fault,
+        Status::WatchdogTripped,
+    ];
+    /// Frequency in Hz used for status tones on a passive buzzer.
+    const TONE: f64 = 2000.0;
+}
+
+/// Light and sound pattern used to show a [Status](Status).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    /// RGB brightnesses as percentages from 0-100% used to light the LEDs
+    /// during each note.
+    pub color: (u8, u8, u8),
+    /// Played once when the status is first shown.
+    pub intro: Melody,
+    /// Sound the buzzer along with the LEDs during the `intro`.
+    pub sound: bool,
+    /// Optional light only pattern repeated after the `intro` for as long as
+    /// the status is shown.
+    pub repeat: Option<Melody>,
+}
+
+/// Set of active statuses with one bit per [Status](Status).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct StatusSet(u8);
+
+impl StatusSet {
+    /// Removes a status from the set.
+    pub fn clear(&mut self, status: Status) {
+        self.0 &= !status.bit();
+    }
+    /// Access the highest priority status in the set if any.
+    pub fn highest(&self) -> Option<Status> {
+        Status::ALL
+            .iter()
+            .rev()
+            .find(|s| self.0 & s.bit() != 0)
+            .copied()
+    }
+    /// Adds a status to the set.
+    pub fn set(&mut self, status: Status) {
+        self.0 |= status.bit();
+    }
+}
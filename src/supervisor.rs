@@ -0,0 +1,330 @@
+// Copyright © 2021-present, Michael Cummings
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// MIT License
+//
+// Copyright © 2021-present, Michael Cummings <mgcummings@yahoo.com>.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! Contains the supervisor used to run autonomous modes in the background and
+//! the shared robot subsystems they drive.
+
+use crate::{EmergencyStop, Hids, Mode, Motors, Result, Rr4cResult, Sensors, Servos, Status};
+use std::{
+    any::Any,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Why a mode run by a [Supervisor](Supervisor) stopped.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ExitReason {
+    /// The mode was stopped by a new mode being selected or
+    /// [`Supervisor::stop()`].
+    ///
+    /// [`Supervisor::stop()`]: Supervisor::stop()
+    Cancelled,
+    /// The mode finished by itself.
+    ///
+    /// Like a lost line not being found again, a light source being reached,
+    /// or the `KEY` button being pressed.
+    Finished,
+    /// The mode returned an error or panicked which is held as a message.
+    Failed(String),
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitReason::Cancelled => write!(f, "Cancelled"),
+            ExitReason::Finished => write!(f, "Finished"),
+            ExitReason::Failed(error) => write!(f, "Failed: {}", error),
+        }
+    }
+}
+
+/// All of the robot subsystems in one place so they can be shared between
+/// the [Decoder](crate::Decoder) and a mode running in the background.
+#[derive(Debug)]
+pub struct Robot {
+    /// Holds instance of `Hids` structure.
+    pub hids: Hids,
+    /// Holds an instance of `Motors` structure.
+    pub motors: Motors,
+    /// Holds an instance of `Sensors` structure.
+    pub sensors: Sensors,
+    /// Holds a instance of `Servos` structure.
+    pub servos: Servos,
+}
+
+impl Robot {
+    /// Constructor
+    ///
+    /// Servos are moved to their default positions.
+    /// The motors and sensors share a single
+    /// [EmergencyStop](EmergencyStop) latch.
+    pub fn new() -> Rr4cResult<Self> {
+        let mut servos = Servos::new()?;
+        servos.servos_init()?;
+        let estop = EmergencyStop::new();
+        let mut motors = Motors::new()?;
+        motors.set_emergency_stop(estop.clone());
+        let mut sensors = Sensors::new()?;
+        sensors.set_emergency_stop(estop);
+        Ok(Self {
+            hids: Hids::new()?,
+            motors,
+            sensors,
+            servos,
+        })
+    }
+    /// Engages the emergency stop latch, brakes, and shows a fault.
+    ///
+    /// Motors refuse to move until the latch is reset.
+    pub fn emergency_stop(&mut self) -> Result {
+        self.motors.emergency_stop().engage();
+        self.hids.set_status(Status::Fault);
+        self.motors.brake()
+    }
+}
+
+/// Runs a [Mode](Mode) on a background thread until it finishes or is
+/// cancelled.
+///
+/// Each step of the mode is given the shared target locked so anything else
+/// using it, like the [Decoder](crate::Decoder) handling remote commands, only
+/// waits for a single step.
+/// Once the mode exits its [`stop()`] is called and the
+/// [ExitReason](ExitReason) is kept so it can be reported.
+/// A mode that panics exits as failed and is still stopped so it can clean up,
+/// like braking the motors.
+/// Any running mode is cancelled when this is dropped.
+///
+/// ## Examples
+///
+/// ```edition2018
+/// use rust_rpi_4wd_car::{ExitReason, Mode, Result, Rr4cResult, Supervisor};
+/// use std::{
+///     sync::{Arc, Mutex},
+///     time::Instant,
+/// };
+///
+/// struct Count;
+///
+/// impl Mode<i32> for Count {
+///     fn step(&mut self, count: &mut i32, _: Instant) -> Rr4cResult<bool> {
+///         *count += 1;
+///         Ok(*count < 3)
+///     }
+///     fn stop(&mut self, count: &mut i32, _: &ExitReason) -> Result {
+///         *count = 0;
+///         Ok(())
+///     }
+/// }
+///
+/// let count = Arc::new(Mutex::new(0));
+/// let mut supervisor = Supervisor::new();
+/// supervisor.start("Count", count.clone(), Count);
+/// supervisor.wait();
+/// assert_eq!(supervisor.mode(), "Count");
+/// assert_eq!(supervisor.exit_reason(), Some(ExitReason::Finished));
+/// assert_eq!(*count.lock().unwrap(), 0);
+/// ```
+///
+/// [`stop()`]: Mode::stop()
+#[derive(Debug)]
+pub struct Supervisor {
+    /// Used to tell the background thread to stop.
+    cancel: Arc<AtomicBool>,
+    /// Why the latest mode exited.
+    exit: Arc<Mutex<Option<ExitReason>>>,
+    /// Background thread running the mode.
+    handle: Option<JoinHandle<()>>,
+    /// Name of the running or latest mode.
+    mode: String,
+}
+
+impl Supervisor {
+    /// Constructor
+    pub fn new() -> Self {
+        Self {
+            cancel: Arc::new(AtomicBool::new(false)),
+            exit: Arc::new(Mutex::new(None)),
+            handle: None,
+            mode: String::new(),
+        }
+    }
+    /// Access why the latest mode exited.
+    ///
+    /// `None` while the mode is still running or if no mode has been run.
+    pub fn exit_reason(&self) -> Option<ExitReason> {
+        self.exit.lock().expect("Someone broke the lock").clone()
+    }
+    /// Used to check if a mode is running.
+    pub fn is_running(&self) -> bool {
+        self.handle
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+    /// Access the name of the running or latest mode.
+    ///
+    /// Empty if no mode has been run.
+    pub fn mode(&self) -> &str {
+        &self.mode
+    }
+    /// Starts running a mode in the background.
+    ///
+    /// Any mode already running is stopped first.
+    /// The mode is stepped every 10 milliseconds.
+    ///
+    /// ## Arguments
+    ///
+    /// * `name` - Name of the mode used when reporting.
+    /// * `target` - Shared target, like a [Robot](Robot), the mode drives.
+    /// * `mode` - The [Mode](Mode) to run.
+    pub fn start<T, M>(&mut self, name: &str, target: Arc<Mutex<T>>, mut mode: M)
+    where
+        T: Send + 'static,
+        M: Mode<T> + 'static,
+    {
+        self.stop();
+        self.cancel = Arc::new(AtomicBool::new(false));
+        self.exit = Arc::new(Mutex::new(None));
+        self.mode = name.to_string();
+        let cancel = self.cancel.clone();
+        let exit = self.exit.clone();
+        let handle = thread::spawn(move || {
+            let started = Self::guarded(&target, |target| mode.start(target));
+            let mut reason = match started {
+                Ok(()) => loop {
+                    if cancel.load(Ordering::Acquire) {
+                        break ExitReason::Cancelled;
+                    }
+                    match Self::guarded(&target, |target| mode.step(target, Instant::now())) {
+                        Ok(true) => thread::park_timeout(Self::PERIOD),
+                        Ok(false) => break ExitReason::Finished,
+                        Err(e) => break e,
+                    }
+                },
+                Err(e) => e,
+            };
+            let stopped = Self::guarded(&target, |target| mode.stop(target, &reason));
+            if let Err(e) = stopped {
+                // Keep the first error.
+                if !matches!(reason, ExitReason::Failed(_)) {
+                    reason = e;
+                }
+            }
+            *exit.lock().expect("Someone broke the lock") = Some(reason);
+        });
+        self.handle = Some(handle);
+    }
+    /// Stops any running mode and waits for its stop function to finish.
+    ///
+    /// Returns why the latest mode exited if a mode has been run.
+    pub fn stop(&mut self) -> Option<ExitReason> {
+        self.cancel.store(true, Ordering::Release);
+        self.wait()
+    }
+    /// Waits for any running mode to finish by itself.
+    ///
+    /// Returns why the latest mode exited if a mode has been run.
+    pub fn wait(&mut self) -> Option<ExitReason> {
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+        self.exit_reason()
+    }
+    /// Calls part of a mode with the target locked turning any error or panic
+    /// into a failed [ExitReason](ExitReason).
+    ///
+    /// The panic is caught while the lock is still held so the target isn't
+    /// poisoned for everything else sharing it.
+    ///
+    /// ## Arguments
+    ///
+    /// * `target` - Shared target to lock.
+    /// * `call` - Part of the mode to call.
+    fn guarded<T, R, F>(target: &Mutex<T>, call: F) -> std::result::Result<R, ExitReason>
+    where
+        F: FnOnce(&mut T) -> Rr4cResult<R>,
+    {
+        let mut target = Self::lock(target);
+        match panic::catch_unwind(AssertUnwindSafe(|| call(&mut target))) {
+            Ok(result) => result.map_err(|e| ExitReason::Failed(e.to_string())),
+            Err(payload) => Err(ExitReason::Failed(Self::panic_message(payload))),
+        }
+    }
+    /// Locks the shared target even if something else panicked while holding
+    /// it.
+    ///
+    /// ## Arguments
+    ///
+    /// * `target` - Shared target to lock.
+    fn lock<T>(target: &Mutex<T>) -> MutexGuard<'_, T> {
+        target.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Makes a message from a caught panic.
+    ///
+    /// ## Arguments
+    ///
+    /// * `payload` - Payload of the panic.
+    fn panic_message(payload: Box<dyn Any + Send>) -> String {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown".to_string(),
+            },
+        };
+        format!("Mode panicked: {}", message)
+    }
+    /// Time between each step of a mode.
+    const PERIOD: Duration = Duration::from_millis(10);
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -36,5 +36,1444 @@
 //! All the tests
 
 mod control {
-    // todo
+    use crate::{
+        command::{FanCommand, RrCommand},
+        Decoder, Rr4cError,
+    };
+
+    #[test]
+    fn rr_command_uses_three_character_prefix() {
+        let cases = [
+            ("FANS50", RrCommand::Fan),
+            ("FANT", RrCommand::Fan),
+            ("CAMP90", RrCommand::Cam),
+            ("CAM", RrCommand::Cam),
+            ("FRT", RrCommand::Frt),
+            ("LED", RrCommand::Led),
+            ("MTRF", RrCommand::Mtr),
+            ("MTR", RrCommand::Mtr),
+            ("STOP", RrCommand::Stop),
+            ("STOP0", RrCommand::Stop),
+        ];
+        for (piece, command) in cases.iter() {
+            assert_eq!(Decoder::rr_command(piece).unwrap(), *command);
+        }
+        for bad in &["FAN", "XYZ1", "FA", ""] {
+            match Decoder::rr_command(bad) {
+                Err(Rr4cError::UnknownCommand(p)) => assert_eq!(p, *bad),
+                r => panic!("Expected UnknownCommand for '{}' but got {:?}", bad, r),
+            }
+        }
+    }
+    #[test]
+    fn fan_command_parses_speed() {
+        let cases = [
+            ("FANS50", FanCommand::Speed(Some(50))),
+            ("FANS", FanCommand::Speed(None)),
+            ("FANT", FanCommand::Toggle),
+            ("FAN0", FanCommand::Off),
+            ("FAN1", FanCommand::Blow),
+        ];
+        for (piece, command) in cases.iter() {
+            assert_eq!(Decoder::fan_command(piece).unwrap(), *command);
+        }
+        assert!(matches!(
+            Decoder::fan_command("FANSx"),
+            Err(Rr4cError::BadCommandValue(_))
+        ));
+    }
+    #[test]
+    fn stop_command_engages_or_resets() {
+        assert!(Decoder::stop_command("STOP").unwrap());
+        assert!(Decoder::stop_command("STOP1").unwrap());
+        assert!(!Decoder::stop_command("STOP0").unwrap());
+        for bad in &["STOPx", "STOP10", "STO"] {
+            match Decoder::stop_command(bad) {
+                Err(Rr4cError::BadCommandValue(p)) => assert_eq!(p, *bad),
+                r => panic!("Expected BadCommandValue for '{}' but got {:?}", bad, r),
+            }
+        }
+    }
+}
+
+mod sound {
+    use crate::{Melody, Rr4cError};
+    use std::time::Duration;
+
+    #[test]
+    fn rtttl_uses_defaults_and_overrides() {
+        let melody = Melody::from_rtttl("test:d=4,o=4,b=120:a,8a5,p,4c#.").unwrap();
+        let notes = melody.notes();
+        assert_eq!(melody.name(), "test");
+        assert_eq!(notes.len(), 4);
+        assert!((notes[0].frequency - 440.0).abs() < 0.01);
+        assert_eq!(notes[0].duration, Duration::from_millis(500));
+        assert!((notes[1].frequency - 880.0).abs() < 0.01);
+        assert_eq!(notes[1].duration, Duration::from_millis(250));
+        assert!(notes[2].is_rest());
+        assert!((notes[3].frequency - 277.18).abs() < 0.01);
+        assert_eq!(notes[3].duration, Duration::from_millis(750));
+    }
+    #[test]
+    fn rtttl_rejects_bad_strings() {
+        for bad in &["no sections", "x:d=3:c", "x:d=4:q", "x:d=4:", "x:o=5:c#x"] {
+            match Melody::from_rtttl(*bad) {
+                Err(Rr4cError::BadRtttl(_)) => {}
+                r => panic!("Expected BadRtttl for '{}' but got {:?}", bad, r),
+            }
+        }
+    }
+    #[test]
+    fn morse_uses_standard_timing() {
+        // 20 wpm gives a 60ms dot.
+        let melody = Melody::from_morse("et a", 20);
+        let millis: Vec<(bool, u128)> = melody
+            .notes()
+            .iter()
+            .map(|n| (n.is_rest(), n.duration.as_millis()))
+            .collect();
+        let expected = vec![
+            (false, 60),  // E
+            (true, 60),   // symbol gap
+            (true, 120),  // stretched to letter gap
+            (false, 180), // T
+            (true, 60),   // symbol gap
+            (true, 240),  // stretched to word gap
+            (false, 60),  // A dot
+            (true, 60),   // symbol gap
+            (false, 180), // A dash
+            (true, 60),   // symbol gap
+            (true, 360),  // stretched to final word gap
+        ];
+        assert_eq!(millis, expected);
+        assert!(Melody::from_morse("~~", None).notes().is_empty());
+    }
+    #[test]
+    fn built_in_jingles_parse() {
+        assert!(!Melody::startup().notes().is_empty());
+        assert!(!Melody::error().notes().is_empty());
+        assert!(!Melody::mode_change().notes().is_empty());
+    }
+}
+
+mod status {
+    use crate::{status::StatusSet, Status};
+
+    #[test]
+    fn priorities_follow_declaration_order() {
+        assert!(Status::WatchdogTripped > Status::Fault);
+        assert!(Status::Fault > Status::LowBattery);
+        assert!(Status::Obstacle > Status::Autonomous);
+        assert!(Status::Booting > Status::Idle);
+        let bits: u8 = Status::ALL.iter().fold(0, |bits, s| {
+            assert_eq!(bits & s.bit(), 0);
+            bits | s.bit()
+        });
+        assert_eq!(bits, u8::MAX);
+    }
+    #[test]
+    fn set_shows_highest_priority() {
+        let mut set = StatusSet::default();
+        assert_eq!(set.highest(), None);
+        set.set(Status::Autonomous);
+        set.set(Status::Idle);
+        assert_eq!(set.highest(), Some(Status::Autonomous));
+        set.set(Status::Fault);
+        assert_eq!(set.highest(), Some(Status::Fault));
+        set.set(Status::Obstacle);
+        assert_eq!(set.highest(), Some(Status::Fault));
+        set.clear(Status::Fault);
+        assert_eq!(set.highest(), Some(Status::Obstacle));
+        set.clear(Status::Obstacle);
+        set.clear(Status::Obstacle);
+        assert_eq!(set.highest(), Some(Status::Autonomous));
+        set.clear(Status::Autonomous);
+        set.clear(Status::Idle);
+        assert_eq!(set.highest(), None);
+    }
+    #[test]
+    fn colors_are_distinct() {
+        for (i, a) in Status::ALL.iter().enumerate() {
+            for b in &Status::ALL[i + 1..] {
+                assert_ne!(a.pattern().color, b.pattern().color, "{:?} {:?}", a, b);
+            }
+        }
+    }
+}
+
+mod estop {
+    use crate::{EmergencyStop, Rr4cError};
+
+    #[test]
+    fn check_refuses_motion_while_engaged() {
+        let estop = EmergencyStop::new();
+        let shared = estop.clone();
+        assert!(shared.check().is_ok());
+        estop.engage();
+        estop.engage();
+        assert!(matches!(shared.check(), Err(Rr4cError::EmergencyStop)));
+        shared.reset();
+        assert!(estop.check().is_ok());
+    }
+}
+
+mod health {
+    use crate::{
+        DigitalHealth, DigitalSensor, HealthMonitor, HealthWarning, SonarError, SonarStats,
+    };
+    use std::time::Duration;
+
+    fn digital(since: [u64; 6], transitions: [u64; 6]) -> [DigitalHealth; 6] {
+        let mut i = 0;
+        DigitalSensor::ALL.map(|sensor| {
+            let health = DigitalHealth {
+                sensor,
+                state: false,
+                since_edge: Duration::from_secs(since[i]),
+                raw_transitions: transitions[i],
+            };
+            i += 1;
+            health
+        })
+    }
+    #[test]
+    fn idle_robot_is_healthy() {
+        let mut monitor = HealthMonitor::new();
+        let report = monitor.check(
+            Duration::from_secs(100),
+            SonarStats::default(),
+            digital([100; 6], [0; 6]),
+        );
+        assert!(report.is_healthy());
+    }
+    #[test]
+    fn stuck_line_sensor() {
+        let mut monitor = HealthMonitor::new_with_thresholds(10.0, None, None);
+        let report = monitor.check(
+            Duration::from_secs(100),
+            SonarStats::default(),
+            digital([100, 100, 1, 2, 60, 1], [0; 6]),
+        );
+        assert_eq!(report.warnings.len(), 1);
+        assert!(matches!(
+            report.warnings[0],
+            HealthWarning::Stuck {
+                sensor: DigitalSensor::LineRight1,
+                ..
+            }
+        ));
+    }
+    #[test]
+    fn ir_sensors_are_independent() {
+        let mut monitor = HealthMonitor::new_with_thresholds(10.0, None, None);
+        let report = monitor.check(
+            Duration::from_secs(100),
+            SonarStats::default(),
+            digital([100, 1, 100, 100, 100, 100], [0; 6]),
+        );
+        assert!(report.is_healthy());
+    }
+    #[test]
+    fn stuck_active_ir_sensor() {
+        let mut monitor = HealthMonitor::new_with_thresholds(10.0, None, None);
+        let mut sensors = digital([100, 100, 100, 100, 100, 100], [0; 6]);
+        sensors[1].state = true;
+        let report = monitor.check(Duration::from_secs(100), SonarStats::default(), sensors);
+        assert_eq!(
+            report.warnings,
+            vec![HealthWarning::Stuck {
+                sensor: DigitalSensor::IrRight,
+                state: true,
+                since_edge: Duration::from_secs(100),
+            }]
+        );
+    }
+    #[test]
+    fn chattering_sensor() {
+        let mut monitor = HealthMonitor::new();
+        let at = Duration::from_secs;
+        monitor.check(at(1), SonarStats::default(), digital([0; 6], [0; 6]));
+        let report = monitor.check(
+            at(2),
+            SonarStats::default(),
+            digital([0; 6], [0, 500, 0, 0, 0, 0]),
+        );
+        assert_eq!(
+            report.warnings,
+            vec![HealthWarning::Chattering {
+                sensor: DigitalSensor::IrRight,
+                rate: 500.0
+            }]
+        );
+    }
+    #[test]
+    fn dead_sonar() {
+        let mut stats = SonarStats::default();
+        for _ in 0..10 {
+            stats.record(&Err(SonarError::Timeout));
+        }
+        assert_eq!(stats.timeouts, 10);
+        assert_eq!(stats.failure_rate(), 1.0);
+        let mut monitor = HealthMonitor::new();
+        let report = monitor.check(Duration::from_secs(1), stats, digital([0; 6], [0; 6]));
+        assert_eq!(
+            report.warnings,
+            vec![HealthWarning::SonarDead {
+                failures: 10,
+                last_error: Some(SonarError::Timeout)
+            }]
+        );
+        assert_eq!(
+            report.warnings[0].to_string(),
+            "Sonar failed 10 times in a row: Echo did not finish before timeout"
+        );
+    }
+}
+
+mod hids {
+    use crate::{hids::KeyHold, Hids, Status};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn key_hold_survives_busy_lock() {
+        let hold = Duration::from_secs(2);
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut key_hold = KeyHold::new(hold);
+        assert!(!key_hold.update(Some(true), at(0)));
+        // A melody or key_press() holding the lock isn't a release.
+        for millis in (20..2000).step_by(20) {
+            assert!(!key_hold.update(None, at(millis)));
+        }
+        assert!(key_hold.update(None, at(2000)));
+        // Only once per press.
+        assert!(!key_hold.update(Some(true), at(3000)));
+        assert!(!key_hold.update(None, at(3020)));
+        // A real release starts over.
+        assert!(!key_hold.update(Some(false), at(3040)));
+        assert!(!key_hold.update(None, at(6000)));
+        assert!(!key_hold.update(Some(true), at(6020)));
+        assert!(key_hold.update(Some(true), at(8020)));
+    }
+
+    #[test]
+    fn shown_status_repeat_survives_sounds() {
+        let fault = Status::Fault.pattern();
+        assert_eq!(
+            Hids::status_repeat(Some(Status::Fault)),
+            Some((fault.repeat.unwrap(), fault.color))
+        );
+        // Statuses without a repeating part have nothing to go back to.
+        assert_eq!(Hids::status_repeat(Some(Status::Idle)), None);
+        assert_eq!(Hids::status_repeat(None), None);
+    }
+}
+
+mod line {
+    use crate::{LinePosition, LineSide, LineState};
+
+    #[test]
+    fn error_follows_line() {
+        let mut position = LinePosition::new();
+        assert_eq!(position.update((true, false, false, false)).0, -1.0);
+        assert_eq!(position.update((false, true, true, false)).0, 0.0);
+        let (error, state) = position.update((false, false, true, true));
+        assert!((error - 2.0 / 3.0).abs() < 0.001);
+        assert_eq!(state, LineState::Following);
+        assert_eq!(position.last_side(), LineSide::Right);
+    }
+    #[test]
+    fn lost_holds_last_side() {
+        let mut position = LinePosition::new();
+        assert_eq!(
+            position.update((false, false, false, false)).1,
+            LineState::Lost
+        );
+        position.update((false, true, false, false));
+        assert_eq!(
+            position.update((false, false, false, false)),
+            (-1.0, LineState::Lost)
+        );
+        assert_eq!(position.last_side(), LineSide::Left);
+    }
+    #[test]
+    fn junctions() {
+        let mut position = LinePosition::new();
+        position.update((false, true, true, false));
+        assert_eq!(
+            position.update((true, true, true, true)).1,
+            LineState::Junction
+        );
+        assert_eq!(
+            position.update((false, true, true, false)).1,
+            LineState::Intersection
+        );
+        assert_eq!(
+            position.update((false, true, true, false)).1,
+            LineState::Following
+        );
+        position.update((true, true, true, true));
+        assert_eq!(
+            position.update((false, false, false, false)),
+            (0.0, LineState::TJunction(LineSide::Center))
+        );
+        assert_eq!(
+            position.update((false, false, false, false)).1,
+            LineState::Lost
+        );
+        assert_eq!(
+            position.update((true, true, true, false)).1,
+            LineState::TJunction(LineSide::Left)
+        );
+        // Crossing seen by only three sensors.
+        position.update((false, true, true, false));
+        assert_eq!(
+            position.update((true, false, true, true)).1,
+            LineState::Junction
+        );
+        assert_eq!(
+            position.update((false, true, true, false)).1,
+            LineState::Intersection
+        );
+        position.update((true, true, false, true));
+        assert_eq!(
+            position.update((false, false, false, false)).1,
+            LineState::TJunction(LineSide::Center)
+        );
+    }
+}
+
+mod modes {
+    use crate::{
+        AvoidState, IrFollower, LightSeeker, LightShow, LineFollower, ObstacleAvoider, SeekState,
+    };
+    use std::time::{Duration, Instant};
+
+    const CENTER: (bool, bool, bool, bool) = (false, true, true, false);
+    const NONE: (bool, bool, bool, bool) = (false, false, false, false);
+
+    #[test]
+    fn ir_follower_holds_distance_and_turns_to_object() {
+        let follower = IrFollower::new_with_kitchen_sink(40, 20.0, 60.0);
+        let none = (false, false);
+        assert_eq!(follower.step(none, None), (0, 0));
+        assert_eq!(follower.step(none, 80.0), (0, 0));
+        assert_eq!(follower.step(none, 50.0), (40, 40));
+        assert_eq!(follower.step(none, 30.0), (20, 20));
+        assert_eq!(follower.step(none, 21.0), (0, 0));
+        assert_eq!(follower.step(none, 10.0), (-20, -20));
+        assert_eq!(follower.step((true, false), 30.0), (-20, 20));
+        assert_eq!(follower.step((false, true), None), (20, -20));
+        assert_eq!(follower.step((true, true), None), (-20, -20));
+    }
+    #[test]
+    fn light_seeker_steers_searches_and_arrives() {
+        let mut seeker = LightSeeker::new_with_kitchen_sink(30, 15.0, 1.0);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let none = (false, false);
+        assert_eq!(seeker.step(none, none, None, at(0)), Some((30, -30)));
+        assert_eq!(seeker.state(), SeekState::Searching);
+        assert_eq!(
+            seeker.step((true, false), none, None, at(10)),
+            Some((10, 30))
+        );
+        assert_eq!(seeker.state(), SeekState::Steering);
+        // Spins back toward where the light was last seen.
+        assert_eq!(seeker.step(none, none, None, at(20)), Some((-30, 30)));
+        assert_eq!(
+            seeker.step((true, true), none, 40.0, at(30)),
+            Some((30, 30))
+        );
+        assert_eq!(seeker.step((true, true), none, 10.0, at(40)), None);
+        assert_eq!(seeker.state(), SeekState::Arrived);
+        assert_eq!(seeker.step((true, true), (false, true), None, at(50)), None);
+        // Gives up when no light is found.
+        let mut seeker = LightSeeker::new_with_kitchen_sink(30, 15.0, 1.0);
+        assert!(seeker.step(none, none, None, at(0)).is_some());
+        assert_eq!(seeker.step(none, none, None, at(1010)), None);
+        assert_eq!(seeker.state(), SeekState::Searching);
+    }
+    #[test]
+    fn light_show_cycles_palettes_and_reacts() {
+        let palettes = vec![vec![(100, 0, 0), (0, 0, 100)], vec![], vec![(0, 100, 0)]];
+        let mut show = LightShow::new_with_kitchen_sink(palettes, 0.5, 40.0);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(show.step(None, false, at(0)), (100, 0, 0));
+        assert_eq!(show.step(None, false, at(600)), (0, 0, 100));
+        assert_eq!(show.step(None, false, at(1100)), (100, 0, 0));
+        // Something close is red and something further away is green.
+        assert_eq!(show.step(5.0, false, at(1110)), (100, 25, 0));
+        assert_eq!(show.step(35.0, false, at(1120)), (25, 100, 0));
+        assert_eq!(show.step(45.0, false, at(1130)), (100, 0, 0));
+        // Held key only moves on one palette and the empty one was dropped.
+        assert_eq!(show.step(None, true, at(1140)), (0, 100, 0));
+        assert_eq!(show.step(None, true, at(1150)), (0, 100, 0));
+        assert_eq!(show.palette(), 1);
+        show.step(None, false, at(1160));
+        assert_eq!(show.step(None, true, at(1170)), (100, 0, 0));
+        assert_eq!(show.palette(), 0);
+    }
+    #[test]
+    fn line_follower_steers_toward_line() {
+        let mut follower = LineFollower::new_with_kitchen_sink(40, 1.0, 0.0, None);
+        let now = Instant::now();
+        assert_eq!(follower.step(CENTER, now), Some((40, 40)));
+        let (left, right) = follower.step((false, false, true, true), now).unwrap();
+        assert!(left > right);
+        let (left, right) = follower.step((true, false, false, false), now).unwrap();
+        assert_eq!((left, right), (0, 80));
+    }
+    #[test]
+    fn line_follower_pivots_into_corners() {
+        let mut follower = LineFollower::new_with_kitchen_sink(40, None, None, None);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        follower.step(CENTER, at(0));
+        // Branch to the right then the line ends so it was a corner.
+        follower.step((false, true, true, true), at(10));
+        assert_eq!(follower.step(NONE, at(20)), Some((40, -40)));
+        assert_eq!(follower.step(NONE, at(500)), Some((40, -40)));
+        assert_eq!(follower.step(CENTER, at(510)), Some((40, 40)));
+    }
+    #[test]
+    fn line_follower_searches_then_gives_up() {
+        let mut follower = LineFollower::new_with_kitchen_sink(40, None, None, 2.0);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        follower.step(CENTER, at(0));
+        // Creeps over a gap then sweeps left, right and left again.
+        assert_eq!(follower.step(NONE, at(10)), Some((20, 20)));
+        assert_eq!(follower.step(NONE, at(300)), Some((-40, 40)));
+        assert_eq!(follower.step(NONE, at(600)), Some((40, -40)));
+        assert_eq!(follower.step(NONE, at(1200)), Some((-40, 40)));
+        assert_eq!(follower.step(NONE, at(2020)), None);
+        follower.reset();
+        assert_eq!(follower.step(CENTER, at(2030)), Some((40, 40)));
+    }
+    #[test]
+    fn avoider_slows_then_scans_and_turns_to_clear_side() {
+        let mut avoider = ObstacleAvoider::new_with_kitchen_sink(40, 20.0, 50.0);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let none = (false, false);
+        assert_eq!(avoider.step(None, none, at(0)), (40, 40));
+        assert_eq!(avoider.step(35.0, none, at(10)), (20, 20));
+        assert_eq!(avoider.step(10.0, none, at(20)), (0, 0));
+        assert_eq!(avoider.state(), AvoidState::Scanning);
+        assert_eq!(avoider.look(), 160);
+        // Left is blocked while right is clear.
+        assert_eq!(avoider.step(15.0, none, at(330)), (0, 0));
+        assert_eq!(avoider.look(), 20);
+        assert_eq!(avoider.step(100.0, none, at(640)), (20, -20));
+        assert_eq!(avoider.state(), AvoidState::Turning);
+        assert_eq!(avoider.look(), 90);
+        assert_eq!(avoider.step(None, none, at(1050)), (40, 40));
+        assert_eq!(avoider.state(), AvoidState::Cruising);
+    }
+    #[test]
+    fn avoider_backs_out_of_dead_ends() {
+        let mut avoider = ObstacleAvoider::new_with_kitchen_sink(40, 20.0, 50.0);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let none = (false, false);
+        avoider.step(10.0, none, at(0));
+        avoider.step(10.0, none, at(310));
+        assert_eq!(avoider.step(12.0, none, at(620)), (-40, -40));
+        assert_eq!(avoider.state(), AvoidState::Reversing);
+        assert_eq!(avoider.step(None, none, at(1130)), (0, 0));
+        assert_eq!(avoider.state(), AvoidState::Scanning);
+        // Boxed in by both IR sensors also backs out.
+        let mut avoider = ObstacleAvoider::new_with_kitchen_sink(40, 20.0, 50.0);
+        assert_eq!(avoider.step(None, (true, true), at(0)), (-40, -40));
+    }
+}
+
+mod obstacle {
+    use crate::{Obstacle, ObstacleDetector};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn sonar_thresholds_with_hysteresis() {
+        let mut detector = ObstacleDetector::new_with_thresholds(20.0, 50.0, 5.0, None);
+        let now = Instant::now();
+        let none = (false, false);
+        assert_eq!(detector.update(None, none, now), Obstacle::Clear);
+        assert_eq!(detector.update(52.0, none, now), Obstacle::Clear);
+        assert_eq!(detector.update(45.0, none, now), Obstacle::FrontFar);
+        assert_eq!(detector.update(52.0, none, now), Obstacle::FrontFar);
+        assert_eq!(detector.update(19.0, none, now), Obstacle::FrontNear);
+        assert_eq!(detector.update(23.0, none, now), Obstacle::FrontNear);
+        assert_eq!(detector.update(26.0, none, now), Obstacle::FrontFar);
+        assert_eq!(detector.update(56.0, none, now), Obstacle::Clear);
+    }
+    #[test]
+    fn missed_echoes_keep_front_state() {
+        let mut detector = ObstacleDetector::new_with_thresholds(20.0, 50.0, 5.0, None);
+        let now = Instant::now();
+        let none = (false, false);
+        assert_eq!(detector.update(10.0, none, now), Obstacle::FrontNear);
+        assert_eq!(detector.update(None, none, now), Obstacle::FrontNear);
+        assert_eq!(detector.update(23.0, none, now), Obstacle::FrontNear);
+        assert_eq!(detector.update(None, none, now), Obstacle::FrontNear);
+        assert_eq!(detector.update(26.0, none, now), Obstacle::FrontFar);
+        assert_eq!(detector.update(None, none, now), Obstacle::FrontFar);
+        assert_eq!(detector.update(56.0, none, now), Obstacle::Clear);
+        assert_eq!(detector.update(None, none, now), Obstacle::Clear);
+    }
+    #[test]
+    fn ir_sides_are_held() {
+        let mut detector = ObstacleDetector::new();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(detector.update(None, (true, false), at(0)), Obstacle::Left);
+        assert_eq!(
+            detector.update(None, (false, false), at(50)),
+            Obstacle::Left
+        );
+        assert_eq!(
+            detector.update(None, (false, true), at(60)),
+            Obstacle::BoxedIn
+        );
+        assert_eq!(
+            detector.update(None, (false, false), at(150)),
+            Obstacle::Right
+        );
+        assert_eq!(
+            detector.update(None, (false, false), at(300)),
+            Obstacle::Clear
+        );
+    }
+    #[test]
+    fn front_near_beats_one_side() {
+        let mut detector = ObstacleDetector::new();
+        let now = Instant::now();
+        assert_eq!(
+            detector.update(10.0, (false, true), now),
+            Obstacle::FrontNear
+        );
+        assert_eq!(detector.update(40.0, (false, true), now), Obstacle::Right);
+        assert_eq!(detector.update(10.0, (true, true), now), Obstacle::BoxedIn);
+    }
+}
+
+mod range {
+    use crate::RangeTracker;
+    use std::time::Duration;
+
+    #[test]
+    fn closing_speed_and_time_to_collision() {
+        let mut tracker = RangeTracker::new_with_gains(0.8, 0.5);
+        let at = Duration::from_millis;
+        // Closing at 50cm/s.
+        let mut estimate = tracker.update(100.0, at(0));
+        assert_eq!(estimate.time_to_collision, None);
+        for i in 1..=10 {
+            estimate = tracker.update(100.0 - 5.0 * i as f32, at(100 * i));
+        }
+        assert!((estimate.distance - 50.0).abs() < 1.0);
+        assert!((estimate.closing_speed - 50.0).abs() < 2.0);
+        let ttc = estimate.time_to_collision.unwrap().as_secs_f32();
+        assert!((ttc - 1.0).abs() < 0.05);
+    }
+    #[test]
+    fn receding_has_no_time_to_collision() {
+        let mut tracker = RangeTracker::new();
+        let at = Duration::from_millis;
+        let mut estimate = tracker.update(50.0, at(0));
+        for i in 1..=10 {
+            estimate = tracker.update(50.0 + 3.0 * i as f32, at(100 * i));
+        }
+        assert!(estimate.closing_speed < 0.0);
+        assert_eq!(estimate.time_to_collision, None);
+    }
+    #[test]
+    fn restarts_after_gap() {
+        let mut tracker = RangeTracker::new();
+        tracker.update(100.0, Duration::from_millis(0));
+        tracker.update(90.0, Duration::from_millis(100));
+        let estimate = tracker.update(40.0, Duration::from_secs(5));
+        assert_eq!(estimate.distance, 40.0);
+        assert_eq!(estimate.closing_speed, 0.0);
+    }
+}
+
+mod sensors {
+    use crate::{
+        sensors::{FilterState, SensorEvents, SonarFilterState, SonarScheduler},
+        DigitalFilter, DigitalSensor, SensorSnapshot, SonarFilter,
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    fn snapshot() -> SensorSnapshot {
+        SensorSnapshot {
+            timestamp: Duration::from_millis(1500),
+            estop: false,
+            sonar: Some(42.7),
+            ir_left: true,
+            ir_right: false,
+            ldr_left: false,
+            ldr_right: true,
+            line_left1: false,
+            line_left2: true,
+            line_right1: true,
+            line_right2: false,
+        }
+    }
+    #[test]
+    fn snapshot_postbacks() {
+        let mut snapshot = snapshot();
+        assert_eq!(
+            snapshot.as_rr_postback(),
+            "$RR4W,SNR42,LNF0:1:1:0,IRP1:0,LDR0:1#"
+        );
+        assert_eq!(
+            snapshot.as_yb_postback(),
+            "$4WD,CSB42,PV8.3,GS0,LF0110,HW10,GM01#"
+        );
+        assert_eq!(
+            snapshot.as_rr_postback_with_estop(),
+            "$RR4W,SNR42,LNF0:1:1:0,IRP1:0,LDR0:1,STOP0#"
+        );
+        assert_eq!(
+            snapshot.as_yb_postback_with_estop(),
+            "$4WD,CSB42,PV8.3,GS0,LF0110,HW10,GM01,ES0#"
+        );
+        snapshot.estop = true;
+        // Field layout apps parse by position doesn't change.
+        assert!(snapshot.as_rr_postback().ends_with(",LDR0:1#"));
+        assert!(snapshot.as_yb_postback().ends_with(",GM01#"));
+        assert!(snapshot.as_rr_postback_with_estop().ends_with(",STOP1#"));
+        assert!(snapshot.as_yb_postback_with_estop().ends_with(",ES1#"));
+        snapshot.sonar = None;
+        assert!(snapshot.as_rr_postback().starts_with("$RR4W,SNR-1,"));
+        assert!(snapshot.as_yb_postback().starts_with("$4WD,CSB-1,"));
+    }
+    #[test]
+    fn snapshot_serde_round_trip() {
+        let snapshot = snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(json.contains("\"sonar\":42.7"));
+        assert_eq!(
+            serde_json::from_str::<SensorSnapshot>(&json).unwrap(),
+            snapshot
+        );
+    }
+    #[test]
+    fn snapshot_serde_defaults_estop() {
+        let mut snapshot = snapshot();
+        snapshot.estop = true;
+        let json = serde_json::to_string(&snapshot)
+            .unwrap()
+            .replace("\"estop\":true,", "");
+        assert!(!json.contains("estop"));
+        assert!(!serde_json::from_str::<SensorSnapshot>(&json).unwrap().estop);
+    }
+    #[test]
+    fn events_are_delivered_with_timestamps() {
+        let epoch = Instant::now();
+        let events = SensorEvents::new(epoch, Default::default());
+        let receiver = events.subscribe();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let callback_seen = seen.clone();
+        events.on_event(move |event| callback_seen.lock().unwrap().push(event));
+        events.update(DigitalSensor::IrLeft, true);
+        let elapsed = epoch.elapsed();
+        events.update(DigitalSensor::IrLeft, false);
+        let first = receiver.try_recv().unwrap();
+        let second = receiver.try_recv().unwrap();
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(first.sensor, DigitalSensor::IrLeft);
+        assert!(first.state);
+        assert!(first.timestamp <= elapsed);
+        assert!(!second.state);
+        assert!(second.timestamp >= first.timestamp);
+        assert_eq!(*seen.lock().unwrap(), vec![first, second]);
+    }
+    #[test]
+    fn events_skip_unchanged_levels() {
+        let events = SensorEvents::new(Instant::now(), Default::default());
+        let receiver = events.subscribe();
+        events.update(DigitalSensor::LineLeft1, false);
+        assert!(receiver.try_recv().is_err());
+        events.update(DigitalSensor::LineLeft1, true);
+        events.update(DigitalSensor::LineLeft1, true);
+        assert!(receiver.try_recv().is_ok());
+        assert!(receiver.try_recv().is_err());
+    }
+    #[test]
+    fn events_prune_dropped_receivers() {
+        let events = SensorEvents::new(Instant::now(), Default::default());
+        let kept = events.subscribe();
+        drop(events.subscribe());
+        events.update(DigitalSensor::LineRight2, true);
+        assert_eq!(events.subscribers.lock().unwrap().len(), 1);
+        assert!(kept.try_recv().is_ok());
+        events.clear_subscribers();
+        events.update(DigitalSensor::LineRight2, false);
+        assert!(events.subscribers.lock().unwrap().is_empty());
+        assert!(kept.try_recv().is_err());
+    }
+    #[test]
+    fn events_count_transitions() {
+        let events = SensorEvents::new(Instant::now(), Default::default());
+        for state in &[true, true, false, true, false, false] {
+            events.update(DigitalSensor::IrRight, *state);
+        }
+        assert_eq!(events.transitions(DigitalSensor::IrRight), 4);
+        assert_eq!(events.transitions(DigitalSensor::IrLeft), 0);
+    }
+    #[test]
+    fn filter_stable_for_waits_out_glitches() {
+        let filter = DigitalFilter {
+            stable_for: Some(Duration::from_millis(5)),
+            ..Default::default()
+        };
+        let mut state = FilterState::new(false);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(!state.apply(&filter, true, 1, at(0), false));
+        assert!(!state.apply(&filter, false, 2, at(2), false));
+        assert!(!state.apply(&filter, true, 3, at(3), false));
+        assert!(!state.apply(&filter, true, 3, at(7), false));
+        assert!(state.apply(&filter, true, 3, at(8), false));
+    }
+    #[test]
+    fn filter_majority_votes() {
+        let filter = DigitalFilter {
+            majority_of: Some(5),
+            ..Default::default()
+        };
+        let mut state = FilterState::new(false);
+        let now = Instant::now();
+        let mut current = false;
+        for (raw, expected) in &[
+            (true, false),
+            (false, false),
+            (true, false),
+            (true, true),
+            (false, true),
+        ] {
+            current = state.apply(&filter, *raw, 0, now, current);
+            assert_eq!(current, *expected);
+        }
+    }
+    #[test]
+    fn filter_rate_limit_holds_while_chattering() {
+        let filter = DigitalFilter {
+            rate_limit: Some((100.0, 20.0)),
+            ..Default::default()
+        };
+        let mut state = FilterState::new(false);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(state.apply(&filter, true, 0, at(0), false));
+        // 50 changes in 100ms is 500 per second.
+        assert!(state.apply(&filter, false, 50, at(100), true));
+        assert!(state.apply(&filter, false, 51, at(150), true));
+        // 5 changes in 100ms is 50 per second which is still above the low rate.
+        assert!(state.apply(&filter, false, 55, at(200), true));
+        // No changes so trusted again.
+        assert!(!state.apply(&filter, false, 55, at(300), true));
+    }
+    #[test]
+    fn sonar_unfiltered_passes_through() {
+        let mut state = SonarFilterState::default();
+        let now = Instant::now();
+        let reading = state.apply(&SonarFilter::default(), 42.0, now, now);
+        assert_eq!(reading.raw, 42.0);
+        assert_eq!(reading.filtered, 42.0);
+        assert_eq!(reading.confidence, 1.0);
+    }
+    #[test]
+    fn sonar_median_ignores_spike() {
+        let filter = SonarFilter {
+            median_of: Some(5),
+            ..Default::default()
+        };
+        let mut state = SonarFilterState::default();
+        let now = Instant::now();
+        let mut reading = None;
+        for raw in &[50.0, 51.0, 300.0, 49.0, 50.0] {
+            reading = Some(state.apply(&filter, *raw, now, now));
+        }
+        let reading = reading.unwrap();
+        assert_eq!(reading.filtered, 50.0);
+        assert!((reading.confidence - 0.8).abs() < 0.001);
+    }
+    #[test]
+    fn sonar_outliers_rejected_until_consistent() {
+        let filter = SonarFilter {
+            max_rate: Some(100.0),
+            ..Default::default()
+        };
+        let mut state = SonarFilterState::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(state.apply(&filter, 50.0, start, at(0)).filtered, 50.0);
+        // 150cm in 50ms is way too fast.
+        for ms in &[50, 100, 150] {
+            let reading = state.apply(&filter, 200.0, start, at(*ms));
+            assert_eq!(reading.raw, 200.0);
+            assert_eq!(reading.filtered, 50.0);
+            assert!(reading.confidence < 1.0);
+            assert_eq!(reading.timestamp, Duration::from_millis(*ms));
+        }
+        // Enough in a row that it is believed.
+        assert_eq!(state.apply(&filter, 200.0, start, at(200)).filtered, 200.0);
+    }
+    #[test]
+    fn sonar_ema_smooths() {
+        let filter = SonarFilter {
+            ema_alpha: Some(0.5),
+            ..Default::default()
+        };
+        let mut state = SonarFilterState::default();
+        let now = Instant::now();
+        assert_eq!(state.apply(&filter, 100.0, now, now).filtered, 100.0);
+        assert_eq!(state.apply(&filter, 50.0, now, now).filtered, 75.0);
+    }
+    #[test]
+    fn sonar_errors_are_distinct() {
+        use crate::{Rr4cError, SonarError};
+        assert_ne!(SonarError::NoEcho, SonarError::Timeout);
+        assert_eq!(
+            SonarError::OutOfRange(600.0).to_string(),
+            "Echo distance of 600cm is out of range"
+        );
+        let err: Rr4cError = SonarError::Stale(Duration::from_millis(150)).into();
+        assert!(matches!(err, Rr4cError::Sonar(SonarError::Stale(_))));
+    }
+    #[test]
+    fn measure_classifies_echoes() {
+        use crate::{
+            sensors::{Echo, EchoQueue, EchoTracker},
+            SonarError,
+        };
+        let wait = Duration::from_millis(5);
+        let stale = Duration::from_millis(100);
+        let epoch = Instant::now();
+        let sender = Arc::new(EchoQueue::new(8));
+        let mut tracker = EchoTracker::new(sender.clone());
+        // Nothing heard at all.
+        assert_eq!(
+            tracker.measure(epoch, Instant::now(), wait, stale),
+            Err(SonarError::NoEcho)
+        );
+        // Echo started but never finished.
+        sender.push(Echo::Started);
+        assert_eq!(
+            tracker.measure(epoch, Instant::now(), wait, stale),
+            Err(SonarError::Timeout)
+        );
+        // Only echo heard was too far away.
+        let start = Instant::now();
+        sender.push(Echo::OutOfRange(612.0, Instant::now()));
+        assert_eq!(
+            tracker.measure(epoch, start, wait, stale),
+            Err(SonarError::OutOfRange(612.0))
+        );
+        // Out of range echoes from before the measurement are ignored.
+        sender.push(Echo::OutOfRange(612.0, start));
+        assert_eq!(
+            tracker.measure(epoch, Instant::now(), wait, stale),
+            Err(SonarError::NoEcho)
+        );
+        // Good echo with the newest of several being used.
+        sender.push(Echo::Started);
+        sender.push(Echo::Measured(40.0, Instant::now()));
+        sender.push(Echo::Measured(42.0, Instant::now()));
+        let reading = tracker.measure(epoch, Instant::now(), wait, stale).unwrap();
+        assert_eq!(reading.raw, 42.0);
+        assert!(reading.age <= stale);
+        // Good echo that was queued too long ago.
+        sender.push(Echo::Measured(42.0, Instant::now()));
+        std::thread::sleep(Duration::from_millis(10));
+        match tracker.measure(epoch, Instant::now(), wait, Duration::from_millis(2)) {
+            Err(SonarError::Stale(age)) => assert!(age >= Duration::from_millis(10)),
+            r => panic!("Expected Stale but got {:?}", r),
+        }
+    }
+    #[test]
+    fn wait_times_follow_ping_period() {
+        use crate::Sonar;
+        let timeout = Duration::from_nanos(Sonar::ULTRASONIC_TIMEOUT);
+        assert_eq!(Sonar::wait_times(None), (timeout, Sonar::STALE_AGE));
+        // Fast pinging keeps the shortest stale age.
+        let fast = Sonar::wait_times(Some(Duration::from_millis(30)));
+        assert_eq!(fast, (Duration::from_millis(45), Sonar::STALE_AGE));
+        // 2Hz pinging waits 1.5 periods.
+        let slow = Duration::from_millis(750);
+        assert_eq!(
+            Sonar::wait_times(Some(Duration::from_millis(500))),
+            (slow, slow)
+        );
+        // 4 scheduled sonars at 30Hz are each pinged every 133ms.
+        let period = SonarScheduler::slot(4, 30.0) * 4;
+        let (timeout, stale_age) = Sonar::wait_times(Some(period));
+        assert!(stale_age > period && timeout > period);
+    }
+    #[test]
+    fn low_ping_rate_readings_are_not_stale() {
+        use crate::{
+            sensors::{Echo, EchoQueue, EchoTracker},
+            Sonar,
+        };
+        let epoch = Instant::now();
+        let queue = Arc::new(EchoQueue::new(8));
+        let mut tracker = EchoTracker::new(queue.clone());
+        // Echo from a 5Hz ping heard 150ms ago.
+        queue.push(Echo::Measured(42.0, Instant::now()));
+        std::thread::sleep(Duration::from_millis(150));
+        let (timeout, stale_age) = Sonar::wait_times(Some(Duration::from_millis(200)));
+        let reading = tracker.measure(epoch, Instant::now(), timeout, stale_age);
+        assert_eq!(reading.unwrap().raw, 42.0);
+        // Next echo arrives within the stretched timeout.
+        let sender = queue.clone();
+        let pinger = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            sender.push(Echo::Measured(43.0, Instant::now()));
+        });
+        let reading = tracker.measure(epoch, Instant::now(), timeout, stale_age);
+        pinger.join().unwrap();
+        assert_eq!(reading.unwrap().raw, 43.0);
+    }
+    #[test]
+    fn scheduler_pings_in_turn_until_dropped() {
+        let pinged = Arc::new(Mutex::new(Vec::new()));
+        let pings = (0..3)
+            .map(|sonar| {
+                let pinged = pinged.clone();
+                move || pinged.lock().unwrap().push((sonar, Instant::now()))
+            })
+            .collect();
+        // 3 sonars at 30Hz are each slowed to 10Hz to get a full 33ms slot.
+        let scheduler = SonarScheduler::new_with_pings(pings, 30.0);
+        std::thread::sleep(Duration::from_millis(230));
+        drop(scheduler);
+        let seen = pinged.lock().unwrap().clone();
+        assert!(seen.len() >= 5, "Only {} pings", seen.len());
+        let slot = SonarScheduler::slot(3, 30.0);
+        for (i, (sonar, _)) in seen.iter().enumerate() {
+            assert_eq!(*sonar, i % 3);
+        }
+        // Pings can be late from timer jitter but are never early.
+        let span = seen[seen.len() - 1].1 - seen[0].1;
+        assert!(span >= slot * (seen.len() as u32 - 1) - Duration::from_millis(2));
+        // Nothing more once dropped.
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(pinged.lock().unwrap().len(), seen.len());
+    }
+    #[test]
+    fn echo_queue_drops_oldest_when_full() {
+        use crate::sensors::{Echo, EchoQueue};
+        let queue = EchoQueue::new(3);
+        let start = Instant::now();
+        for distance in 1..=5 {
+            queue.push(Echo::Measured(distance as f32 * 10.0, start));
+        }
+        let mut kept = Vec::new();
+        while let Some(Echo::Measured(distance, _)) = queue.try_recv() {
+            kept.push(distance);
+        }
+        assert_eq!(kept, vec![30.0, 40.0, 50.0]);
+        let waited = Instant::now();
+        assert!(queue.recv_timeout(Duration::from_millis(5)).is_none());
+        assert!(waited.elapsed() >= Duration::from_millis(5));
+    }
+    #[test]
+    fn echo_queue_hands_off_between_threads() {
+        use crate::sensors::{Echo, EchoQueue};
+        let queue = Arc::new(EchoQueue::new(4));
+        let producer = queue.clone();
+        let start = Instant::now();
+        let handle = std::thread::spawn(move || {
+            for distance in 1..=10_000 {
+                let heard = start + Duration::from_micros(distance);
+                producer.push(Echo::Measured(distance as f32, heard));
+            }
+        });
+        let mut last = 0.0;
+        let mut received = 0;
+        while last < 10_000.0 {
+            match queue.recv_timeout(Duration::from_secs(1)) {
+                Some(Echo::Measured(distance, heard)) => {
+                    // Always newer and never torn between the two fields.
+                    assert!(distance > last);
+                    assert_eq!(heard - start, Duration::from_micros(distance as u64));
+                    last = distance;
+                    received += 1;
+                }
+                echo => panic!("Unexpected {:?}", echo),
+            }
+        }
+        handle.join().unwrap();
+        assert!(received > 0);
+        assert!(queue.try_recv().is_none());
+    }
+    #[test]
+    fn echo_edges_are_classified() {
+        use crate::sensors::Echo;
+        use rppal::gpio::Level;
+        let speed = 17_000.0;
+        let start = Instant::now();
+        let mut rising = None;
+        // Falling edge without a rising edge is ignored.
+        assert!(Echo::from_edge(Level::Low, start, &mut rising, speed).is_none());
+        assert!(matches!(
+            Echo::from_edge(Level::High, start, &mut rising, speed),
+            Some(Echo::Started)
+        ));
+        // 1ms out and back at 17000cm/s is 17cm.
+        let end = start + Duration::from_millis(1);
+        match Echo::from_edge(Level::Low, end, &mut rising, speed) {
+            Some(Echo::Measured(distance, at)) => {
+                assert!((distance - 17.0).abs() < 0.01);
+                assert_eq!(at, end);
+            }
+            r => panic!("Expected Measured but got {:?}", r),
+        }
+        assert!(rising.is_none());
+        // 40ms is 680cm which is too far to trust.
+        Echo::from_edge(Level::High, start, &mut rising, speed);
+        let end = start + Duration::from_millis(40);
+        assert!(matches!(
+            Echo::from_edge(Level::Low, end, &mut rising, speed),
+            Some(Echo::OutOfRange(d, _)) if (d - 680.0).abs() < 0.1
+        ));
+    }
+    #[test]
+    fn receive_tracks_pending_echoes() {
+        use crate::{
+            sensors::{Echo, EchoQueue, EchoTracker},
+            SonarError,
+        };
+        let epoch = Instant::now();
+        let mut tracker = EchoTracker::new(Arc::new(EchoQueue::new(8)));
+        let mut out_of_range = None;
+        let start = Instant::now();
+        tracker.receive(Echo::Started, start, &mut out_of_range);
+        tracker.receive(Echo::OutOfRange(1.0, start), start, &mut out_of_range);
+        assert_eq!(out_of_range, Some(1.0));
+        tracker.receive(Echo::Started, start, &mut out_of_range);
+        tracker.receive(Echo::Measured(25.0, start), start, &mut out_of_range);
+        // Queued reading is used without waiting on the queue.
+        let wait = Duration::from_secs(1);
+        let reading = tracker.measure(epoch, start, wait, wait).unwrap();
+        assert_eq!(reading.raw, 25.0);
+        // Nothing pending any more so no timeout.
+        assert_eq!(
+            tracker.measure(epoch, Instant::now(), Duration::from_millis(1), wait),
+            Err(SonarError::NoEcho)
+        );
+    }
+    #[test]
+    fn zero_timeout_measure_never_waits() {
+        use crate::sensors::{Echo, EchoQueue, EchoTracker};
+        let epoch = Instant::now();
+        let queue = Arc::new(EchoQueue::new(8));
+        let mut tracker = EchoTracker::new(queue.clone());
+        let stale_age = Duration::from_secs(1);
+        // Echo started but not finished yet.
+        queue.push(Echo::Started);
+        let start = Instant::now();
+        assert!(tracker
+            .measure(epoch, start, Duration::ZERO, stale_age)
+            .is_err());
+        assert!(start.elapsed() < Duration::from_millis(50));
+        queue.push(Echo::Measured(30.0, Instant::now()));
+        let reading = tracker
+            .measure(epoch, Instant::now(), Duration::ZERO, stale_age)
+            .unwrap();
+        assert_eq!(reading.raw, 30.0);
+    }
+    #[test]
+    fn temp_hum_are_clamped_and_change_speed() {
+        use crate::sensors::Ultrasonic;
+        use std::sync::atomic::Ordering;
+        let speed = |u: &Ultrasonic| f32::from_bits(u.speed_of_sound.load(Ordering::Relaxed));
+        let mut ultrasonic = Ultrasonic::new(None, None);
+        assert_eq!((ultrasonic.temperature, ultrasonic.humidity), (20.0, 40.0));
+        // (331.3 + 0.606 * 20 + 0.0124 * 40) * 50
+        assert!((speed(&ultrasonic) - 17_195.8).abs() < 0.1);
+        let cool = speed(&ultrasonic);
+        ultrasonic.set_temp_hum(35.0, None);
+        assert!(speed(&ultrasonic) > cool);
+        ultrasonic.set_temp_hum(100.0, 150.0);
+        assert_eq!((ultrasonic.temperature, ultrasonic.humidity), (65.5, 100.0));
+        ultrasonic.set_temp_hum(-60.0, -5.0);
+        assert_eq!((ultrasonic.temperature, ultrasonic.humidity), (-40.0, 0.0));
+        assert!(speed(&ultrasonic) < cool);
+    }
+    #[test]
+    fn environment_source_is_read_every_interval() {
+        use crate::{sensors::Ultrasonic, FixedEnvironment, Sonar};
+        let mut ultrasonic = Ultrasonic::new(None, None);
+        ultrasonic.environment = Some((
+            Box::new(FixedEnvironment {
+                temperature: Some(32.0),
+                humidity: None,
+            }),
+            None,
+        ));
+        let start = Instant::now();
+        ultrasonic.refresh_environment(start);
+        // Unknown humidity is left unchanged.
+        assert_eq!((ultrasonic.temperature, ultrasonic.humidity), (32.0, 40.0));
+        ultrasonic.set_temp_hum(20.0, 40.0);
+        ultrasonic.refresh_environment(start + Sonar::ENVIRONMENT_REFRESH / 2);
+        assert_eq!(ultrasonic.temperature, 20.0);
+        ultrasonic.refresh_environment(start + Sonar::ENVIRONMENT_REFRESH);
+        assert_eq!(ultrasonic.temperature, 32.0);
+    }
+    #[test]
+    fn environment_is_shared_by_two_sonars() {
+        use crate::{
+            sensors::{SharedEnvironment, Ultrasonic},
+            EnvironmentSource,
+        };
+        #[derive(Debug)]
+        struct Thermometer(u32);
+        impl EnvironmentSource for Thermometer {
+            fn temperature(&mut self) -> Option<f32> {
+                self.0 += 1;
+                Some(30.0 + self.0 as f32)
+            }
+        }
+        let shared = SharedEnvironment::new(Box::new(Thermometer(0)));
+        let mut front = Ultrasonic::new(None, None);
+        let mut rear = Ultrasonic::new(None, None);
+        front.environment = Some((Box::new(shared.clone()), None));
+        rear.environment = Some((Box::new(shared), None));
+        let now = Instant::now();
+        front.refresh_environment(now);
+        rear.refresh_environment(now);
+        // Both sonars read the same thermometer.
+        assert_eq!(front.temperature, 31.0);
+        assert_eq!(rear.temperature, 32.0);
+    }
+    #[test]
+    fn environment_source_defaults() {
+        use crate::{EnvironmentSource, FixedEnvironment};
+        #[derive(Debug)]
+        struct Thermometer;
+        impl EnvironmentSource for Thermometer {
+            fn temperature(&mut self) -> Option<f32> {
+                Some(31.5)
+            }
+        }
+        let mut thermometer = Thermometer;
+        assert_eq!(thermometer.temperature(), Some(31.5));
+        assert_eq!(thermometer.humidity(), None);
+        let mut weather = FixedEnvironment {
+            humidity: Some(80.0),
+            ..Default::default()
+        };
+        assert_eq!(weather.temperature(), None);
+        assert_eq!(weather.humidity(), Some(80.0));
+    }
+    #[test]
+    fn sonar_scheduler_slots() {
+        // One sonar at 10Hz gets the whole period.
+        assert_eq!(SonarScheduler::slot(1, 10.0), Duration::from_millis(100));
+        // Two sonars at 10Hz split it.
+        assert_eq!(SonarScheduler::slot(2, 10.0), Duration::from_millis(50));
+        // Never shorter than an echo can take.
+        let min = Duration::from_secs_f64(1.0 / 30.0);
+        assert_eq!(SonarScheduler::slot(4, 30.0), min);
+    }
+    #[test]
+    fn polarity_levels() {
+        use crate::Polarity;
+        use rppal::gpio::Level;
+        assert!(Polarity::ActiveLow.is_active(Level::Low));
+        assert!(!Polarity::ActiveLow.is_active(Level::High));
+        assert!(Polarity::ActiveHigh.is_active(Level::High));
+    }
+    #[test]
+    fn polarity_config_uses_defaults_for_missing() {
+        use crate::{DigitalSensor, Polarity, SensorPolarity};
+        let polarity: SensorPolarity =
+            serde_json::from_str(r#"{"line_left1":"ActiveHigh"}"#).unwrap();
+        assert_eq!(polarity.of(DigitalSensor::LineLeft1), Polarity::ActiveHigh);
+        assert_eq!(polarity.of(DigitalSensor::LineLeft2), Polarity::ActiveLow);
+        assert_eq!(polarity.ldr_left, Polarity::ActiveHigh);
+    }
+}
+
+mod supervisor {
+    use crate::{
+        command::Latched, EmergencyStop, ExitReason, Mode, Result, Rr4cError, Rr4cResult,
+        Supervisor,
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    /// Records what it was asked to do and fails when told to.
+    struct Recorder {
+        fail_start: bool,
+        fail_step: bool,
+    }
+
+    impl Mode<Vec<String>> for Recorder {
+        fn start(&mut self, log: &mut Vec<String>) -> Result {
+            log.push("start".to_string());
+            if self.fail_start {
+                return Err(Rr4cError::BadCommand("start".to_string()));
+            }
+            Ok(())
+        }
+        fn step(&mut self, log: &mut Vec<String>, _: Instant) -> Rr4cResult<bool> {
+            log.push("step".to_string());
+            if self.fail_step {
+                return Err(Rr4cError::UnknownCommand("XYZ".to_string()));
+            }
+            Ok(true)
+        }
+        fn stop(&mut self, log: &mut Vec<String>, reason: &ExitReason) -> Result {
+            log.push(format!("stop {}", reason));
+            Ok(())
+        }
+    }
+
+    /// Panics on its first step.
+    struct Panicker;
+
+    impl Mode<Vec<String>> for Panicker {
+        fn step(&mut self, _: &mut Vec<String>, _: Instant) -> Rr4cResult<bool> {
+            panic!("boom");
+        }
+        fn stop(&mut self, log: &mut Vec<String>, reason: &ExitReason) -> Result {
+            log.push(format!("stop {}", reason));
+            Ok(())
+        }
+    }
+
+    fn recorder() -> Recorder {
+        Recorder {
+            fail_start: false,
+            fail_step: false,
+        }
+    }
+
+    #[test]
+    fn cancelled_mode_is_stopped_and_reported() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut supervisor = Supervisor::new();
+        assert!(!supervisor.is_running());
+        assert_eq!(supervisor.exit_reason(), None);
+        supervisor.start("Forever", log.clone(), recorder());
+        sleep(Duration::from_millis(30));
+        assert!(supervisor.is_running());
+        assert_eq!(supervisor.mode(), "Forever");
+        assert_eq!(supervisor.stop(), Some(ExitReason::Cancelled));
+        assert!(!supervisor.is_running());
+        let log = log.lock().unwrap();
+        assert!(log.len() > 3);
+        assert_eq!(log.first().map(String::as_str), Some("start"));
+        assert_eq!(log.last().map(String::as_str), Some("stop Cancelled"));
+    }
+    #[test]
+    fn target_is_free_between_steps() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut supervisor = Supervisor::new();
+        supervisor.start("Forever", log.clone(), recorder());
+        // Like the decoder handling frames while a mode runs.
+        for _ in 0..5 {
+            let start = Instant::now();
+            let steps = log.lock().unwrap().len();
+            assert!(start.elapsed() < Duration::from_millis(100));
+            sleep(Duration::from_millis(20));
+            assert!(log.lock().unwrap().len() > steps);
+        }
+        let start = Instant::now();
+        assert_eq!(supervisor.stop(), Some(ExitReason::Cancelled));
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+    #[test]
+    fn starting_a_mode_replaces_the_running_one() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut supervisor = Supervisor::new();
+        supervisor.start("First", log.clone(), recorder());
+        supervisor.start("Second", log.clone(), Box::new(recorder()));
+        assert_eq!(supervisor.mode(), "Second");
+        supervisor.stop();
+        let log = log.lock().unwrap();
+        let stops: Vec<_> = log.iter().filter(|l| l.starts_with("stop")).collect();
+        assert_eq!(stops, ["stop Cancelled", "stop Cancelled"]);
+    }
+    #[test]
+    fn failed_mode_keeps_error() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut supervisor = Supervisor::new();
+        let mode = Recorder {
+            fail_start: false,
+            fail_step: true,
+        };
+        supervisor.start("Broken", log.clone(), mode);
+        let reason = supervisor.wait().unwrap();
+        assert_eq!(
+            reason,
+            ExitReason::Failed(Rr4cError::UnknownCommand("XYZ".to_string()).to_string())
+        );
+        // Failing to start skips straight to stopping.
+        let mode = Recorder {
+            fail_start: true,
+            fail_step: false,
+        };
+        log.lock().unwrap().clear();
+        supervisor.start("Broken", log.clone(), mode);
+        assert!(matches!(supervisor.wait(), Some(ExitReason::Failed(_))));
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log[1].starts_with("stop Failed"));
+    }
+    #[test]
+    fn engaged_latch_aborts_running_mode() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let estop = EmergencyStop::new();
+        let mut supervisor = Supervisor::new();
+        supervisor.start("Latched", log.clone(), Latched(estop.clone(), recorder()));
+        sleep(Duration::from_millis(30));
+        assert!(supervisor.is_running());
+        estop.engage();
+        let reason = ExitReason::Failed(Rr4cError::EmergencyStop.to_string());
+        assert_eq!(supervisor.wait(), Some(reason.clone()));
+        let log = log.lock().unwrap();
+        assert_eq!(log.last(), Some(&format!("stop {}", reason)));
+    }
+    #[test]
+    fn panicking_mode_fails_and_is_stopped() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut supervisor = Supervisor::new();
+        supervisor.start("Panicky", log.clone(), Panicker);
+        let reason = ExitReason::Failed("Mode panicked: boom".to_string());
+        assert_eq!(supervisor.wait(), Some(reason));
+        assert!(!log.is_poisoned());
+        assert_eq!(*log.lock().unwrap(), ["stop Failed: Mode panicked: boom"]);
+    }
+    #[test]
+    fn poisoned_target_is_recovered() {
+        let log = Arc::new(Mutex::new(Vec::<String>::new()));
+        let poison = log.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poison.lock().unwrap();
+            panic!("poison");
+        })
+        .join();
+        assert!(log.is_poisoned());
+        let mut supervisor = Supervisor::new();
+        supervisor.start("Recorder", log.clone(), recorder());
+        sleep(Duration::from_millis(30));
+        assert_eq!(supervisor.stop(), Some(ExitReason::Cancelled));
+        let log = log.lock().unwrap_or_else(|e| e.into_inner());
+        assert_eq!(log.last().map(String::as_str), Some("stop Cancelled"));
+    }
 }